@@ -1,7 +1,228 @@
 #[macro_use]
 extern crate criterion;
 
-use criterion::Criterion;
+use criterion::{black_box, Criterion};
+use rand::prelude::*;
+use std::sync::Arc;
+
+use thruster::acceleration::bvh::{BVHConstructionAlgorithm, BVHLinearTree};
+use thruster::acceleration::kdtree::{KdTree, KdTreeConfig};
+use thruster::acceleration::queue_systems::FastStack;
+use thruster::acceleration::wide_bvh::{BVHWidth, WideBVH};
+use thruster::algebra::prelude::*;
+use thruster::algebra::simd::Lanes;
+use thruster::core::material::Matte;
+use thruster::core::medium::{HomogeneousMedium, MediumInterface};
+use thruster::core::primitive::{GeometricPrimitive, Primitive};
+use thruster::core::spectrum::RGBSpectrum;
+use thruster::core::texture::ConstantTexture;
+use thruster::geometry::shape::Shape;
+use thruster::geometry::triangle::Triangle;
+use thruster::parser;
+
+fn teapot() -> BVHLinearTree {
+    let object = parser::parse("objs/teapot.obj".to_string()).expect("could not load teapot");
+    let medium = HomogeneousMedium::default();
+    let primitives: Vec<Arc<dyn Primitive + Sync + Send>> = object
+        .tris
+        .into_iter()
+        .map(|(a, b, c)| {
+            let primitive: Arc<dyn Primitive + Sync + Send> = Arc::new(GeometricPrimitive {
+                emission: RGBSpectrum::BLACK,
+                material: Arc::new(Matte::lambertian(Arc::new(ConstantTexture::new(
+                    RGBSpectrum::from_rgb(255.0, 255.0, 255.0),
+                )))),
+                shape: Arc::new(Triangle::new(a, b, c)),
+                medium_interface: MediumInterface {
+                    inside: Box::new(medium.clone()),
+                    outside: Box::new(medium.clone()),
+                },
+            });
+            primitive
+        })
+        .collect();
+    BVHLinearTree::build(BVHConstructionAlgorithm::SAH, primitives)
+}
+
+/// Rays from all around the teapot, aimed at random points inside its bounds
+fn rays(bounds: &BoundingBox, amount: usize) -> Vec<Ray> {
+    let mut rng = StdRng::seed_from_u64(0);
+    let centre = bounds.centre();
+    let radius = bounds.diagonal().length() * 2.0;
+    let random_in_bounds = |rng: &mut StdRng| {
+        let d = bounds.diagonal();
+        Point3::new(
+            bounds.min.x + rng.gen::<Float>() * d.x,
+            bounds.min.y + rng.gen::<Float>() * d.y,
+            bounds.min.z + rng.gen::<Float>() * d.z,
+        )
+    };
+    (0..amount)
+        .map(|_| {
+            let direction = Vec3::new(
+                rng.gen::<Float>() - 0.5,
+                rng.gen::<Float>() - 0.5,
+                rng.gen::<Float>() - 0.5,
+            )
+            .normalized();
+            let origin = centre + direction * radius;
+            let target = random_in_bounds(&mut rng);
+            Ray::new(origin, target - origin)
+        })
+        .collect()
+}
+
+/// Pinhole camera rays looking at the teapot, ordered in 8x8 tiles so that every 64 rays form a
+/// coherent packet
+fn camera_rays(bounds: &BoundingBox, resolution: usize) -> Vec<Ray> {
+    let centre = bounds.centre();
+    let extent = bounds.diagonal().length();
+    let origin = centre - Vec3::new(0.0, 0.0, extent * 2.0);
+    let mut rays = Vec::with_capacity(resolution * resolution);
+    for tile_y in (0..resolution).step_by(8) {
+        for tile_x in (0..resolution).step_by(8) {
+            for y in tile_y..tile_y + 8 {
+                for x in tile_x..tile_x + 8 {
+                    let u = (x as Float + 0.5) / resolution as Float - 0.5;
+                    let v = (y as Float + 0.5) / resolution as Float - 0.5;
+                    let target = centre + Vec3::new(u * extent, v * extent, 0.0);
+                    rays.push(Ray::new(origin, target - origin));
+                }
+            }
+        }
+    }
+    rays
+}
+
+/// The traversal as it was before the precomputed slab test and closest hit pruning, to compare
+/// against
+fn reference_intersect(tree: &BVHLinearTree, ray: &Ray) -> Option<Float> {
+    let mut current_task = 0;
+    let mut queue = FastStack::new();
+    let mut closest: Option<Float> = None;
+    loop {
+        let n = &tree.linear_nodes[current_task];
+        if n.bounding_box.does_intersect(ray) {
+            if n.primitive_amount > 0 {
+                for i in 0..n.primitive_amount {
+                    if let Some(geom) = tree.primitives[i + n.node_content].intersect(ray) {
+                        closest = Some(closest.map_or(geom.t, |t: Float| t.min(geom.t)));
+                    }
+                }
+                match queue.pop() {
+                    None => return closest,
+                    Some(task) => current_task = task,
+                };
+            } else if ray.direction[n.axis] < 0.0 {
+                queue.push(current_task + 1);
+                current_task = n.node_content;
+            } else {
+                queue.push(n.node_content);
+                current_task += 1;
+            }
+        } else {
+            match queue.pop() {
+                None => return closest,
+                Some(task) => current_task = task,
+            };
+        }
+    }
+}
+
+/// The scalar inverse from before the SIMD version, to compare against
+fn reference_inverse(mat: &Mat4x4) -> Mat4x4 {
+    let m = &mat.m;
+    let mut i = Mat4x4::EMPTY;
+    i.m[0] = m[5] * m[10] * m[15] - m[5] * m[11] * m[14] - m[9] * m[6] * m[15]
+        + m[9] * m[7] * m[14]
+        + m[13] * m[6] * m[11]
+        - m[13] * m[7] * m[10];
+
+    i.m[4] = -m[4] * m[10] * m[15] + m[4] * m[11] * m[14] + m[8] * m[6] * m[15]
+        - m[8] * m[7] * m[14]
+        - m[12] * m[6] * m[11]
+        + m[12] * m[7] * m[10];
+
+    i.m[8] = m[4] * m[9] * m[15] - m[4] * m[11] * m[13] - m[8] * m[5] * m[15]
+        + m[8] * m[7] * m[13]
+        + m[12] * m[5] * m[11]
+        - m[12] * m[7] * m[9];
+
+    i.m[12] = -m[4] * m[9] * m[14] + m[4] * m[10] * m[13] + m[8] * m[5] * m[14]
+        - m[8] * m[6] * m[13]
+        - m[12] * m[5] * m[10]
+        + m[12] * m[6] * m[9];
+
+    i.m[1] = -m[1] * m[10] * m[15] + m[1] * m[11] * m[14] + m[9] * m[2] * m[15]
+        - m[9] * m[3] * m[14]
+        - m[13] * m[2] * m[11]
+        + m[13] * m[3] * m[10];
+
+    i.m[5] = m[0] * m[10] * m[15] - m[0] * m[11] * m[14] - m[8] * m[2] * m[15]
+        + m[8] * m[3] * m[14]
+        + m[12] * m[2] * m[11]
+        - m[12] * m[3] * m[10];
+
+    i.m[9] = -m[0] * m[9] * m[15] + m[0] * m[11] * m[13] + m[8] * m[1] * m[15]
+        - m[8] * m[3] * m[13]
+        - m[12] * m[1] * m[11]
+        + m[12] * m[3] * m[9];
+
+    i.m[13] = m[0] * m[9] * m[14] - m[0] * m[10] * m[13] - m[8] * m[1] * m[14]
+        + m[8] * m[2] * m[13]
+        + m[12] * m[1] * m[10]
+        - m[12] * m[2] * m[9];
+
+    i.m[2] = m[1] * m[6] * m[15] - m[1] * m[7] * m[14] - m[5] * m[2] * m[15]
+        + m[5] * m[3] * m[14]
+        + m[13] * m[2] * m[7]
+        - m[13] * m[3] * m[6];
+
+    i.m[6] = -m[0] * m[6] * m[15] + m[0] * m[7] * m[14] + m[4] * m[2] * m[15]
+        - m[4] * m[3] * m[14]
+        - m[12] * m[2] * m[7]
+        + m[12] * m[3] * m[6];
+
+    i.m[10] = m[0] * m[5] * m[15] - m[0] * m[7] * m[13] - m[4] * m[1] * m[15]
+        + m[4] * m[3] * m[13]
+        + m[12] * m[1] * m[7]
+        - m[12] * m[3] * m[5];
+
+    i.m[14] = -m[0] * m[5] * m[14] + m[0] * m[6] * m[13] + m[4] * m[1] * m[14]
+        - m[4] * m[2] * m[13]
+        - m[12] * m[1] * m[6]
+        + m[12] * m[2] * m[5];
+
+    i.m[3] = -m[1] * m[6] * m[11] + m[1] * m[7] * m[10] + m[5] * m[2] * m[11]
+        - m[5] * m[3] * m[10]
+        - m[9] * m[2] * m[7]
+        + m[9] * m[3] * m[6];
+
+    i.m[7] = m[0] * m[6] * m[11] - m[0] * m[7] * m[10] - m[4] * m[2] * m[11]
+        + m[4] * m[3] * m[10]
+        + m[8] * m[2] * m[7]
+        - m[8] * m[3] * m[6];
+
+    i.m[11] = -m[0] * m[5] * m[11] + m[0] * m[7] * m[9] + m[4] * m[1] * m[11]
+        - m[4] * m[3] * m[9]
+        - m[8] * m[1] * m[7]
+        + m[8] * m[3] * m[5];
+
+    i.m[15] = m[0] * m[5] * m[10] - m[0] * m[6] * m[9] - m[4] * m[1] * m[10]
+        + m[4] * m[2] * m[9]
+        + m[8] * m[1] * m[6]
+        - m[8] * m[2] * m[5];
+
+    let mut det = m[0] * i.m[0] + m[1] * i.m[4] + m[2] * i.m[8] + m[3] * i.m[12];
+    assert!(det != 0.0);
+    det = 1.0 / det;
+
+    for x in &mut i.m {
+        *x *= det;
+    }
+    i
+}
+
 fn criterion_benchmark(c: &mut Criterion) {
     let mut group = c.benchmark_group("Screenshot Renders");
 
@@ -10,5 +231,150 @@ fn criterion_benchmark(c: &mut Criterion) {
     group.bench_function("Basic Scene Screenshot 1080p", |b| {});
 }
 
-criterion_group!(benches, criterion_benchmark);
+fn bvh_benchmark(c: &mut Criterion) {
+    let tree = teapot();
+    let rays = rays(&tree.bounds, 10_000);
+
+    let mut group = c.benchmark_group("BVH Traversal Teapot");
+
+    group.bench_function("Reference slab test", |b| {
+        b.iter(|| {
+            rays.iter()
+                .filter_map(|ray| reference_intersect(&tree, ray))
+                .count()
+        })
+    });
+
+    group.bench_function("Precomputed slab test", |b| {
+        b.iter(|| rays.iter().filter_map(|ray| tree.intersect(ray)).count())
+    });
+
+    group.bench_function("Precomputed slab test, any hit", |b| {
+        b.iter(|| rays.iter().filter(|ray| tree.does_intersect(ray)).count())
+    });
+
+    let camera_rays = camera_rays(&tree.bounds, 128);
+
+    group.bench_function("Camera rays, one at a time", |b| {
+        b.iter(|| {
+            camera_rays
+                .iter()
+                .filter_map(|ray| tree.intersect(ray))
+                .count()
+        })
+    });
+
+    group.bench_function("Camera rays, packets of 8x8", |b| {
+        b.iter(|| {
+            camera_rays
+                .chunks(64)
+                .map(|packet| tree.intersect_packet(packet).into_iter().flatten().count())
+                .sum::<usize>()
+        })
+    });
+
+    group.bench_function("Sorted stream", |b| {
+        b.iter(|| tree.intersect_stream(&rays).into_iter().flatten().count())
+    });
+
+    let bvh4 = WideBVH::from_tree(&tree, BVHWidth::BVH4);
+    group.bench_function("BVH4", |b| {
+        b.iter(|| rays.iter().filter_map(|ray| bvh4.intersect(ray)).count())
+    });
+
+    let bvh8 = WideBVH::from_tree(&tree, BVHWidth::BVH8);
+    group.bench_function("BVH8", |b| {
+        b.iter(|| rays.iter().filter_map(|ray| bvh8.intersect(ray)).count())
+    });
+
+    let kdtree = KdTree::new(KdTreeConfig::default(), tree.primitives.clone());
+    group.bench_function("kd-tree", |b| {
+        b.iter(|| rays.iter().filter_map(|ray| kdtree.intersect(ray)).count())
+    });
+}
+
+fn algebra_benchmark(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(0);
+    let transforms: Vec<Transform> = (0..1000)
+        .map(|_| {
+            Transform::translation(&Vec3::new(rng.gen(), rng.gen(), rng.gen()))
+                * Transform::rotate_y(rng.gen())
+                * Transform::scaling(rng.gen::<Float>() + 0.5, 1.0, 2.0)
+        })
+        .collect();
+    let vectors: Vec<Vec3> = (0..1000)
+        .map(|_| Vec3::new(rng.gen(), rng.gen(), rng.gen()))
+        .collect();
+    let points: Vec<Point3> = vectors.iter().map(|&v| Point3::from(v)).collect();
+
+    let mut group = c.benchmark_group("Algebra");
+
+    group.bench_function("Reference inverse", |b| {
+        b.iter(|| {
+            for t in &transforms {
+                black_box(reference_inverse(&t.mat));
+            }
+        })
+    });
+    group.bench_function("Inverse", |b| {
+        b.iter(|| {
+            for t in &transforms {
+                black_box(t.mat.inverse());
+            }
+        })
+    });
+
+    group.bench_function("Matrix product", |b| {
+        b.iter(|| {
+            for t in &transforms {
+                black_box(t.mat * t.inv_mat);
+            }
+        })
+    });
+    group.bench_function("Matrix product, lanes", |b| {
+        b.iter(|| {
+            for t in &transforms {
+                black_box(t.mat.mul_lanes(&t.inv_mat));
+            }
+        })
+    });
+
+    group.bench_function("Point transform", |b| {
+        b.iter(|| {
+            for (p, t) in points.iter().zip(&transforms) {
+                black_box(p.apply_t(t));
+            }
+        })
+    });
+    group.bench_function("Point transform, lanes", |b| {
+        b.iter(|| {
+            for (p, t) in points.iter().zip(&transforms) {
+                black_box(t.apply_lanes(p));
+            }
+        })
+    });
+
+    group.bench_function("Cross and dot", |b| {
+        b.iter(|| {
+            for w in vectors.windows(2) {
+                black_box(comb::dot(&comb::cross(&w[0], &w[1]), &w[0]));
+            }
+        })
+    });
+    group.bench_function("Cross and dot, lanes", |b| {
+        b.iter(|| {
+            for w in vectors.windows(2) {
+                let (a, b) = (Lanes::from(w[0]), Lanes::from(w[1]));
+                black_box(a.cross3(b).dot3(a));
+            }
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    criterion_benchmark,
+    bvh_benchmark,
+    algebra_benchmark
+);
 criterion_main!(benches);
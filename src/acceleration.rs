@@ -1,3 +1,23 @@
+/// The interface shared by the acceleration structures
+pub mod accelerator;
 /// BVHTree acceleration method
 pub mod bvh;
+/// Caching built BVHs on disk
+pub mod bvh_cache;
+/// BVH quality statistics and traversal counters
+pub mod bvh_stats;
+/// Two-level acceleration: instances of bottom level BVHs in a top level BVH
+pub mod instancing;
+/// SAH kd-tree, an alternative to the BVH
+pub mod kdtree;
+/// Morton code based BVH construction (LBVH/HLBVH), for fast builds
+pub mod lbvh;
+/// Queries for all hits along a ray
+pub mod multi_hit;
 pub mod queue_systems;
+/// Traversal of packets and streams of rays, for batches of coherent rays
+pub mod ray_packets;
+/// Spatial split BVH construction (SBVH), for scenes with long thin primitives
+pub mod sbvh;
+/// BVH4/BVH8: binary BVHs collapsed into wide nodes which are tested with SIMD
+pub mod wide_bvh;
@@ -0,0 +1,283 @@
+// The interface shared by all acceleration structures, so a scene can pick whichever works best
+// for its geometry.
+
+use crate::acceleration::bvh::{BVHConstructionAlgorithm, BVHLinearNode, BVHLinearTree};
+use crate::acceleration::bvh_stats::TraversalCounters;
+use crate::acceleration::kdtree::{KdNode, KdTree, KdTreeConfig};
+use crate::acceleration::multi_hit;
+use crate::acceleration::wide_bvh::{BVHWidth, NodeQuad, WideBVH};
+use crate::algebra::prelude::*;
+use crate::core::interaction::{HitFilter, Interaction};
+use crate::core::primitive::Primitive;
+
+use std::fmt;
+use std::mem;
+use std::sync::Arc;
+
+/// Numbers describing the shape of an acceleration structure
+#[derive(Debug, Clone, Default)]
+pub struct AcceleratorStats {
+    pub name: &'static str,
+    pub nodes: usize,
+    pub leaves: usize,
+    /// References to primitives from the leaves. More than the amount of primitives when they
+    /// can end up in multiple leaves (kd-trees, spatial splits).
+    pub primitive_references: usize,
+    pub max_depth: usize,
+    /// Approximate memory used by the nodes and primitive references, in bytes
+    pub memory: usize,
+}
+
+impl fmt::Display for AcceleratorStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} nodes, {} leaves, {} primitive references, depth {}, {} KiB",
+            self.name,
+            self.nodes,
+            self.leaves,
+            self.primitive_references,
+            self.max_depth,
+            self.memory / 1024
+        )
+    }
+}
+
+/// An acceleration structure over primitives
+pub trait Accelerator: fmt::Debug {
+    fn bounds(&self) -> BoundingBox;
+    fn intersect(&self, ray: &Ray) -> Option<Interaction<'_>>;
+    fn does_intersect(&self, ray: &Ray) -> bool;
+    /// The nearest hit accepted by `filter`
+    fn intersect_filtered(&self, ray: &Ray, filter: &HitFilter) -> Option<Interaction<'_>>;
+    /// Any hit accepted by `filter`, stopping at the first one
+    fn intersect_any(&self, ray: &Ray, filter: &HitFilter) -> Option<Interaction<'_>>;
+    fn stats(&self) -> AcceleratorStats;
+
+    /// Intersect while counting the work done, for diagnosing slow scenes. Structures which
+    /// don't keep count report zeroes.
+    fn intersect_counted(&self, ray: &Ray) -> (Option<Interaction<'_>>, TraversalCounters) {
+        (self.intersect(ray), TraversalCounters::default())
+    }
+
+    /// Every hit in `[ray.min_t, ray.max_t]` sorted by distance, or only the `max_hits` nearest.
+    /// Found by intersecting again from just past every hit, unless the structure has a better
+    /// way.
+    fn intersect_all(&self, ray: &Ray, max_hits: Option<usize>) -> Vec<Interaction<'_>> {
+        multi_hit::walk_hits(ray, ray.max_t, max_hits.unwrap_or(usize::MAX), |ray| {
+            self.intersect(ray)
+        })
+    }
+
+    /// Intersect a packet of coherent rays. Traces them one by one unless the structure has a
+    /// better way.
+    fn intersect_packet(&self, rays: &[Ray]) -> Vec<Option<Interaction<'_>>> {
+        rays.iter().map(|ray| self.intersect(ray)).collect()
+    }
+
+    fn does_intersect_packet(&self, rays: &[Ray]) -> Vec<bool> {
+        rays.iter().map(|ray| self.does_intersect(ray)).collect()
+    }
+
+    /// Intersect any amount of rays. Traces them one by one unless the structure has a better
+    /// way.
+    fn intersect_stream(&self, rays: &[Ray]) -> Vec<Option<Interaction<'_>>> {
+        rays.iter().map(|ray| self.intersect(ray)).collect()
+    }
+
+    fn does_intersect_stream(&self, rays: &[Ray]) -> Vec<bool> {
+        rays.iter().map(|ray| self.does_intersect(ray)).collect()
+    }
+}
+
+/// Which acceleration structure to build
+#[derive(Clone, Copy, Debug)]
+pub enum AcceleratorType {
+    BVH(BVHConstructionAlgorithm),
+    /// A BVH built with the given algorithm, collapsed into a wide BVH
+    WideBVH(BVHConstructionAlgorithm, BVHWidth),
+    KdTree(KdTreeConfig),
+}
+
+impl Default for AcceleratorType {
+    fn default() -> Self {
+        AcceleratorType::BVH(BVHConstructionAlgorithm::SAH)
+    }
+}
+
+impl AcceleratorType {
+    pub fn build(
+        self,
+        primitives: Vec<Arc<dyn Primitive + Sync + Send>>,
+    ) -> Box<dyn Accelerator + Sync + Send> {
+        match self {
+            AcceleratorType::BVH(algorithm) => {
+                Box::new(BVHLinearTree::build(algorithm, primitives))
+            }
+            AcceleratorType::WideBVH(algorithm, width) => Box::new(WideBVH::from_tree(
+                &BVHLinearTree::build(algorithm, primitives),
+                width,
+            )),
+            AcceleratorType::KdTree(config) => Box::new(KdTree::new(config, primitives)),
+        }
+    }
+}
+
+impl Accelerator for BVHLinearTree {
+    fn bounds(&self) -> BoundingBox {
+        self.bounds.clone()
+    }
+
+    fn intersect(&self, ray: &Ray) -> Option<Interaction<'_>> {
+        BVHLinearTree::intersect(self, ray)
+    }
+
+    fn does_intersect(&self, ray: &Ray) -> bool {
+        BVHLinearTree::does_intersect(self, ray)
+    }
+
+    fn intersect_filtered(&self, ray: &Ray, filter: &HitFilter) -> Option<Interaction<'_>> {
+        BVHLinearTree::intersect_filtered(self, ray, filter)
+    }
+
+    fn intersect_any(&self, ray: &Ray, filter: &HitFilter) -> Option<Interaction<'_>> {
+        BVHLinearTree::intersect_any(self, ray, filter)
+    }
+
+    fn stats(&self) -> AcceleratorStats {
+        let stats = self.statistics();
+        AcceleratorStats {
+            name: "BVH",
+            nodes: stats.nodes,
+            leaves: stats.leaves,
+            primitive_references: stats.primitive_references,
+            max_depth: stats.max_depth(),
+            memory: self.linear_nodes.len() * mem::size_of::<BVHLinearNode>()
+                + self.primitives.len() * mem::size_of::<Arc<dyn Primitive + Sync + Send>>(),
+        }
+    }
+
+    fn intersect_counted(&self, ray: &Ray) -> (Option<Interaction<'_>>, TraversalCounters) {
+        BVHLinearTree::intersect_counted(self, ray)
+    }
+
+    fn intersect_all(&self, ray: &Ray, max_hits: Option<usize>) -> Vec<Interaction<'_>> {
+        BVHLinearTree::intersect_all(self, ray, max_hits)
+    }
+
+    fn intersect_packet(&self, rays: &[Ray]) -> Vec<Option<Interaction<'_>>> {
+        BVHLinearTree::intersect_packet(self, rays)
+    }
+
+    fn does_intersect_packet(&self, rays: &[Ray]) -> Vec<bool> {
+        BVHLinearTree::does_intersect_packet(self, rays)
+    }
+
+    fn intersect_stream(&self, rays: &[Ray]) -> Vec<Option<Interaction<'_>>> {
+        BVHLinearTree::intersect_stream(self, rays)
+    }
+
+    fn does_intersect_stream(&self, rays: &[Ray]) -> Vec<bool> {
+        BVHLinearTree::does_intersect_stream(self, rays)
+    }
+}
+
+impl Accelerator for WideBVH {
+    fn bounds(&self) -> BoundingBox {
+        self.bounds.clone()
+    }
+
+    fn intersect(&self, ray: &Ray) -> Option<Interaction<'_>> {
+        WideBVH::intersect(self, ray)
+    }
+
+    fn does_intersect(&self, ray: &Ray) -> bool {
+        WideBVH::does_intersect(self, ray)
+    }
+
+    fn intersect_filtered(&self, ray: &Ray, filter: &HitFilter) -> Option<Interaction<'_>> {
+        WideBVH::intersect_filtered(self, ray, filter)
+    }
+
+    fn intersect_any(&self, ray: &Ray, filter: &HitFilter) -> Option<Interaction<'_>> {
+        WideBVH::intersect_any(self, ray, filter)
+    }
+
+    fn stats(&self) -> AcceleratorStats {
+        let groups = self.width.lanes() / 4;
+        let mut stats = AcceleratorStats {
+            name: match self.width {
+                BVHWidth::BVH4 => "BVH4",
+                BVHWidth::BVH8 => "BVH8",
+            },
+            nodes: self.quads.len() / groups,
+            memory: self.quads.len() * mem::size_of::<NodeQuad>()
+                + self.primitives.len() * mem::size_of::<Arc<dyn Primitive + Sync + Send>>(),
+            ..AcceleratorStats::default()
+        };
+        if self.quads.is_empty() {
+            return stats;
+        }
+        let mut stack = vec![(0, 0)];
+        while let Some((node, depth)) = stack.pop() {
+            stats.max_depth = stats.max_depth.max(depth);
+            for quad in &self.quads[node * groups..(node + 1) * groups] {
+                for lane in 0..4 {
+                    // Empty lanes have inverted bounds
+                    if quad.min_x[lane] > quad.max_x[lane] {
+                        continue;
+                    }
+                    let amount = quad.primitive_amount[lane] as usize;
+                    if amount > 0 {
+                        stats.leaves += 1;
+                        stats.primitive_references += amount;
+                    } else {
+                        stack.push((quad.child[lane] as usize, depth + 1));
+                    }
+                }
+            }
+        }
+        stats
+    }
+}
+
+impl Accelerator for KdTree {
+    fn bounds(&self) -> BoundingBox {
+        self.bounds.clone()
+    }
+
+    fn intersect(&self, ray: &Ray) -> Option<Interaction<'_>> {
+        KdTree::intersect(self, ray)
+    }
+
+    fn does_intersect(&self, ray: &Ray) -> bool {
+        KdTree::does_intersect(self, ray)
+    }
+
+    fn intersect_filtered(&self, ray: &Ray, filter: &HitFilter) -> Option<Interaction<'_>> {
+        KdTree::intersect_filtered(self, ray, filter)
+    }
+
+    fn intersect_any(&self, ray: &Ray, filter: &HitFilter) -> Option<Interaction<'_>> {
+        KdTree::intersect_any(self, ray, filter)
+    }
+
+    fn stats(&self) -> AcceleratorStats {
+        AcceleratorStats {
+            name: "kd-tree",
+            nodes: self.nodes.len(),
+            leaves: self
+                .nodes
+                .iter()
+                .filter(|node| match node {
+                    KdNode::Leaf { .. } => true,
+                    KdNode::Interior { .. } => false,
+                })
+                .count(),
+            primitive_references: self.primitive_indices.len(),
+            max_depth: self.max_depth,
+            memory: self.nodes.len() * mem::size_of::<KdNode>()
+                + self.primitive_indices.len() * mem::size_of::<usize>(),
+        }
+    }
+}
@@ -5,10 +5,13 @@
 // It's the very backbone of the performance that Thruster has, and so it's the piece that needs
 // most performance tweaking.
 
+use crate::acceleration::lbvh;
 use crate::acceleration::queue_systems::FastStack;
+use crate::acceleration::sbvh::{self, SpatialSplitConfig};
 use crate::algebra::prelude::*;
-use crate::core::{interaction::Interaction, primitive::Primitive};
-use crate::geometry::shape::Shape;
+use crate::algebra::ray::RayPrecompute;
+use crate::core::interaction::{HitFilter, Interaction};
+use crate::core::primitive::Primitive;
 use crate::utils;
 use std::sync::Arc;
 
@@ -38,7 +41,7 @@ impl Default for BucketInfo {
 }
 
 /// The approach to be used in constructing a BVH tree
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub enum BVHConstructionAlgorithm {
     /// Basic and primitive way
     Middle,
@@ -47,6 +50,16 @@ pub enum BVHConstructionAlgorithm {
     /// Surface Area Heuristic: One of the better systems for constructing optimal BVH trees. It
     /// constructs very optimal trees, but is slightly slower. Perfect for static scenes
     SAH,
+    /// Linear BVH: Sorts primitives along a Morton curve and emits the tree straight from the
+    /// codes. Very fast to build, but the trees are of lower quality. Useful for dynamic scenes
+    LBVH,
+    /// Hierarchical Linear BVH: Like `LBVH`, but the upper levels of the tree are built using
+    /// SAH, which recovers most of the quality at nearly the same build speed
+    HLBVH,
+    /// Spatial split BVH: SAH which may also split space, referencing primitives from both
+    /// sides. Builds the best trees for scenes with large or long thin primitives, at the cost of
+    /// slower construction and more memory
+    SBVH(SpatialSplitConfig),
 }
 
 impl BVHConstructionAlgorithm {
@@ -58,7 +71,13 @@ impl BVHConstructionAlgorithm {
         centroid_bounds: &BoundingBox,
     ) -> Option<usize> {
         match self {
-            BVHConstructionAlgorithm::Middle => {
+            // The Morton-based and spatial split builders don't partition recursively, see
+            // [BVHAccel::construct](struct.BVHAccel.html#method.construct). Fall back to a middle
+            // split in case this is ever called for them.
+            BVHConstructionAlgorithm::Middle
+            | BVHConstructionAlgorithm::LBVH
+            | BVHConstructionAlgorithm::HLBVH
+            | BVHConstructionAlgorithm::SBVH(_) => {
                 let len = primitive_info.len();
                 let pmid = (centroid_bounds.min[dimension] + centroid_bounds.max[dimension]) / 2.0;
                 let mut middle =
@@ -105,7 +124,7 @@ impl BVHConstructionAlgorithm {
                     let bucket_amount = 12;
                     let mut buckets: Vec<BucketInfo> = vec![BucketInfo::default(); bucket_amount];
                     for info in primitive_info.iter() {
-                        let b = (f64::from(bucket_amount as u32)
+                        let b = (bucket_amount as Float
                             * centroid_bounds.offset(&info.centre)[(dimension)])
                             as usize;
                         let b = if b == bucket_amount {
@@ -125,17 +144,19 @@ impl BVHConstructionAlgorithm {
                     //.collect::<Vec<usize>>()
                     //);
 
+                    // Splitting after bucket `n` puts buckets `0..=n` on one side and the rest on
+                    // the other
                     let mut cost = vec![0.0; bucket_amount - 1];
-                    for bucket_n in 0..bucket_amount - 1 {
-                        let mut bounds_a = buckets[0].bounding_box.clone();
-                        let mut bounds_b = buckets[bucket_n + 1].bounding_box.clone();
+                    for (bucket_n, bucket_cost) in cost.iter_mut().enumerate() {
+                        let mut bounds_a = BoundingBox::EMPTY;
+                        let mut bounds_b = BoundingBox::EMPTY;
                         let mut count_a = 0;
                         let mut count_b = 0;
-                        for bucket in buckets.iter().take(bucket_n - 1) {
+                        for bucket in buckets.iter().take(bucket_n + 1) {
                             bounds_a = bounds_a.merge(&bucket.bounding_box);
                             count_a += bucket.count;
                         }
-                        for bucket in buckets.iter().skip(bucket_n - 1) {
+                        for bucket in buckets.iter().skip(bucket_n + 1) {
                             bounds_b = bounds_b.merge(&bucket.bounding_box);
                             count_b += bucket.count;
                         }
@@ -145,7 +166,7 @@ impl BVHConstructionAlgorithm {
                         //bounds_a.surface_area(),
                         //bounds_b.surface_area()
                         //);
-                        cost[bucket_n] = 0.125
+                        *bucket_cost = 0.125
                             + (f64::from(count_a as u32) * bounds_a.surface_area()
                                 + f64::from(count_b as u32) * bounds_b.surface_area())
                                 / aggregate_bounds.surface_area();
@@ -163,7 +184,7 @@ impl BVHConstructionAlgorithm {
                     let leaf_cost = len;
                     if leaf_cost > 16 || minimum.0 < f64::from(leaf_cost as u32) {
                         let middle = utils::partition(primitive_info, |pi: &BVHPrimitiveInfo| {
-                            let b = (f64::from(bucket_amount as u32)
+                            let b = (bucket_amount as Float
                                 * centroid_bounds.offset(&pi.centre)[(dimension)])
                                 as usize;
                             let b = if b >= bucket_amount {
@@ -250,6 +271,13 @@ impl BVHAccel {
     }
 
     pub fn construct(&mut self) -> Option<(usize, BVHBuildNode)> {
+        self.construct_with_ordering()
+            .map(|(total_nodes, node, _)| (total_nodes, node))
+    }
+
+    /// Like [construct](#method.construct), but also returns which of the original primitives
+    /// ended up at each position of the reordered primitives
+    pub fn construct_with_ordering(&mut self) -> Option<(usize, BVHBuildNode, Vec<usize>)> {
         if self.primitives.is_empty() {
             None
         } else {
@@ -262,23 +290,51 @@ impl BVHAccel {
                     index: i,
                 });
             }
-            let mut total_nodes = 0;
-            let mut ordered_shapes = Vec::new();
 
-            let node =
-                self.recursive_build(&mut primitive_info, &mut total_nodes, &mut ordered_shapes);
-            self.primitives.swap_with_slice(&mut ordered_shapes);
-            Some((total_nodes, node))
+            let (total_nodes, node, ordering) = self.build_hierarchy(primitive_info);
+            // Spatial splits may reference primitives more than once, so this can be longer
+            self.primitives = ordering
+                .iter()
+                .map(|&index| self.primitives[index].clone())
+                .collect();
+            Some((total_nodes, node, ordering))
         }
     }
 
+    /// Build a hierarchy over `primitive_info`, which must not be empty. Returns the amount of
+    /// nodes, the root, and the order in which the leaves reference the primitives as indices
+    /// into `primitive_info`.
+    pub fn build_hierarchy(
+        &self,
+        mut primitive_info: Vec<BVHPrimitiveInfo>,
+    ) -> (usize, BVHBuildNode, Vec<usize>) {
+        let mut total_nodes = 0;
+        let mut ordering = Vec::with_capacity(primitive_info.len());
+
+        let node = match self.algorithm {
+            BVHConstructionAlgorithm::LBVH | BVHConstructionAlgorithm::HLBVH => {
+                let upper_sah = matches!(self.algorithm, BVHConstructionAlgorithm::HLBVH);
+                lbvh::construct(&primitive_info, upper_sah, &mut total_nodes, &mut ordering)
+            }
+            BVHConstructionAlgorithm::SBVH(config) => sbvh::construct(
+                &self.primitives,
+                primitive_info,
+                config,
+                &mut total_nodes,
+                &mut ordering,
+            ),
+            _ => self.recursive_build(&mut primitive_info, &mut total_nodes, &mut ordering),
+        };
+        (total_nodes, node, ordering)
+    }
+
     /// Recursively build a [BVHBuildNode](struct.BVHBuildNode.html) by splitting with a
     /// particular algorithm
     pub fn recursive_build(
         &self,
         primitive_info: &mut [BVHPrimitiveInfo],
         total_nodes: &mut usize,
-        ordered_shapes: &mut Vec<Arc<dyn Primitive + Sync + Send>>,
+        ordering: &mut Vec<usize>,
     ) -> BVHBuildNode {
         assert!(!primitive_info.is_empty());
         *total_nodes += 1;
@@ -289,10 +345,8 @@ impl BVHAccel {
 
         let len = primitive_info.len();
         if len == 1 {
-            let first_offset = ordered_shapes.len();
-            for info in primitive_info {
-                ordered_shapes.push(self.primitives[info.index].clone()); // TODO: Remove clone; this should be possible by using Rc during construction instead of pure Shapes
-            }
+            let first_offset = ordering.len();
+            ordering.extend(primitive_info.iter().map(|info| info.index));
             BVHBuildNode::new_leaf(first_offset, len, aggregate_bounds)
         } else {
             let centroid_bounds = primitive_info
@@ -302,13 +356,11 @@ impl BVHAccel {
                 });
             let dimension = centroid_bounds.max_extent();
             if (centroid_bounds.max[dimension] - centroid_bounds.min[dimension]).abs()
-                < std::f64::EPSILON
+                < float::EPSILON
             {
                 // Centroid bounds are small, construct leaf node.
-                let first_offset = ordered_shapes.len();
-                for info in primitive_info {
-                    ordered_shapes.push(self.primitives[info.index].clone()); // TODO: See above
-                }
+                let first_offset = ordering.len();
+                ordering.extend(primitive_info.iter().map(|info| info.index));
                 BVHBuildNode::new_leaf(first_offset, len, aggregate_bounds)
             } else if let Some(middle) = self.algorithm.perform_partitioning(
                 primitive_info,
@@ -321,19 +373,17 @@ impl BVHAccel {
                     Box::new(self.recursive_build(
                         &mut primitive_info[..middle],
                         total_nodes,
-                        ordered_shapes,
+                        ordering,
                     )),
                     Box::new(self.recursive_build(
                         &mut primitive_info[middle..],
                         total_nodes,
-                        ordered_shapes,
+                        ordering,
                     )),
                 )
             } else {
-                let first_offset = ordered_shapes.len();
-                for info in primitive_info {
-                    ordered_shapes.push(self.primitives[info.index].clone()); // TODO: See above
-                }
+                let first_offset = ordering.len();
+                ordering.extend(primitive_info.iter().map(|info| info.index));
                 BVHBuildNode::new_leaf(first_offset, len, aggregate_bounds)
             }
         }
@@ -345,9 +395,29 @@ impl BVHAccel {
             bounds,
             linear_nodes: Vec::with_capacity(total_nodes),
             primitives: self.primitives,
+            algorithm: self.algorithm,
+            build_cost: 0.0,
         };
         let mut offset = 0;
         tree.flatten_from(node, &mut offset);
+        tree.build_cost = match tree.algorithm {
+            // Refitting loses the clipping of spatial splits, so measure against refitted bounds,
+            // otherwise the first refit would always look like a degradation
+            BVHConstructionAlgorithm::SBVH(_) => {
+                let clipped: Vec<BoundingBox> = tree
+                    .linear_nodes
+                    .iter()
+                    .map(|node| node.bounding_box.clone())
+                    .collect();
+                tree.refit();
+                let cost = tree.sah_cost();
+                for (node, bounding_box) in tree.linear_nodes.iter_mut().zip(clipped) {
+                    node.bounding_box = bounding_box;
+                }
+                cost
+            }
+            _ => tree.sah_cost(),
+        };
         tree
     }
 }
@@ -357,10 +427,17 @@ pub struct BVHLinearTree {
     pub bounds: BoundingBox,
     pub linear_nodes: Vec<BVHLinearNode>,
     pub primitives: Vec<Arc<dyn Primitive + Sync + Send>>,
+    /// The algorithm the tree was built with, used again when it has to be rebuilt
+    pub algorithm: BVHConstructionAlgorithm,
+    /// SAH cost of the tree right after it was built
+    pub build_cost: f64,
 }
 
+/// Cost of traversing a node relative to intersecting a primitive, as used by the SAH split
+pub const TRAVERSAL_COST: f64 = 0.125;
+
 /// A compacted BVHNode for use in indexing
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BVHLinearNode {
     pub bounding_box: BoundingBox,
     pub primitive_amount: usize,
@@ -380,6 +457,91 @@ impl Default for BVHLinearNode {
 }
 
 impl BVHLinearTree {
+    /// Construct and flatten a tree over `primitives`. Without any primitives the tree is empty,
+    /// and never intersects anything.
+    pub fn build(
+        algorithm: BVHConstructionAlgorithm,
+        primitives: Vec<Arc<dyn Primitive + Sync + Send>>,
+    ) -> Self {
+        let mut accel = BVHAccel::new(algorithm, primitives);
+        match accel.construct() {
+            Some((total, node)) => accel.flatten(Box::new(node), total),
+            None => Self {
+                bounds: BoundingBox::EMPTY,
+                linear_nodes: Vec::new(),
+                primitives: Vec::new(),
+                algorithm,
+                build_cost: 0.0,
+            },
+        }
+    }
+
+    /// Recompute the bounds of every node bottom-up from the current bounds of the primitives.
+    ///
+    /// This is for animated geometry: replace the moved primitives in `primitives` (keeping their
+    /// positions in the vector) and refit, instead of building a new tree. The topology of the
+    /// tree stays the same, so its quality degrades the further things move, see
+    /// [refit_or_rebuild](#method.refit_or_rebuild).
+    pub fn refit(&mut self) {
+        // Children are always flattened after their parent, so walking the nodes backwards
+        // visits them before the parent
+        for i in (0..self.linear_nodes.len()).rev() {
+            let node = &self.linear_nodes[i];
+            let bounding_box = if node.primitive_amount > 0 {
+                self.primitives[node.node_content..node.node_content + node.primitive_amount]
+                    .iter()
+                    .fold(BoundingBox::EMPTY, |acc, prim| acc.merge(&prim.bounds()))
+            } else {
+                self.linear_nodes[i + 1]
+                    .bounding_box
+                    .merge(&self.linear_nodes[node.node_content].bounding_box)
+            };
+            self.linear_nodes[i].bounding_box = bounding_box;
+        }
+        self.bounds = match self.linear_nodes.first() {
+            Some(root) => root.bounding_box.clone(),
+            None => BoundingBox::EMPTY,
+        };
+    }
+
+    /// The expected cost of intersecting a random ray with the tree, according to the Surface
+    /// Area Heuristic. Lower is better.
+    pub fn sah_cost(&self) -> f64 {
+        let root_area = self.bounds.surface_area();
+        if self.linear_nodes.is_empty() || root_area <= 0.0 {
+            return 0.0;
+        }
+        self.linear_nodes
+            .iter()
+            .map(|node| {
+                let cost = if node.primitive_amount > 0 {
+                    f64::from(node.primitive_amount as u32)
+                } else {
+                    TRAVERSAL_COST
+                };
+                cost * node.bounding_box.surface_area() / root_area
+            })
+            .sum()
+    }
+
+    /// Refit the tree, and rebuild it from scratch when its SAH cost has grown beyond
+    /// `max_degradation` times the cost it had when it was built (for example `1.5`).
+    /// Returns whether the tree was rebuilt.
+    pub fn refit_or_rebuild(&mut self, max_degradation: f64) -> bool {
+        self.refit();
+        if self.sah_cost() <= self.build_cost * max_degradation {
+            return false;
+        }
+        let mut primitives = std::mem::take(&mut self.primitives);
+        if let BVHConstructionAlgorithm::SBVH(_) = self.algorithm {
+            // Spatial splits reference primitives more than once
+            let mut seen = std::collections::HashSet::new();
+            primitives.retain(|prim| seen.insert(&**prim as *const _ as *const () as usize));
+        }
+        *self = Self::build(self.algorithm, primitives);
+        true
+    }
+
     pub fn flatten_from(&mut self, node: Box<BVHBuildNode>, offset: &mut usize) -> usize {
         self.linear_nodes.push(BVHLinearNode::default()); // FIXME: this should not be necessary
         let my_offset = *offset;
@@ -412,21 +574,17 @@ impl BVHLinearTree {
     }
 
     pub fn does_intersect(&self, ray: &Ray) -> bool {
-        let inv_dir = Vec3::new(
-            1.0 / ray.direction.x,
-            1.0 / ray.direction.y,
-            1.0 / ray.direction.z,
-        );
-        let sign = Vec3::new(
-            if ray.direction.x > 0.0 { 1.0 } else { 0.0 },
-            if ray.direction.y > 0.0 { 1.0 } else { 0.0 },
-            if ray.direction.z > 0.0 { 1.0 } else { 0.0 },
-        );
+        if self.linear_nodes.is_empty() {
+            return false;
+        }
+        let pre = RayPrecompute::new(ray);
         let mut current_task = 0;
         let mut queue = FastStack::new();
         loop {
             let n = &self.linear_nodes[current_task];
-            if n.bounding_box.does_intersect(ray) {
+            if n.bounding_box
+                .intersect_precomputed(ray, &pre, float::INFINITY)
+            {
                 if n.primitive_amount > 0 {
                     for i in 0..n.primitive_amount {
                         let prim = &self.primitives[i + n.node_content];
@@ -438,7 +596,7 @@ impl BVHLinearTree {
                         None => return false,
                         Some(task) => current_task = task,
                     };
-                } else if inv_dir[n.axis] < 0.0 {
+                } else if pre.dir_is_neg[n.axis] {
                     queue.push(current_task + 1);
                     current_task = n.node_content;
                 } else {
@@ -454,34 +612,53 @@ impl BVHLinearTree {
         }
     }
 
-    pub fn intersect(&self, ray: &Ray) -> Option<Interaction> {
-        let inv_dir = Vec3::new(
-            1.0 / ray.direction.x,
-            1.0 / ray.direction.y,
-            1.0 / ray.direction.z,
-        );
-        let sign = Vec3::new(
-            if ray.direction.x > 0.0 { 1.0 } else { 0.0 },
-            if ray.direction.y > 0.0 { 1.0 } else { 0.0 },
-            if ray.direction.z > 0.0 { 1.0 } else { 0.0 },
-        );
+    pub fn intersect(&self, ray: &Ray) -> Option<Interaction<'_>> {
+        self.intersect_with(ray, |_| true, false)
+    }
+
+    /// The nearest hit accepted by `filter`
+    pub fn intersect_filtered(&self, ray: &Ray, filter: &HitFilter) -> Option<Interaction<'_>> {
+        self.intersect_with(ray, filter, false)
+    }
+
+    /// Any hit accepted by `filter`, not necessarily the nearest. Traversal stops at the first
+    /// one, which makes this much cheaper for occlusion tests which need to skip some hits.
+    pub fn intersect_any(&self, ray: &Ray, filter: &HitFilter) -> Option<Interaction<'_>> {
+        self.intersect_with(ray, filter, true)
+    }
+
+    fn intersect_with<F>(&self, ray: &Ray, accept: F, any_hit: bool) -> Option<Interaction<'_>>
+    where
+        F: Fn(&Interaction) -> bool,
+    {
+        if self.linear_nodes.is_empty() {
+            return None;
+        }
+        let pre = RayPrecompute::new(ray);
         let mut current_task = 0;
         let mut queue = FastStack::new();
         let mut closest: Option<Interaction> = None;
+        // Nodes further away than the closest hit so far can't contain anything closer
+        let mut closest_t = float::INFINITY;
         loop {
             let n = &self.linear_nodes[current_task];
-            if n.bounding_box.does_intersect(ray) {
+            if n.bounding_box.intersect_precomputed(ray, &pre, closest_t) {
                 if n.primitive_amount > 0 {
                     for i in 0..n.primitive_amount {
                         let prim = &self.primitives[i + n.node_content];
                         if let Some(geom) = prim.intersect(ray) {
-                            let interaction = Interaction {
-                                geom,
-                                primitive: Arc::clone(prim),
-                            };
-                            closest = match closest {
-                                None => Some(interaction),
-                                Some(closest) => Some(closest.nearest(interaction)),
+                            if geom.t < closest_t {
+                                let interaction = Interaction {
+                                    geom,
+                                    primitive: Arc::clone(prim),
+                                };
+                                if accept(&interaction) {
+                                    if any_hit {
+                                        return Some(interaction);
+                                    }
+                                    closest_t = interaction.geom.t;
+                                    closest = Some(interaction);
+                                }
                             }
                         }
                     }
@@ -489,7 +666,7 @@ impl BVHLinearTree {
                         None => return closest,
                         Some(task) => current_task = task,
                     };
-                } else if inv_dir[n.axis] < 0.0 {
+                } else if pre.dir_is_neg[n.axis] {
                     queue.push(current_task + 1);
                     current_task = n.node_content;
                 } else {
@@ -505,3 +682,284 @@ impl BVHLinearTree {
         }
     }
 }
+
+/// Random scenes and brute force answers, for checking acceleration structures against
+#[cfg(test)]
+pub(crate) mod testing {
+    use crate::algebra::prelude::*;
+    use crate::core::material::Matte;
+    use crate::core::medium::{HomogeneousMedium, MediumInterface};
+    use crate::core::primitive::{GeometricPrimitive, Primitive};
+    use crate::core::spectrum::RGBSpectrum;
+    use crate::core::texture::ConstantTexture;
+    use crate::geometry::shape::Shape;
+    use crate::geometry::sphere::Sphere;
+    use crate::geometry::triangle::Triangle;
+    use rand::prelude::*;
+    use std::sync::Arc;
+
+    pub fn primitive(shape: Arc<dyn Shape>) -> Arc<dyn Primitive + Sync + Send> {
+        Arc::new(GeometricPrimitive {
+            shape,
+            material: Arc::new(Matte::lambertian(Arc::new(ConstantTexture::new(
+                RGBSpectrum::BLACK,
+            )))),
+            emission: RGBSpectrum::BLACK,
+            medium_interface: MediumInterface {
+                inside: Box::new(HomogeneousMedium::default()),
+                outside: Box::new(HomogeneousMedium::default()),
+            },
+        })
+    }
+
+    /// Spheres of different sizes spread through a cube of 100 units
+    pub fn spheres(n: usize, seed: u64) -> Vec<Arc<dyn Primitive + Sync + Send>> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..n)
+            .map(|_| {
+                let origin = Point3::new(
+                    rng.gen::<Float>() * 100.0,
+                    rng.gen::<Float>() * 100.0,
+                    rng.gen::<Float>() * 100.0,
+                );
+                primitive(Arc::new(Sphere::new(origin, 0.5 + rng.gen::<Float>())))
+            })
+            .collect()
+    }
+
+    /// Long, thin triangles running diagonally through the same cube, which make the children
+    /// of object splits overlap a lot
+    pub fn long_triangles(n: usize, seed: u64) -> Vec<Arc<dyn Primitive + Sync + Send>> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut point = || {
+            Vec3::new(
+                rng.gen::<Float>() * 100.0,
+                rng.gen::<Float>() * 100.0,
+                rng.gen::<Float>() * 100.0,
+            )
+        };
+        (0..n)
+            .map(|_| {
+                let a = point();
+                let b = point();
+                let normal = Vec3::new(0.0, 1.0, 0.0);
+                let vertex = |origin| Vertex::new(origin, normal, Vec2::new(0.0, 0.0));
+                primitive(Arc::new(Triangle::new(
+                    vertex(a),
+                    vertex(b),
+                    vertex(b + Vec3::new(0.5, 0.0, 0.5)),
+                )))
+            })
+            .collect()
+    }
+
+    /// Rays through the cube, starting in front of it
+    pub fn rays(n: usize, seed: u64) -> Vec<Ray> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..n)
+            .map(|_| {
+                Ray::new(
+                    Point3::new(
+                        rng.gen::<Float>() * 100.0,
+                        rng.gen::<Float>() * 100.0,
+                        -50.0,
+                    ),
+                    Vec3::new(rng.gen::<Float>() - 0.5, rng.gen::<Float>() - 0.5, 1.0),
+                )
+            })
+            .collect()
+    }
+
+    /// The distance to the nearest hit, by intersecting every primitive
+    pub fn brute_force(
+        primitives: &[Arc<dyn Primitive + Sync + Send>],
+        ray: &Ray,
+    ) -> Option<Float> {
+        primitives
+            .iter()
+            .filter_map(|primitive| primitive.intersect(ray))
+            .map(|geom| geom.t)
+            .fold(None, |nearest: Option<Float>, t| {
+                Some(nearest.map_or(t, |nearest| nearest.min(t)))
+            })
+    }
+
+    /// Whether two hit distances are the same, up to rounding
+    pub fn same_hit(a: Option<Float>, b: Option<Float>) -> bool {
+        match (a, b) {
+            (Some(a), Some(b)) => (a - b).abs() <= (1e-4 as Float).max(a.abs() * 1e-5),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::{primitive, spheres};
+    use super::*;
+    use crate::core::interaction::Interaction;
+    use crate::geometry::sphere::Sphere;
+
+    /// Replace every primitive with a sphere of the same size, moved by `offset(i)`
+    fn move_spheres<F: Fn(usize) -> Vec3>(tree: &mut BVHLinearTree, offset: F) {
+        for (i, prim) in tree.primitives.iter_mut().enumerate() {
+            let bounds = prim.bounds();
+            let radius = (bounds.max.x - bounds.min.x) / 2.0;
+            *prim = primitive(Arc::new(Sphere::new(bounds.centre() + offset(i), radius)));
+        }
+    }
+
+    fn contains(outer: &BoundingBox, inner: &BoundingBox) -> bool {
+        (0..3).all(|axis| {
+            outer.min[axis] <= inner.min[axis] + 1e-6 && outer.max[axis] >= inner.max[axis] - 1e-6
+        })
+    }
+
+    #[test]
+    fn traversal_matches_brute_force() {
+        let primitives = spheres(1000, 7);
+        let mut rays = testing::rays(1000, 9);
+        // Axis-parallel rays, where the inverse direction is infinite, in either direction
+        for i in 0..200 {
+            let origin = Point3::new((i % 20) as Float * 5.0, (i / 20) as Float * 10.0, 50.0);
+            let sign = if i % 2 == 0 { 1.0 } else { -1.0 };
+            let mut direction = Vec3::new(0.0, 0.0, 0.0);
+            direction[i % 3] = sign;
+            rays.push(Ray::new(origin, direction));
+        }
+
+        for &algorithm in &[
+            BVHConstructionAlgorithm::Middle,
+            BVHConstructionAlgorithm::Equal,
+            BVHConstructionAlgorithm::SAH,
+            BVHConstructionAlgorithm::LBVH,
+            BVHConstructionAlgorithm::HLBVH,
+            BVHConstructionAlgorithm::SBVH(Default::default()),
+        ] {
+            let tree = BVHLinearTree::build(algorithm, primitives.clone());
+            for ray in &rays {
+                let expected = testing::brute_force(&primitives, ray);
+                let nearest = tree.intersect(ray).map(|i| i.geom.t);
+                assert!(
+                    testing::same_hit(nearest, expected),
+                    "{:?} {:?} {:?}",
+                    algorithm,
+                    nearest,
+                    expected
+                );
+                assert_eq!(tree.does_intersect(ray), expected.is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn filtered_and_any_hit_queries() {
+        let primitives = spheres(1000, 13);
+        let tree = BVHLinearTree::build(BVHConstructionAlgorithm::SAH, primitives.clone());
+        let left = |i: &Interaction| i.primitive.bounds().centre().x < 50.0;
+        let left_primitives: Vec<_> = primitives
+            .into_iter()
+            .filter(|p| p.bounds().centre().x < 50.0)
+            .collect();
+        for ray in &testing::rays(1000, 14) {
+            let expected = testing::brute_force(&left_primitives, ray);
+            let nearest = tree.intersect_filtered(ray, &left);
+            assert!(nearest.as_ref().is_none_or(&left));
+            assert!(testing::same_hit(nearest.map(|i| i.geom.t), expected));
+
+            let any = tree.intersect_any(ray, &left);
+            assert_eq!(any.is_some(), expected.is_some());
+            if let (Some(any), Some(expected)) = (any, expected) {
+                assert!(left(&any));
+                assert!(any.geom.t >= expected - 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn refit_bounds_contain_everything() {
+        let mut tree = BVHLinearTree::build(BVHConstructionAlgorithm::SAH, spheres(300, 1));
+        move_spheres(&mut tree, |i| {
+            Vec3::new(
+                (i % 7) as Float,
+                (i % 5) as Float * -2.0,
+                (i % 3) as Float * 3.0,
+            )
+        });
+        tree.refit();
+
+        for (i, node) in tree.linear_nodes.iter().enumerate() {
+            if node.primitive_amount > 0 {
+                let primitives = &tree.primitives[node.node_content..][..node.primitive_amount];
+                for prim in primitives {
+                    assert!(contains(&node.bounding_box, &prim.bounds()));
+                }
+            } else {
+                assert!(contains(
+                    &node.bounding_box,
+                    &tree.linear_nodes[i + 1].bounding_box
+                ));
+                let right = &tree.linear_nodes[node.node_content].bounding_box;
+                assert!(contains(&node.bounding_box, right));
+            }
+        }
+        assert!(contains(&tree.bounds, &tree.linear_nodes[0].bounding_box));
+    }
+
+    #[test]
+    fn refit_or_rebuild_rebuilds_past_the_threshold() {
+        let mut tree = BVHLinearTree::build(BVHConstructionAlgorithm::SAH, spheres(300, 1));
+
+        // Nudging everything a little keeps the tree good enough
+        move_spheres(&mut tree, |_| Vec3::new(0.1, 0.0, 0.0));
+        assert!(!tree.refit_or_rebuild(1.5));
+
+        // Scattering the primitives makes the nodes overlap everywhere
+        move_spheres(&mut tree, |i| {
+            let sign = if i % 2 == 0 { 1.0 } else { -1.0 };
+            Vec3::new(sign * 60.0, 0.0, -sign * 60.0)
+        });
+        tree.refit();
+        assert!(tree.sah_cost() > tree.build_cost * 1.5);
+        assert!(tree.refit_or_rebuild(1.5));
+        assert!(tree.sah_cost() <= tree.build_cost * 1.5);
+        assert_eq!(tree.primitives.len(), 300);
+    }
+
+    #[test]
+    fn sah_splits_between_clusters() {
+        // Two clumps of boxes far apart, which any sensible split separates
+        let mut primitive_info: Vec<BVHPrimitiveInfo> = (0..20)
+            .map(|i| {
+                let x = if i % 2 == 0 { 0.0 } else { 99.0 } + (i / 2) as Float * 0.01;
+                let bounding_box = BoundingBox {
+                    min: Point3::new(x, 0.0, 0.0),
+                    max: Point3::new(x + 1.0, 1.0, 1.0),
+                };
+                BVHPrimitiveInfo {
+                    centre: bounding_box.centre(),
+                    bounding_box,
+                    index: i,
+                }
+            })
+            .collect();
+        let aggregate_bounds = primitive_info.iter().fold(BoundingBox::EMPTY, |acc, info| {
+            acc.merge(&info.bounding_box)
+        });
+        let centroid_bounds = primitive_info.iter().fold(BoundingBox::EMPTY, |acc, info| {
+            acc.merge_with_point(&info.centre)
+        });
+
+        let middle = BVHConstructionAlgorithm::SAH
+            .perform_partitioning(&mut primitive_info, 0, &aggregate_bounds, &centroid_bounds)
+            .unwrap();
+        assert_eq!(middle, 10);
+        assert!(primitive_info[..middle]
+            .iter()
+            .all(|info| info.centre.x < 50.0));
+        assert!(primitive_info[middle..]
+            .iter()
+            .all(|info| info.centre.x > 50.0));
+    }
+}
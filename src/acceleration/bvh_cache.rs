@@ -0,0 +1,298 @@
+// On-disk cache of built BVHs.
+//
+// Building the BVH of a large mesh can take longer than a preview render, while the result only
+// depends on the primitives and the build parameters. The flattened nodes and the order of the
+// primitives are written to a compact binary file, together with a hash of everything that went
+// into the build. When the hash still matches, the tree is loaded from the file instead.
+
+use crate::acceleration::bvh::{BVHAccel, BVHConstructionAlgorithm, BVHLinearNode, BVHLinearTree};
+use crate::algebra::prelude::*;
+use crate::core::primitive::Primitive;
+
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Bumped whenever the layout of the cache or the builders change, so old caches are rebuilt
+const CACHE_VERSION: u32 = 2;
+
+#[derive(Debug)]
+pub enum CacheError {
+    Io(io::Error),
+    Encoding(bincode::Error),
+    /// The cache was built from different primitives or parameters
+    KeyMismatch,
+    /// The cache doesn't describe a valid tree over the primitives
+    Corrupt,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedTree {
+    version: u32,
+    key: u64,
+    primitive_amount: usize,
+    bounds: BoundingBox,
+    build_cost: f64,
+    linear_nodes: Vec<BVHLinearNode>,
+    /// For every position in the tree's primitives, the index of the original primitive
+    ordering: Vec<usize>,
+}
+
+/// 64 bit FNV-1a, which unlike the standard library's hashers is guaranteed to stay the same
+/// between builds
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// The key identifying a build: a hash of the algorithm with its parameters and of the geometry
+/// of every primitive, in order
+pub fn cache_key(
+    algorithm: BVHConstructionAlgorithm,
+    primitives: &[Arc<dyn Primitive + Sync + Send>],
+) -> u64 {
+    let mut hash = Fnv::new();
+    hash.write(&CACHE_VERSION.to_le_bytes());
+    // The nodes are stored in the precision of the geometry
+    hash.write(&(std::mem::size_of::<Float>() as u64).to_le_bytes());
+    hash.write(format!("{:?}", algorithm).as_bytes());
+    hash.write(&(primitives.len() as u64).to_le_bytes());
+    for primitive in primitives {
+        primitive.hash_geometry(&mut hash);
+    }
+    hash.finish()
+}
+
+/// Build a tree and remember the order of the primitives, so it can be cached
+fn build(
+    algorithm: BVHConstructionAlgorithm,
+    primitives: Vec<Arc<dyn Primitive + Sync + Send>>,
+) -> (BVHLinearTree, Vec<usize>) {
+    let mut accel = BVHAccel::new(algorithm, primitives);
+    match accel.construct_with_ordering() {
+        Some((total, node, ordering)) => (accel.flatten(Box::new(node), total), ordering),
+        None => (BVHLinearTree::build(algorithm, Vec::new()), Vec::new()),
+    }
+}
+
+/// Write `tree` to `path`. `ordering` maps every primitive of the tree to its index in the
+/// primitives the key was computed from.
+pub fn save<P: AsRef<Path>>(
+    path: P,
+    key: u64,
+    primitive_amount: usize,
+    tree: &BVHLinearTree,
+    ordering: &[usize],
+) -> Result<(), CacheError> {
+    let cached = CachedTree {
+        version: CACHE_VERSION,
+        key,
+        primitive_amount,
+        bounds: tree.bounds.clone(),
+        build_cost: tree.build_cost,
+        linear_nodes: tree.linear_nodes.clone(),
+        ordering: ordering.to_vec(),
+    };
+    let writer = BufWriter::new(File::create(path).map_err(CacheError::Io)?);
+    bincode::serialize_into(writer, &cached).map_err(CacheError::Encoding)
+}
+
+/// Load the tree cached at `path`, if it was built from `primitives` with `algorithm`
+pub fn load<P: AsRef<Path>>(
+    path: P,
+    algorithm: BVHConstructionAlgorithm,
+    primitives: &[Arc<dyn Primitive + Sync + Send>],
+) -> Result<BVHLinearTree, CacheError> {
+    let reader = BufReader::new(File::open(path).map_err(CacheError::Io)?);
+    let cached: CachedTree = bincode::deserialize_from(reader).map_err(CacheError::Encoding)?;
+    if cached.version != CACHE_VERSION
+        || cached.primitive_amount != primitives.len()
+        || cached.key != cache_key(algorithm, primitives)
+    {
+        return Err(CacheError::KeyMismatch);
+    }
+
+    // Make sure a damaged file can't make traversal index out of bounds
+    let node_amount = cached.linear_nodes.len();
+    let valid = cached.ordering.iter().all(|&i| i < primitives.len())
+        && cached.linear_nodes.iter().enumerate().all(|(i, n)| {
+            if n.primitive_amount > 0 {
+                n.node_content + n.primitive_amount <= cached.ordering.len()
+            } else {
+                // The second child always comes after the first, which makes loops impossible
+                i + 1 < n.node_content && n.node_content < node_amount && n.axis < 3
+            }
+        });
+    if !valid || (node_amount == 0) != primitives.is_empty() {
+        return Err(CacheError::Corrupt);
+    }
+
+    Ok(BVHLinearTree {
+        bounds: cached.bounds,
+        linear_nodes: cached.linear_nodes,
+        primitives: cached
+            .ordering
+            .iter()
+            .map(|&i| Arc::clone(&primitives[i]))
+            .collect(),
+        algorithm,
+        build_cost: cached.build_cost,
+    })
+}
+
+/// Load the tree from the cache at `path` when it matches the primitives and algorithm, and
+/// build it (and update the cache) otherwise. Failing to write the cache is not an error, the
+/// tree is simply rebuilt next time.
+pub fn build_cached<P: AsRef<Path>>(
+    path: P,
+    algorithm: BVHConstructionAlgorithm,
+    primitives: Vec<Arc<dyn Primitive + Sync + Send>>,
+) -> BVHLinearTree {
+    if let Ok(tree) = load(&path, algorithm, &primitives) {
+        return tree;
+    }
+    let key = cache_key(algorithm, &primitives);
+    let amount = primitives.len();
+    let (tree, ordering) = build(algorithm, primitives);
+    if let Err(error) = save(&path, key, amount, &tree, &ordering) {
+        log::warn!("Could not write BVH cache: {:?}", error);
+    }
+    tree
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acceleration::bvh::testing;
+    use crate::geometry::triangle::Triangle;
+    use std::path::PathBuf;
+
+    fn cache_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("thruster-{}-{}.bvh", name, std::process::id()))
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = cache_path("round-trip");
+        let algorithm = BVHConstructionAlgorithm::SBVH(Default::default());
+        let primitives = testing::long_triangles(300, 1);
+        let built = build_cached(&path, algorithm, primitives.clone());
+        let loaded = load(&path, algorithm, &primitives).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.linear_nodes.len(), built.linear_nodes.len());
+        assert_eq!(loaded.primitives.len(), built.primitives.len());
+        for (a, b) in loaded.primitives.iter().zip(&built.primitives) {
+            assert!(Arc::ptr_eq(a, b));
+        }
+        for ray in &testing::rays(500, 2) {
+            assert!(testing::same_hit(
+                loaded.intersect(ray).map(|i| i.geom.t),
+                built.intersect(ray).map(|i| i.geom.t)
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_other_builds() {
+        let path = cache_path("mismatch");
+        let algorithm = BVHConstructionAlgorithm::SBVH(Default::default());
+        let primitives = testing::spheres(100, 3);
+        build_cached(&path, algorithm, primitives.clone());
+
+        let other_algorithm = load(&path, BVHConstructionAlgorithm::SAH, &primitives);
+        let other_primitives = load(&path, algorithm, &testing::spheres(100, 4));
+        let fewer_primitives = load(&path, algorithm, &primitives[1..]);
+        std::fs::remove_file(&path).unwrap();
+        for result in [other_algorithm, other_primitives, fewer_primitives] {
+            match result {
+                Err(CacheError::KeyMismatch) => {}
+                other => panic!("{:?}", other.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn keys_see_inside_the_bounds() {
+        // Both diagonals of the same square have the same bounds
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let vertex = |x, y| Vertex::new(Vec3::new(x, y, 0.0), normal, Vec2::new(0.0, 0.0));
+        let rising = vec![testing::primitive(Arc::new(Triangle::new(
+            vertex(0.0, 0.0),
+            vertex(1.0, 1.0),
+            vertex(0.9, 1.0),
+        )))];
+        let falling = vec![testing::primitive(Arc::new(Triangle::new(
+            vertex(0.0, 1.0),
+            vertex(1.0, 0.0),
+            vertex(0.9, 0.0),
+        )))];
+        let sah = BVHConstructionAlgorithm::SAH;
+        let sbvh = BVHConstructionAlgorithm::SBVH(Default::default());
+        assert_ne!(cache_key(sah, &rising), cache_key(sah, &falling));
+        assert_ne!(cache_key(sbvh, &rising), cache_key(sbvh, &falling));
+    }
+
+    #[test]
+    fn rejects_corrupt_files() {
+        let primitives = testing::spheres(100, 5);
+        let algorithm = BVHConstructionAlgorithm::SAH;
+
+        let garbage = cache_path("garbage");
+        std::fs::write(&garbage, b"not a bvh").unwrap();
+        let result = load(&garbage, algorithm, &primitives);
+        std::fs::remove_file(&garbage).unwrap();
+        match result {
+            Err(CacheError::Encoding(_)) => {}
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+
+        // Well formed, but pointing past the end of the primitives
+        let out_of_range = cache_path("out-of-range");
+        let key = cache_key(algorithm, &primitives);
+        let (tree, mut ordering) = build(algorithm, primitives.clone());
+        ordering[0] = primitives.len();
+        save(&out_of_range, key, primitives.len(), &tree, &ordering).unwrap();
+        let result = load(&out_of_range, algorithm, &primitives);
+        std::fs::remove_file(&out_of_range).unwrap();
+        match result {
+            Err(CacheError::Corrupt) => {}
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+
+        // Well formed, but an inner node pointing back at the root, which would loop forever
+        let back_edge = cache_path("back-edge");
+        let (mut tree, ordering) = build(algorithm, primitives.clone());
+        let inner = tree
+            .linear_nodes
+            .iter()
+            .rposition(|n| n.primitive_amount == 0)
+            .unwrap();
+        tree.linear_nodes[inner].node_content = 0;
+        save(&back_edge, key, primitives.len(), &tree, &ordering).unwrap();
+        let result = load(&back_edge, algorithm, &primitives);
+        std::fs::remove_file(&back_edge).unwrap();
+        match result {
+            Err(CacheError::Corrupt) => {}
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+    }
+}
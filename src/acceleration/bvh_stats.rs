@@ -0,0 +1,235 @@
+// Insight into the quality of a BVH: statistics about its shape, and counters of the work done
+// while traversing it, which the heatmap renderer turns into an image.
+
+use crate::acceleration::bvh::BVHLinearTree;
+use crate::acceleration::queue_systems::FastStack;
+use crate::algebra::prelude::*;
+use crate::algebra::ray::RayPrecompute;
+use crate::core::interaction::Interaction;
+
+use std::fmt;
+use std::sync::Arc;
+
+/// The shape of a BVH
+#[derive(Debug, Clone, Default)]
+pub struct BVHStats {
+    pub nodes: usize,
+    pub leaves: usize,
+    pub primitive_references: usize,
+    /// The amount of leaves at each depth
+    pub depth_histogram: Vec<usize>,
+    /// The amount of leaves holding each amount of primitives
+    pub leaf_size_histogram: Vec<usize>,
+    /// See [BVHLinearTree::sah_cost](../bvh/struct.BVHLinearTree.html#method.sah_cost)
+    pub sah_cost: f64,
+    /// The surface area in which the children of a node overlap, summed over all nodes and
+    /// relative to the root. Roughly how many extra boxes a random ray has to test because of
+    /// overlap.
+    pub overlap: f64,
+}
+
+impl BVHStats {
+    pub fn max_depth(&self) -> usize {
+        self.depth_histogram.len().saturating_sub(1)
+    }
+
+    pub fn average_leaf_size(&self) -> f64 {
+        if self.leaves == 0 {
+            0.0
+        } else {
+            self.primitive_references as f64 / self.leaves as f64
+        }
+    }
+}
+
+impl fmt::Display for BVHStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Nodes: {} ({} leaves)", self.nodes, self.leaves)?;
+        writeln!(
+            f,
+            "Primitive references: {} ({:.2} per leaf)",
+            self.primitive_references,
+            self.average_leaf_size()
+        )?;
+        writeln!(f, "SAH cost: {:.3}", self.sah_cost)?;
+        writeln!(f, "Overlap: {:.3}", self.overlap)?;
+        writeln!(f, "Leaves per depth:")?;
+        for (depth, &amount) in self.depth_histogram.iter().enumerate() {
+            if amount > 0 {
+                writeln!(f, "  {:3}: {}", depth, amount)?;
+            }
+        }
+        writeln!(f, "Leaves per size:")?;
+        for (size, &amount) in self.leaf_size_histogram.iter().enumerate() {
+            if amount > 0 {
+                writeln!(f, "  {:3}: {}", size, amount)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The work done to trace a ray
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TraversalCounters {
+    /// Nodes whose bounds were tested against the ray
+    pub nodes_visited: usize,
+    pub primitives_tested: usize,
+}
+
+impl std::ops::AddAssign for TraversalCounters {
+    fn add_assign(&mut self, other: Self) {
+        self.nodes_visited += other.nodes_visited;
+        self.primitives_tested += other.primitives_tested;
+    }
+}
+
+impl BVHLinearTree {
+    pub fn statistics(&self) -> BVHStats {
+        let mut stats = BVHStats {
+            nodes: self.linear_nodes.len(),
+            sah_cost: self.sah_cost(),
+            ..BVHStats::default()
+        };
+        if self.linear_nodes.is_empty() {
+            return stats;
+        }
+        let root_area = self.bounds.surface_area();
+        let mut stack = vec![(0, 0)];
+        while let Some((node, depth)) = stack.pop() {
+            let n = &self.linear_nodes[node];
+            if n.primitive_amount > 0 {
+                stats.leaves += 1;
+                stats.primitive_references += n.primitive_amount;
+                increment(&mut stats.depth_histogram, depth);
+                increment(&mut stats.leaf_size_histogram, n.primitive_amount);
+            } else {
+                let left = &self.linear_nodes[node + 1].bounding_box;
+                let right = &self.linear_nodes[n.node_content].bounding_box;
+                let overlap = left.intersection(right);
+                if !overlap.is_empty() && root_area > 0.0 {
+                    stats.overlap += overlap.surface_area() / root_area;
+                }
+                stack.push((node + 1, depth + 1));
+                stack.push((n.node_content, depth + 1));
+            }
+        }
+        stats
+    }
+
+    /// Like [intersect](../bvh/struct.BVHLinearTree.html#method.intersect), but also counts the
+    /// work it took
+    pub fn intersect_counted(&self, ray: &Ray) -> (Option<Interaction<'_>>, TraversalCounters) {
+        let mut counters = TraversalCounters::default();
+        if self.linear_nodes.is_empty() {
+            return (None, counters);
+        }
+        let pre = RayPrecompute::new(ray);
+        let mut current_task = 0;
+        let mut queue = FastStack::new();
+        let mut closest: Option<Interaction> = None;
+        let mut closest_t = float::INFINITY;
+        loop {
+            let n = &self.linear_nodes[current_task];
+            counters.nodes_visited += 1;
+            if n.bounding_box.intersect_precomputed(ray, &pre, closest_t) {
+                if n.primitive_amount > 0 {
+                    for i in 0..n.primitive_amount {
+                        let prim = &self.primitives[i + n.node_content];
+                        counters.primitives_tested += 1;
+                        if let Some(geom) = prim.intersect(ray) {
+                            if geom.t < closest_t {
+                                closest_t = geom.t;
+                                closest = Some(Interaction {
+                                    geom,
+                                    primitive: Arc::clone(prim),
+                                });
+                            }
+                        }
+                    }
+                    match queue.pop() {
+                        None => return (closest, counters),
+                        Some(task) => current_task = task,
+                    };
+                } else if pre.dir_is_neg[n.axis] {
+                    queue.push(current_task + 1);
+                    current_task = n.node_content;
+                } else {
+                    queue.push(n.node_content);
+                    current_task += 1;
+                }
+            } else {
+                match queue.pop() {
+                    None => return (closest, counters),
+                    Some(task) => current_task = task,
+                };
+            }
+        }
+    }
+}
+
+fn increment(histogram: &mut Vec<usize>, index: usize) {
+    if histogram.len() <= index {
+        histogram.resize(index + 1, 0);
+    }
+    histogram[index] += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acceleration::bvh::{testing, BVHConstructionAlgorithm, BVHLinearNode};
+    use crate::geometry::sphere::Sphere;
+
+    #[test]
+    fn statistics_of_a_small_tree() {
+        let primitives: Vec<_> = [0.0, 1.0, 10.0, 10.5, 13.0]
+            .iter()
+            .map(|&x| testing::primitive(Arc::new(Sphere::new(Point3::new(x, 0.0, 0.0), 1.0))))
+            .collect();
+        let bounds = |range: std::ops::Range<usize>| {
+            primitives[range]
+                .iter()
+                .fold(BoundingBox::EMPTY, |acc, p| acc.merge(&p.bounds()))
+        };
+        let node = |bounding_box, primitive_amount, node_content| BVHLinearNode {
+            bounding_box,
+            primitive_amount,
+            node_content,
+            axis: 0,
+        };
+        // A leaf with two spheres next to a node with a leaf of one sphere and one of two, which
+        // overlap in [9.5, 11] x [-1, 1] x [-1, 1]
+        let tree = BVHLinearTree {
+            linear_nodes: vec![
+                node(bounds(0..5), 0, 2),
+                node(bounds(0..2), 2, 0),
+                node(bounds(2..5), 0, 4),
+                node(bounds(2..3), 1, 2),
+                node(bounds(3..5), 2, 3),
+            ],
+            bounds: bounds(0..5),
+            primitives: primitives.clone(),
+            algorithm: BVHConstructionAlgorithm::SAH,
+            build_cost: 0.0,
+        };
+
+        let stats = tree.statistics();
+        assert_eq!(stats.nodes, 5);
+        assert_eq!(stats.leaves, 3);
+        assert_eq!(stats.primitive_references, 5);
+        assert_eq!(stats.depth_histogram, vec![0, 1, 2]);
+        assert_eq!(stats.leaf_size_histogram, vec![0, 1, 2]);
+        assert_eq!(stats.max_depth(), 2);
+        assert!((stats.average_leaf_size() - 5.0 / 3.0).abs() < 1e-9);
+        assert!((stats.overlap - 20.0 / 128.0).abs() < 1e-6);
+
+        // The first leaf holds the nearest hit, so the other side is skipped after testing its
+        // bounds
+        let ray = Ray::new(Point3::new(-10.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let (hit, counters) = tree.intersect_counted(&ray);
+        assert!(testing::same_hit(hit.map(|i| i.geom.t), Some(9.0)));
+        assert_eq!(counters.nodes_visited, 3);
+        assert_eq!(counters.primitives_tested, 2);
+    }
+}
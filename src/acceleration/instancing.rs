@@ -0,0 +1,505 @@
+// Two-level acceleration.
+//
+// Geometry which is used many times (like a mesh) gets its own bottom level BVH, which is built
+// once. Each placement of that geometry in the world is an `Instance`, which only holds a
+// reference to the bottom level BVH and a transform. The top level BVH is then built over the
+// instances, and since there are usually few of them, it's cheap to rebuild when they move.
+
+use crate::acceleration::bvh::{
+    BVHAccel, BVHConstructionAlgorithm, BVHLinearNode, BVHLinearTree, BVHPrimitiveInfo,
+};
+use crate::acceleration::bvh_stats::TraversalCounters;
+use crate::acceleration::queue_systems::FastStack;
+use crate::algebra::prelude::*;
+use crate::algebra::ray::RayPrecompute;
+use crate::core::interaction::{HitFilter, Interaction};
+use crate::geometry::geometry_information::GeometryInformation;
+
+use std::sync::Arc;
+
+/// A bottom level BVH placed in the world by a transform
+#[derive(Debug, Clone)]
+pub struct Instance {
+    pub blas: Arc<BVHLinearTree>,
+    pub object_to_world: Transform,
+    pub world_to_object: Transform,
+}
+
+impl Instance {
+    pub fn new(blas: Arc<BVHLinearTree>, object_to_world: Transform) -> Self {
+        Self {
+            blas,
+            world_to_object: object_to_world.clone().inverse(),
+            object_to_world,
+        }
+    }
+
+    /// Move the instance. The top level BVH has to be rebuilt afterwards
+    pub fn set_transform(&mut self, object_to_world: Transform) {
+        self.world_to_object = object_to_world.clone().inverse();
+        self.object_to_world = object_to_world;
+    }
+
+    /// The bounds of the instance in world space
+    pub fn bounds(&self) -> BoundingBox {
+        if self.blas.bounds.is_empty() {
+            BoundingBox::EMPTY
+        } else {
+            self.blas.bounds.clone().apply_t(&self.object_to_world)
+        }
+    }
+
+    /// Bring a world space ray into the instance's object space
+    fn local_ray(&self, ray: &Ray) -> Ray {
+        // Shapes expect normalized directions, so distances are fixed up after intersecting, and
+        // the range is scaled to match
+        let direction = ray.direction.apply_t(&self.world_to_object);
+        let scale = direction.length();
+        Ray {
+            origin: ray.origin.apply_t(&self.world_to_object),
+            direction: direction.normalized(),
+            min_t: ray.min_t * scale,
+            max_t: ray.max_t * scale,
+            ..*ray
+        }
+    }
+
+    /// Bring a hit on the bottom level BVH back into world space
+    fn world_interaction<'a>(&self, ray: &Ray, interaction: Interaction<'a>) -> Interaction<'a> {
+        let origin = interaction.geom.origin.apply_t(&self.object_to_world);
+        let geom = GeometryInformation {
+            t: origin.distance(&ray.origin),
+            normal: interaction
+                .geom
+                .normal
+                .apply_t(&self.object_to_world)
+                .normalized(),
+            origin,
+            uv: interaction.geom.uv,
+            dpdu: interaction.geom.dpdu.apply_t(&self.object_to_world),
+            dpdv: interaction.geom.dpdv.apply_t(&self.object_to_world),
+        };
+        Interaction {
+            geom,
+            ..interaction
+        }
+    }
+
+    pub fn intersect(&self, ray: &Ray) -> Option<Interaction<'_>> {
+        self.blas
+            .intersect(&self.local_ray(ray))
+            .map(|interaction| self.world_interaction(ray, interaction))
+    }
+
+    /// Like `intersect`, but also counts the work done in the bottom level BVH
+    pub fn intersect_counted(&self, ray: &Ray) -> (Option<Interaction<'_>>, TraversalCounters) {
+        let (hit, counters) = self.blas.intersect_counted(&self.local_ray(ray));
+        (
+            hit.map(|interaction| self.world_interaction(ray, interaction)),
+            counters,
+        )
+    }
+
+    /// The nearest hit accepted by `filter`, which is given the hit in world space
+    pub fn intersect_filtered(&self, ray: &Ray, filter: &HitFilter) -> Option<Interaction<'_>> {
+        let accept =
+            |interaction: &Interaction| filter(&self.world_interaction(ray, interaction.clone()));
+        self.blas
+            .intersect_filtered(&self.local_ray(ray), &accept)
+            .map(|interaction| self.world_interaction(ray, interaction))
+    }
+
+    /// Any hit accepted by `filter`, which is given the hit in world space
+    pub fn intersect_any(&self, ray: &Ray, filter: &HitFilter) -> Option<Interaction<'_>> {
+        let accept =
+            |interaction: &Interaction| filter(&self.world_interaction(ray, interaction.clone()));
+        self.blas
+            .intersect_any(&self.local_ray(ray), &accept)
+            .map(|interaction| self.world_interaction(ray, interaction))
+    }
+
+    pub fn does_intersect(&self, ray: &Ray) -> bool {
+        self.blas.does_intersect(&self.local_ray(ray))
+    }
+}
+
+/// A BVH over instances
+#[derive(Debug)]
+pub struct TopLevelBVH {
+    pub algorithm: BVHConstructionAlgorithm,
+    pub bounds: BoundingBox,
+    pub linear_nodes: Vec<BVHLinearNode>,
+    pub instances: Vec<Instance>,
+}
+
+impl TopLevelBVH {
+    pub fn new(algorithm: BVHConstructionAlgorithm, instances: Vec<Instance>) -> Self {
+        let mut tlas = Self {
+            algorithm,
+            bounds: BoundingBox::EMPTY,
+            linear_nodes: Vec::new(),
+            instances,
+        };
+        tlas.rebuild();
+        tlas
+    }
+
+    /// Rebuild the tree over the instances, for example after they've been moved. The bottom
+    /// level BVHs are left untouched.
+    pub fn rebuild(&mut self) {
+        if self.instances.is_empty() {
+            self.bounds = BoundingBox::EMPTY;
+            self.linear_nodes = Vec::new();
+            return;
+        }
+
+        let primitive_info = self
+            .instances
+            .iter()
+            .enumerate()
+            .map(|(index, instance)| {
+                let bounding_box = instance.bounds();
+                BVHPrimitiveInfo {
+                    centre: bounding_box.centre(),
+                    bounding_box,
+                    index,
+                }
+            })
+            .collect();
+
+        // Instances can't be clipped, so spatial splits don't apply to them
+        let algorithm = match self.algorithm {
+            BVHConstructionAlgorithm::SBVH(_) => BVHConstructionAlgorithm::SAH,
+            algorithm => algorithm,
+        };
+        let accel = BVHAccel::new(algorithm, Vec::new());
+        let (total_nodes, node, ordering) = accel.build_hierarchy(primitive_info);
+        self.instances = ordering
+            .into_iter()
+            .map(|index| self.instances[index].clone())
+            .collect();
+
+        let tree = accel.flatten(Box::new(node), total_nodes);
+        self.bounds = tree.bounds;
+        self.linear_nodes = tree.linear_nodes;
+    }
+
+    pub fn does_intersect(&self, ray: &Ray) -> bool {
+        if self.linear_nodes.is_empty() {
+            return false;
+        }
+        let pre = RayPrecompute::new(ray);
+        let mut current_task = 0;
+        let mut queue = FastStack::new();
+        loop {
+            let n = &self.linear_nodes[current_task];
+            if n.bounding_box
+                .intersect_precomputed(ray, &pre, float::INFINITY)
+            {
+                if n.primitive_amount > 0 {
+                    for i in 0..n.primitive_amount {
+                        if self.instances[i + n.node_content].does_intersect(ray) {
+                            return true;
+                        }
+                    }
+                    match queue.pop() {
+                        None => return false,
+                        Some(task) => current_task = task,
+                    };
+                } else if pre.dir_is_neg[n.axis] {
+                    queue.push(current_task + 1);
+                    current_task = n.node_content;
+                } else {
+                    queue.push(n.node_content);
+                    current_task += 1;
+                }
+            } else {
+                match queue.pop() {
+                    None => return false,
+                    Some(task) => current_task = task,
+                };
+            }
+        }
+    }
+
+    pub fn intersect(&self, ray: &Ray) -> Option<Interaction<'_>> {
+        let counters = &mut TraversalCounters::default();
+        self.intersect_with(ray, |instance, _| instance.intersect(ray), false, counters)
+    }
+
+    /// Like `intersect`, but also counts the work done. Both the nodes of the top level BVH and
+    /// those of the bottom level BVHs count as visited nodes.
+    pub fn intersect_counted(&self, ray: &Ray) -> (Option<Interaction<'_>>, TraversalCounters) {
+        let mut counters = TraversalCounters::default();
+        let hit = self.intersect_with(
+            ray,
+            |instance, counters| {
+                let (hit, instance_counters) = instance.intersect_counted(ray);
+                *counters += instance_counters;
+                hit
+            },
+            false,
+            &mut counters,
+        );
+        (hit, counters)
+    }
+
+    /// The nearest hit accepted by `filter`
+    pub fn intersect_filtered(&self, ray: &Ray, filter: &HitFilter) -> Option<Interaction<'_>> {
+        let counters = &mut TraversalCounters::default();
+        self.intersect_with(
+            ray,
+            |instance, _| instance.intersect_filtered(ray, filter),
+            false,
+            counters,
+        )
+    }
+
+    /// Any hit accepted by `filter`, stopping at the first one
+    pub fn intersect_any(&self, ray: &Ray, filter: &HitFilter) -> Option<Interaction<'_>> {
+        let counters = &mut TraversalCounters::default();
+        self.intersect_with(
+            ray,
+            |instance, _| instance.intersect_any(ray, filter),
+            true,
+            counters,
+        )
+    }
+
+    /// Traverse the instances, using `instance_hit` to intersect each of them
+    fn intersect_with<'a, F>(
+        &'a self,
+        ray: &Ray,
+        instance_hit: F,
+        any_hit: bool,
+        counters: &mut TraversalCounters,
+    ) -> Option<Interaction<'a>>
+    where
+        F: Fn(&'a Instance, &mut TraversalCounters) -> Option<Interaction<'a>>,
+    {
+        if self.linear_nodes.is_empty() {
+            return None;
+        }
+        let pre = RayPrecompute::new(ray);
+        let mut current_task = 0;
+        let mut queue = FastStack::new();
+        let mut closest: Option<Interaction> = None;
+        let mut closest_t = float::INFINITY;
+        loop {
+            let n = &self.linear_nodes[current_task];
+            counters.nodes_visited += 1;
+            if n.bounding_box.intersect_precomputed(ray, &pre, closest_t) {
+                if n.primitive_amount > 0 {
+                    for i in 0..n.primitive_amount {
+                        let instance = &self.instances[i + n.node_content];
+                        if let Some(interaction) = instance_hit(instance, counters) {
+                            if any_hit {
+                                return Some(interaction);
+                            }
+                            if interaction.geom.t < closest_t {
+                                closest_t = interaction.geom.t;
+                                closest = Some(interaction);
+                            }
+                        }
+                    }
+                    match queue.pop() {
+                        None => return closest,
+                        Some(task) => current_task = task,
+                    };
+                } else if pre.dir_is_neg[n.axis] {
+                    queue.push(current_task + 1);
+                    current_task = n.node_content;
+                } else {
+                    queue.push(n.node_content);
+                    current_task += 1;
+                }
+            } else {
+                match queue.pop() {
+                    None => return closest,
+                    Some(task) => current_task = task,
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acceleration::bvh::testing;
+    use crate::core::primitive::Primitive;
+    use crate::geometry::triangle::Triangle;
+    use rand::prelude::*;
+
+    type Corners = [Vec3; 3];
+
+    /// Small triangles in a cube of 30 units, to be placed by the instances
+    fn local_triangles(n: usize, seed: u64) -> Vec<Corners> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut point = |size: Float| {
+            Vec3::new(
+                rng.gen::<Float>() * size,
+                rng.gen::<Float>() * size,
+                rng.gen::<Float>() * size,
+            )
+        };
+        (0..n)
+            .map(|_| {
+                let a = point(30.0);
+                [a, a + point(5.0), a + point(5.0)]
+            })
+            .collect()
+    }
+
+    fn triangles(corners: &[Corners]) -> Vec<Arc<dyn Primitive + Sync + Send>> {
+        corners
+            .iter()
+            .map(|[a, b, c]| {
+                let normal = comb::cross(&(*b - *a), &(*c - *a)).normalized();
+                let vertex = |origin| Vertex::new(origin, normal, Vec2::new(0.0, 0.0));
+                testing::primitive(Arc::new(Triangle::new(vertex(*a), vertex(*b), vertex(*c))))
+            })
+            .collect()
+    }
+
+    /// The same geometry as the instances, with every triangle moved into world space
+    fn flattened(corners: &[Corners], tlas: &TopLevelBVH) -> Vec<Arc<dyn Primitive + Sync + Send>> {
+        let world: Vec<Corners> = tlas
+            .instances
+            .iter()
+            .flat_map(|instance| {
+                let t = &instance.object_to_world;
+                corners
+                    .iter()
+                    .map(move |c| [0, 1, 2].map(|i| Vec3::from(Point3::from(c[i]).apply_t(t))))
+            })
+            .collect();
+        triangles(&world)
+    }
+
+    /// Instances with non-uniform scales and rotations
+    fn instances(blas: &Arc<BVHLinearTree>) -> Vec<Instance> {
+        vec![
+            Transform::translation(&Vec3::new(10.0, 20.0, 10.0))
+                * Transform::rotate_y(0.5)
+                * Transform::scaling(1.0, 2.0, 0.5),
+            Transform::translation(&Vec3::new(60.0, 10.0, 40.0))
+                * Transform::rotate_x(-0.8)
+                * Transform::scaling(2.0, 0.5, 1.0),
+            Transform::translation(&Vec3::new(30.0, 60.0, 20.0))
+                * Transform::rotate_z(1.0)
+                * Transform::scaling(0.5, 0.5, 3.0),
+        ]
+        .into_iter()
+        .map(|t| Instance::new(Arc::clone(blas), t))
+        .collect()
+    }
+
+    /// Check every query of `tlas` against intersecting all of `world`
+    fn check_against_brute_force(tlas: &TopLevelBVH, world: &[Arc<dyn Primitive + Sync + Send>]) {
+        let left = |i: &Interaction| i.geom.origin.x < 50.0;
+        let mut hits = 0;
+        for ray in &testing::rays(2000, 21) {
+            // Also with a range, which has to be scaled into object space
+            for &(min_t, max_t) in &[(0.0, float::INFINITY), (55.0, 90.0)] {
+                let ray = &Ray {
+                    min_t,
+                    max_t,
+                    ..*ray
+                };
+                let expected = testing::brute_force(world, ray);
+                let nearest = tlas.intersect(ray);
+                assert!(testing::same_hit(
+                    nearest.as_ref().map(|i| i.geom.t),
+                    expected
+                ));
+                if let Some(hit) = nearest {
+                    hits += 1;
+                    assert!(
+                        (hit.geom.origin - (ray.origin + ray.direction * hit.geom.t)).length()
+                            < 1e-3
+                    );
+                }
+                assert_eq!(tlas.does_intersect(ray), expected.is_some());
+
+                let expected_left = world
+                    .iter()
+                    .filter_map(|p| p.intersect(ray))
+                    .filter(|geom| geom.origin.x < 50.0)
+                    .map(|geom| geom.t)
+                    .fold(None, |nearest: Option<Float>, t| {
+                        Some(nearest.map_or(t, |nearest| nearest.min(t)))
+                    });
+                let nearest_left = tlas.intersect_filtered(ray, &left);
+                assert!(nearest_left.as_ref().is_none_or(&left));
+                assert!(testing::same_hit(
+                    nearest_left.map(|i| i.geom.t),
+                    expected_left
+                ));
+                let any = tlas.intersect_any(ray, &left);
+                assert_eq!(any.is_some(), expected_left.is_some());
+                assert!(any.as_ref().is_none_or(&left));
+            }
+        }
+        assert!(hits > 100);
+    }
+
+    #[test]
+    fn instances_match_flattened_geometry() {
+        let corners = local_triangles(200, 20);
+        let blas = Arc::new(BVHLinearTree::build(
+            BVHConstructionAlgorithm::SAH,
+            triangles(&corners),
+        ));
+        let tlas = TopLevelBVH::new(BVHConstructionAlgorithm::SAH, instances(&blas));
+        check_against_brute_force(&tlas, &flattened(&corners, &tlas));
+    }
+
+    #[test]
+    fn moved_instances_match_after_rebuild() {
+        let corners = local_triangles(200, 22);
+        let blas = Arc::new(BVHLinearTree::build(
+            BVHConstructionAlgorithm::SAH,
+            triangles(&corners),
+        ));
+        let mut tlas = TopLevelBVH::new(BVHConstructionAlgorithm::SAH, instances(&blas));
+        tlas.instances[0].set_transform(
+            Transform::translation(&Vec3::new(50.0, 50.0, 60.0))
+                * Transform::rotate(&Vec3::new(1.0, 1.0, 0.0).normalized(), 2.0)
+                * Transform::scaling(1.5, 0.5, 1.0),
+        );
+        tlas.rebuild();
+        check_against_brute_force(&tlas, &flattened(&corners, &tlas));
+    }
+
+    #[test]
+    fn counts_both_levels() {
+        let corners = local_triangles(200, 23);
+        let blas = Arc::new(BVHLinearTree::build(
+            BVHConstructionAlgorithm::SAH,
+            triangles(&corners),
+        ));
+        // A single instance makes the top level a single leaf
+        let instance = Instance::new(Arc::clone(&blas), Transform::identity());
+        let tlas = TopLevelBVH::new(BVHConstructionAlgorithm::SAH, vec![instance]);
+        let mut tested = 0;
+        for ray in &testing::rays(500, 24) {
+            let (hit, counters) = tlas.intersect_counted(ray);
+            assert!(testing::same_hit(
+                hit.map(|i| i.geom.t),
+                tlas.intersect(ray).map(|i| i.geom.t)
+            ));
+            let root = &tlas.linear_nodes[0].bounding_box;
+            if root.intersect_precomputed(ray, &RayPrecompute::new(ray), float::INFINITY) {
+                let (_, blas_counters) = blas.intersect_counted(ray);
+                assert_eq!(counters.nodes_visited, 1 + blas_counters.nodes_visited);
+                assert_eq!(counters.primitives_tested, blas_counters.primitives_tested);
+                tested += blas_counters.primitives_tested;
+            } else {
+                assert_eq!(counters.nodes_visited, 1);
+                assert_eq!(counters.primitives_tested, 0);
+            }
+        }
+        assert!(tested > 0);
+    }
+}
@@ -0,0 +1,436 @@
+// SAH kd-tree.
+//
+// Like the BVH, heavily based on the PBR book. Instead of grouping primitives, a kd-tree splits
+// space with axis aligned planes, so primitives which straddle a plane are referenced from both
+// sides. Nodes never overlap, which makes front to back traversal exact and lets it stop as soon
+// as a hit is found in front of the next node.
+
+use crate::algebra::prelude::*;
+use crate::algebra::ray::RayPrecompute;
+use crate::core::interaction::{HitFilter, Interaction};
+use crate::core::primitive::Primitive;
+
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+/// The deepest a tree can get, since traversal keeps the nodes it still has to visit in a fixed
+/// size stack with at most one entry per level
+pub const MAX_DEPTH: usize = 64;
+
+/// Costs used to decide where, and whether, to split
+#[derive(Clone, Copy, Debug)]
+pub struct KdTreeConfig {
+    /// Cost of intersecting a primitive, relative to traversing a node
+    pub intersect_cost: f64,
+    pub traversal_cost: f64,
+    /// How much cheaper a split is considered when one of its sides is empty, from 0 to 1
+    pub empty_bonus: f64,
+    /// Nodes with this many primitives or less become leaves
+    pub max_primitives: usize,
+    /// Defaults to `8 + 1.3 log2(N)` when not given, and never goes past
+    /// [MAX_DEPTH](constant.MAX_DEPTH.html)
+    pub max_depth: Option<usize>,
+}
+
+impl Default for KdTreeConfig {
+    fn default() -> Self {
+        Self {
+            intersect_cost: 80.0,
+            traversal_cost: 1.0,
+            empty_bonus: 0.5,
+            max_primitives: 1,
+            max_depth: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum KdNode {
+    /// The child below the split is always the next node
+    Interior {
+        axis: usize,
+        split: Float,
+        above_child: usize,
+    },
+    /// `amount` primitives, starting at `first` in the primitive indices
+    Leaf { first: usize, amount: usize },
+}
+
+/// Where a primitive's bounds start or end along an axis
+#[derive(Clone, Copy)]
+struct BoundEdge {
+    t: Float,
+    primitive: usize,
+    starting: bool,
+}
+
+#[derive(Debug)]
+pub struct KdTree {
+    pub config: KdTreeConfig,
+    pub bounds: BoundingBox,
+    pub nodes: Vec<KdNode>,
+    /// Indices into `primitives`, referenced by the leaves
+    pub primitive_indices: Vec<usize>,
+    pub primitives: Vec<Arc<dyn Primitive + Sync + Send>>,
+    pub max_depth: usize,
+}
+
+impl KdTree {
+    pub fn new(config: KdTreeConfig, primitives: Vec<Arc<dyn Primitive + Sync + Send>>) -> Self {
+        let primitive_bounds: Vec<BoundingBox> = primitives.iter().map(|p| p.bounds()).collect();
+        let bounds = primitive_bounds
+            .iter()
+            .fold(BoundingBox::EMPTY, |acc, b| acc.merge(b));
+        let max_depth = config
+            .max_depth
+            .unwrap_or_else(|| {
+                (8.0 + 1.3 * (primitives.len().max(1) as f64).log2()).round() as usize
+            })
+            .min(MAX_DEPTH);
+        let mut tree = Self {
+            config,
+            bounds: bounds.clone(),
+            nodes: Vec::new(),
+            primitive_indices: Vec::new(),
+            primitives,
+            max_depth: 0,
+        };
+        if !tree.primitives.is_empty() {
+            let all = (0..tree.primitives.len()).collect();
+            tree.build(&primitive_bounds, &bounds, all, 0, max_depth, 0);
+        }
+        tree
+    }
+
+    fn push_leaf(&mut self, primitives: Vec<usize>, depth: usize) {
+        self.max_depth = self.max_depth.max(depth);
+        self.nodes.push(KdNode::Leaf {
+            first: self.primitive_indices.len(),
+            amount: primitives.len(),
+        });
+        self.primitive_indices.extend(primitives);
+    }
+
+    fn build(
+        &mut self,
+        primitive_bounds: &[BoundingBox],
+        node_bounds: &BoundingBox,
+        primitives: Vec<usize>,
+        depth: usize,
+        max_depth: usize,
+        bad_refines: usize,
+    ) {
+        if primitives.len() <= self.config.max_primitives || depth == max_depth {
+            self.push_leaf(primitives, depth);
+            return;
+        }
+
+        // Find the cheapest split, starting with the axis in which the node is largest
+        let old_cost = self.config.intersect_cost * primitives.len() as f64;
+        let total_area = node_bounds.surface_area();
+        let d = node_bounds.diagonal();
+        let mut best: Option<(usize, usize, f64)> = None;
+        let mut edges: Vec<BoundEdge> = Vec::with_capacity(2 * primitives.len());
+        let mut axis = node_bounds.max_extent();
+        for _ in 0..3 {
+            edges.clear();
+            for &p in &primitives {
+                let b = &primitive_bounds[p];
+                edges.push(BoundEdge {
+                    t: b.min[axis],
+                    primitive: p,
+                    starting: true,
+                });
+                edges.push(BoundEdge {
+                    t: b.max[axis],
+                    primitive: p,
+                    starting: false,
+                });
+            }
+            edges.sort_by(|a, b| match a.t.partial_cmp(&b.t) {
+                Some(Ordering::Equal) | None => b.starting.cmp(&a.starting),
+                Some(ordering) => ordering,
+            });
+
+            let (other_0, other_1) = ((axis + 1) % 3, (axis + 2) % 3);
+            let mut below = 0;
+            let mut above = primitives.len();
+            for (i, edge) in edges.iter().enumerate() {
+                if !edge.starting {
+                    above -= 1;
+                }
+                if edge.t > node_bounds.min[axis] && edge.t < node_bounds.max[axis] {
+                    let (d_0, d_1) = (float::to_f64(d[other_0]), float::to_f64(d[other_1]));
+                    let below_extent = float::to_f64(edge.t - node_bounds.min[axis]);
+                    let above_extent = float::to_f64(node_bounds.max[axis] - edge.t);
+                    let below_area = 2.0 * (d_0 * d_1 + below_extent * (d_0 + d_1));
+                    let above_area = 2.0 * (d_0 * d_1 + above_extent * (d_0 + d_1));
+                    let bonus = if below == 0 || above == 0 {
+                        self.config.empty_bonus
+                    } else {
+                        0.0
+                    };
+                    let cost = self.config.traversal_cost
+                        + self.config.intersect_cost
+                            * (1.0 - bonus)
+                            * (below_area * below as f64 + above_area * above as f64)
+                            / total_area;
+                    if best.is_none_or(|(_, _, best_cost)| cost < best_cost) {
+                        best = Some((axis, i, cost));
+                    }
+                }
+                if edge.starting {
+                    below += 1;
+                }
+            }
+            if best.is_some() {
+                break;
+            }
+            // Nothing to split along this axis, try the next one
+            axis = (axis + 1) % 3;
+        }
+
+        let (axis, offset, cost) = match best {
+            Some(best) => best,
+            None => {
+                self.push_leaf(primitives, depth);
+                return;
+            }
+        };
+        let bad_refines = if cost > old_cost {
+            bad_refines + 1
+        } else {
+            bad_refines
+        };
+        if (cost > 4.0 * old_cost && primitives.len() < 16) || bad_refines == 3 {
+            self.push_leaf(primitives, depth);
+            return;
+        }
+
+        // `edges` still holds the edges along the axis of the best split
+        let split = edges[offset].t;
+        let below: Vec<usize> = edges[..offset]
+            .iter()
+            .filter(|edge| edge.starting)
+            .map(|edge| edge.primitive)
+            .collect();
+        let above: Vec<usize> = edges[offset + 1..]
+            .iter()
+            .filter(|edge| !edge.starting)
+            .map(|edge| edge.primitive)
+            .collect();
+
+        let mut below_bounds = node_bounds.clone();
+        below_bounds.max[axis] = split;
+        let mut above_bounds = node_bounds.clone();
+        above_bounds.min[axis] = split;
+
+        let index = self.nodes.len();
+        self.nodes.push(KdNode::Leaf {
+            first: 0,
+            amount: 0,
+        });
+        self.build(
+            primitive_bounds,
+            &below_bounds,
+            below,
+            depth + 1,
+            max_depth,
+            bad_refines,
+        );
+        let above_child = self.nodes.len();
+        self.build(
+            primitive_bounds,
+            &above_bounds,
+            above,
+            depth + 1,
+            max_depth,
+            bad_refines,
+        );
+        self.nodes[index] = KdNode::Interior {
+            axis,
+            split,
+            above_child,
+        };
+    }
+
+    /// Walk the nodes pierced by the ray front to back, calling `visit` with the primitives of
+    /// every leaf. `visit` returns the distance up to which hits have been found, and traversal
+    /// stops once the next node lies beyond it.
+    fn traverse<F>(&self, ray: &Ray, mut visit: F)
+    where
+        F: FnMut(&[usize]) -> Float,
+    {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let pre = RayPrecompute::new(ray);
+        let (mut t_min, mut t_max) = match self.bounds.intersect_range(ray, &pre, float::INFINITY) {
+            Some(range) => range,
+            None => return,
+        };
+        let mut closest_t = float::INFINITY;
+        let mut stack = [(0, 0.0, 0.0); MAX_DEPTH];
+        let mut end = 0;
+        let mut node = 0;
+        loop {
+            if closest_t < t_min {
+                return;
+            }
+            match self.nodes[node] {
+                KdNode::Interior {
+                    axis,
+                    split,
+                    above_child,
+                } => {
+                    let t_plane = (split - ray.origin[axis]) * pre.inv_dir[axis];
+                    let below_first = ray.origin[axis] < split
+                        || (ray.origin[axis] == split && ray.direction[axis] <= 0.0);
+                    let (first, second) = if below_first {
+                        (node + 1, above_child)
+                    } else {
+                        (above_child, node + 1)
+                    };
+                    if t_plane > t_max || t_plane <= 0.0 {
+                        node = first;
+                    } else if t_plane < t_min {
+                        node = second;
+                    } else {
+                        stack[end] = (second, t_plane, t_max);
+                        end += 1;
+                        node = first;
+                        t_max = t_plane;
+                    }
+                }
+                KdNode::Leaf { first, amount } => {
+                    closest_t = visit(&self.primitive_indices[first..first + amount]);
+                    if end == 0 {
+                        return;
+                    }
+                    end -= 1;
+                    let (next, next_min, next_max) = stack[end];
+                    node = next;
+                    t_min = next_min;
+                    t_max = next_max;
+                }
+            }
+        }
+    }
+
+    pub fn intersect(&self, ray: &Ray) -> Option<Interaction<'_>> {
+        self.intersect_with(ray, |_| true, false)
+    }
+
+    /// The nearest hit accepted by `filter`
+    pub fn intersect_filtered(&self, ray: &Ray, filter: &HitFilter) -> Option<Interaction<'_>> {
+        self.intersect_with(ray, filter, false)
+    }
+
+    /// Any hit accepted by `filter`, stopping at the first one
+    pub fn intersect_any(&self, ray: &Ray, filter: &HitFilter) -> Option<Interaction<'_>> {
+        self.intersect_with(ray, filter, true)
+    }
+
+    fn intersect_with<F>(&self, ray: &Ray, accept: F, any_hit: bool) -> Option<Interaction<'_>>
+    where
+        F: Fn(&Interaction) -> bool,
+    {
+        let mut closest: Option<Interaction> = None;
+        let mut closest_t = float::INFINITY;
+        self.traverse(ray, |indices| {
+            for &i in indices {
+                let prim = &self.primitives[i];
+                if let Some(geom) = prim.intersect(ray) {
+                    if geom.t < closest_t {
+                        let interaction = Interaction {
+                            geom,
+                            primitive: Arc::clone(prim),
+                        };
+                        if accept(&interaction) {
+                            closest_t = interaction.geom.t;
+                            closest = Some(interaction);
+                            if any_hit {
+                                return float::NEG_INFINITY;
+                            }
+                        }
+                    }
+                }
+            }
+            closest_t
+        });
+        closest
+    }
+
+    pub fn does_intersect(&self, ray: &Ray) -> bool {
+        let mut hit = false;
+        self.traverse(ray, |indices| {
+            if !hit {
+                hit = indices
+                    .iter()
+                    .any(|&i| self.primitives[i].does_intersect(ray));
+            }
+            // Any hit ends the traversal
+            if hit {
+                float::NEG_INFINITY
+            } else {
+                float::INFINITY
+            }
+        });
+        hit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acceleration::bvh::{testing, BVHConstructionAlgorithm, BVHLinearTree};
+    use crate::acceleration::wide_bvh::{BVHWidth, WideBVH};
+    use crate::geometry::sphere::Sphere;
+
+    #[test]
+    fn hits_like_the_bvhs() {
+        let primitives = testing::spheres(1000, 11);
+        let kd_tree = KdTree::new(KdTreeConfig::default(), primitives.clone());
+        let bvh = BVHLinearTree::build(BVHConstructionAlgorithm::SAH, primitives);
+        let wide = WideBVH::from_tree(&bvh, BVHWidth::BVH4);
+        for ray in &testing::rays(2000, 12) {
+            let expected = bvh.intersect(ray).map(|i| i.geom.t);
+            assert!(testing::same_hit(
+                kd_tree.intersect(ray).map(|i| i.geom.t),
+                expected
+            ));
+            assert!(testing::same_hit(
+                wide.intersect(ray).map(|i| i.geom.t),
+                expected
+            ));
+            let occluded = bvh.does_intersect(ray);
+            assert_eq!(kd_tree.does_intersect(ray), occluded);
+            assert_eq!(wide.does_intersect(ray), occluded);
+        }
+    }
+
+    #[test]
+    fn depth_is_clamped() {
+        let config = KdTreeConfig {
+            max_depth: Some(1000),
+            ..Default::default()
+        };
+        // Each twice as far out and twice as large as the last, so every split cuts off one sphere
+        let primitives: Vec<_> = (0..100)
+            .map(|i| {
+                let x = (2.0 as Float).powi(i);
+                testing::primitive(Arc::new(Sphere::new(Point3::new(x, 0.0, 0.0), x / 4.0)))
+            })
+            .collect();
+        let tree = KdTree::new(config, primitives.clone());
+        assert_eq!(tree.max_depth, MAX_DEPTH);
+
+        // Traversal still works all the way down
+        let ray = Ray::new(Point3::new(-1.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let expected = testing::brute_force(&primitives, &ray);
+        assert!(testing::same_hit(
+            tree.intersect(&ray).map(|i| i.geom.t),
+            expected
+        ));
+    }
+}
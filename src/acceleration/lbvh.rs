@@ -0,0 +1,318 @@
+// Linear BVH construction, as described in the PBR book (HLBVH).
+//
+// Primitives are ordered along a Z-order curve by quantizing their centroids into Morton codes.
+// Primitives that are close along that curve are close in space, so the hierarchy can be emitted
+// by simply looking at the bits of the sorted codes, which is a lot faster than SAH.
+
+use crate::acceleration::bvh::{BVHBuildNode, BVHConstructionAlgorithm, BVHPrimitiveInfo};
+use crate::algebra::prelude::*;
+
+use rayon::prelude::*;
+
+/// Amount of bits used for each dimension when quantizing a centroid
+const MORTON_BITS: u32 = 10;
+/// Total amount of bits in a Morton code
+const MORTON_TOTAL_BITS: u32 = MORTON_BITS * 3;
+/// Amount of high bits which are used to cluster primitives into treelets
+const TREELET_BITS: u32 = 12;
+/// Maximum amount of primitives in a leaf emitted by the LBVH
+const MAX_PRIMITIVES_IN_LEAF: usize = 4;
+
+/// A primitive index paired with the Morton code of its centroid
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MortonPrimitive {
+    pub index: usize,
+    pub code: u32,
+}
+
+/// Spread the lower 10 bits of `x` out such that there are two zero bits between each of them
+pub fn left_shift_3(x: u32) -> u32 {
+    // Centroids exactly on the upper bound quantize to 1024, which doesn't fit in 10 bits
+    let mut x = if x == (1 << MORTON_BITS) { x - 1 } else { x };
+    x = (x | (x << 16)) & 0b0000_0011_0000_0000_0000_0000_1111_1111;
+    x = (x | (x << 8)) & 0b0000_0011_0000_0000_1111_0000_0000_1111;
+    x = (x | (x << 4)) & 0b0000_0011_0000_1100_0011_0000_1100_0011;
+    x = (x | (x << 2)) & 0b0000_1001_0010_0100_1001_0010_0100_1001;
+    x
+}
+
+/// Interleave the bits of the quantized coordinates of `v`, which must be within [0, 1024]
+pub fn encode_morton_3(v: &Vec3) -> u32 {
+    (left_shift_3(v.z as u32) << 2) | (left_shift_3(v.y as u32) << 1) | left_shift_3(v.x as u32)
+}
+
+/// Sort Morton primitives by their code using a least significant digit radix sort
+pub fn radix_sort(primitives: &mut Vec<MortonPrimitive>) {
+    const BITS_PER_PASS: u32 = 6;
+    const PASSES: u32 = MORTON_TOTAL_BITS / BITS_PER_PASS;
+    const BUCKETS: usize = 1 << BITS_PER_PASS;
+    const BIT_MASK: u32 = (1 << BITS_PER_PASS) - 1;
+
+    let mut temp = vec![MortonPrimitive::default(); primitives.len()];
+    for pass in 0..PASSES {
+        let low_bit = pass * BITS_PER_PASS;
+        let bucket_of = |p: &MortonPrimitive| ((p.code >> low_bit) & BIT_MASK) as usize;
+
+        let mut bucket_count = [0; BUCKETS];
+        for p in primitives.iter() {
+            bucket_count[bucket_of(p)] += 1;
+        }
+
+        // Compute where each bucket starts in the output
+        let mut out_index = [0; BUCKETS];
+        for i in 1..BUCKETS {
+            out_index[i] = out_index[i - 1] + bucket_count[i - 1];
+        }
+
+        for p in primitives.iter() {
+            let b = bucket_of(p);
+            temp[out_index[b]] = *p;
+            out_index[b] += 1;
+        }
+        std::mem::swap(primitives, &mut temp);
+    }
+}
+
+/// Construct a BVH from primitive information by sorting primitives along a Morton curve.
+///
+/// When `upper_sah` is set, the primitives are clustered into treelets by the high bits of their
+/// codes, and the treelets are then joined using SAH (HLBVH). Otherwise the whole hierarchy is
+/// emitted directly from the Morton codes (LBVH).
+///
+/// Primitives end up in leaves in Morton order, which is pushed to `ordering` as indices into
+/// `primitive_info`.
+pub fn construct(
+    primitive_info: &[BVHPrimitiveInfo],
+    upper_sah: bool,
+    total_nodes: &mut usize,
+    ordering: &mut Vec<usize>,
+) -> BVHBuildNode {
+    assert!(!primitive_info.is_empty());
+    let centroid_bounds = primitive_info
+        .iter()
+        .fold(BoundingBox::EMPTY, |a: BoundingBox, b| {
+            a.merge_with_point(&b.centre)
+        });
+
+    let morton_scale = (1 << MORTON_BITS) as Float;
+    let mut morton_primitives: Vec<MortonPrimitive> = primitive_info
+        .par_iter()
+        .enumerate()
+        .map(|(index, info)| MortonPrimitive {
+            index,
+            code: encode_morton_3(&(centroid_bounds.offset(&info.centre) * morton_scale)),
+        })
+        .collect();
+    radix_sort(&mut morton_primitives);
+
+    ordering.extend(morton_primitives.iter().map(|p| p.index));
+
+    if !upper_sah {
+        return emit_lbvh(
+            primitive_info,
+            &morton_primitives,
+            0,
+            MORTON_TOTAL_BITS as i32 - 1,
+            total_nodes,
+        );
+    }
+
+    // Find the ranges of primitives which share the same high bits
+    let mask: u32 = ((1 << TREELET_BITS) - 1) << (MORTON_TOTAL_BITS - TREELET_BITS);
+    let mut treelet_ranges = Vec::new();
+    let mut start = 0;
+    for end in 1..=morton_primitives.len() {
+        if end == morton_primitives.len()
+            || (morton_primitives[start].code & mask) != (morton_primitives[end].code & mask)
+        {
+            treelet_ranges.push((start, end));
+            start = end;
+        }
+    }
+
+    // Treelets are completely independent, so they can be emitted in parallel
+    let first_bit = (MORTON_TOTAL_BITS - TREELET_BITS) as i32 - 1;
+    let treelets: Vec<(BVHBuildNode, usize)> = treelet_ranges
+        .into_par_iter()
+        .map(|(start, end)| {
+            let mut nodes = 0;
+            let node = emit_lbvh(
+                primitive_info,
+                &morton_primitives[start..end],
+                start,
+                first_bit,
+                &mut nodes,
+            );
+            (node, nodes)
+        })
+        .collect();
+
+    let mut roots = Vec::with_capacity(treelets.len());
+    for (node, nodes) in treelets {
+        *total_nodes += nodes;
+        roots.push(node);
+    }
+    build_upper_sah(roots, total_nodes)
+}
+
+/// Recursively emit a hierarchy by splitting on the highest bit at which the codes differ.
+///
+/// `first_offset` is the index of the first primitive of `morton_primitives` in the final
+/// primitive ordering.
+fn emit_lbvh(
+    primitive_info: &[BVHPrimitiveInfo],
+    morton_primitives: &[MortonPrimitive],
+    first_offset: usize,
+    bit_index: i32,
+    total_nodes: &mut usize,
+) -> BVHBuildNode {
+    let len = morton_primitives.len();
+    if bit_index == -1 || len <= MAX_PRIMITIVES_IN_LEAF {
+        *total_nodes += 1;
+        let bounds = morton_primitives
+            .iter()
+            .fold(BoundingBox::EMPTY, |acc: BoundingBox, p| {
+                acc.merge(&primitive_info[p.index].bounding_box)
+            });
+        return BVHBuildNode::new_leaf(first_offset, len, bounds);
+    }
+
+    let mask = 1 << bit_index;
+    if (morton_primitives[0].code & mask) == (morton_primitives[len - 1].code & mask) {
+        // All primitives are on the same side of this plane
+        return emit_lbvh(
+            primitive_info,
+            morton_primitives,
+            first_offset,
+            bit_index - 1,
+            total_nodes,
+        );
+    }
+
+    // Binary search for the first primitive which has the bit set
+    let (mut low, mut high) = (0, len - 1);
+    while low + 1 != high {
+        let mid = (low + high) / 2;
+        if (morton_primitives[low].code & mask) == (morton_primitives[mid].code & mask) {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    let split = high;
+
+    *total_nodes += 1;
+    // Bits are interleaved as `zyx`, so the bit index tells us which axis we're splitting
+    let axis = (bit_index % 3) as usize;
+    BVHBuildNode::new_branch(
+        axis,
+        Box::new(emit_lbvh(
+            primitive_info,
+            &morton_primitives[..split],
+            first_offset,
+            bit_index - 1,
+            total_nodes,
+        )),
+        Box::new(emit_lbvh(
+            primitive_info,
+            &morton_primitives[split..],
+            first_offset + split,
+            bit_index - 1,
+            total_nodes,
+        )),
+    )
+}
+
+/// Join the treelet roots into a single tree using SAH partitioning
+fn build_upper_sah(mut roots: Vec<BVHBuildNode>, total_nodes: &mut usize) -> BVHBuildNode {
+    assert!(!roots.is_empty());
+    let len = roots.len();
+    if len == 1 {
+        return roots.pop().unwrap();
+    }
+    *total_nodes += 1;
+
+    let mut node_info: Vec<BVHPrimitiveInfo> = roots
+        .iter()
+        .enumerate()
+        .map(|(index, node)| BVHPrimitiveInfo {
+            bounding_box: node.bounding_box.clone(),
+            centre: node.bounding_box.centre(),
+            index,
+        })
+        .collect();
+    let aggregate_bounds = node_info
+        .iter()
+        .fold(BoundingBox::EMPTY, |acc: BoundingBox, b| {
+            acc.merge(&b.bounding_box)
+        });
+    let centroid_bounds = node_info
+        .iter()
+        .fold(BoundingBox::EMPTY, |a: BoundingBox, b| {
+            a.merge_with_point(&b.centre)
+        });
+    let dimension = centroid_bounds.max_extent();
+
+    // Unlike the primitives, treelets can't be grouped into a leaf, so always split
+    let middle = BVHConstructionAlgorithm::SAH
+        .perform_partitioning(
+            &mut node_info,
+            dimension,
+            &aggregate_bounds,
+            &centroid_bounds,
+        )
+        .filter(|&middle| middle > 0 && middle < len)
+        .unwrap_or(len / 2);
+
+    let mut slots: Vec<Option<BVHBuildNode>> = roots.into_iter().map(Some).collect();
+    let mut take = |infos: &[BVHPrimitiveInfo]| -> Vec<BVHBuildNode> {
+        infos
+            .iter()
+            .map(|info| slots[info.index].take().unwrap())
+            .collect()
+    };
+    let left = take(&node_info[..middle]);
+    let right = take(&node_info[middle..]);
+
+    BVHBuildNode::new_branch(
+        dimension,
+        Box::new(build_upper_sah(left, total_nodes)),
+        Box::new(build_upper_sah(right, total_nodes)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn morton_encoding() {
+        assert_eq!(left_shift_3(0b1), 0b1);
+        assert_eq!(left_shift_3(0b11), 0b1001);
+        assert_eq!(left_shift_3(0b101), 0b1_000_001);
+        assert_eq!(left_shift_3(1024), left_shift_3(1023));
+
+        assert_eq!(encode_morton_3(&Vec3::new(1.0, 0.0, 0.0)), 0b001);
+        assert_eq!(encode_morton_3(&Vec3::new(0.0, 1.0, 0.0)), 0b010);
+        assert_eq!(encode_morton_3(&Vec3::new(0.0, 0.0, 1.0)), 0b100);
+        assert_eq!(encode_morton_3(&Vec3::new(3.0, 0.0, 1.0)), 0b001_101);
+    }
+
+    #[test]
+    fn radix_sort_orders_codes() {
+        let codes = vec![0x3fff_ffff, 5, 0, 1 << 20, 5, 0x0bad_cafe & 0x3fff_ffff, 77];
+        let mut prims: Vec<MortonPrimitive> = codes
+            .iter()
+            .enumerate()
+            .map(|(index, &code)| MortonPrimitive { index, code })
+            .collect();
+        radix_sort(&mut prims);
+
+        let mut expected = codes.clone();
+        expected.sort();
+        assert_eq!(prims.iter().map(|p| p.code).collect::<Vec<u32>>(), expected);
+        // Radix sort is stable
+        assert_eq!(prims[1].index, 1);
+        assert_eq!(prims[2].index, 4);
+    }
+}
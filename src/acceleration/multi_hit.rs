@@ -0,0 +1,221 @@
+// Queries for every intersection along a ray instead of only the nearest one, as needed for CSG,
+// tracking which volumes a ray is inside of, or just debugging.
+//
+// Primitives only report their first hit, so to find the ones behind it (like where a ray leaves
+// a sphere) the primitive is intersected again with the ray's range starting just past the
+// previous hit.
+
+use crate::acceleration::bvh::BVHLinearTree;
+use crate::acceleration::queue_systems::FastStack;
+use crate::algebra::prelude::*;
+use crate::algebra::ray::RayPrecompute;
+use crate::core::interaction::Interaction;
+
+use std::sync::Arc;
+
+/// How far past a hit the ray's range starts again, at least. Hits closer together than this along
+/// the ray are reported once.
+pub const SKIP_DISTANCE: Float = 1e-4;
+
+/// How far past a hit at `t` the ray's range starts again. Further away the rounding errors of the
+/// hit grow, so the gap does too, which matters mostly with single precision geometry.
+pub fn skip_distance(t: Float) -> Float {
+    SKIP_DISTANCE.max(t.abs() * 256.0 * float::EPSILON)
+}
+
+/// All hits `intersect` finds along the ray in `[ray.min_t, max_t]`, nearest first and at most
+/// `max_hits` of them. After every hit, `intersect` is called again with `min_t` just past it, so
+/// this works for anything which can find the nearest hit within the ray's range.
+pub fn walk_hits<'a, F>(
+    ray: &Ray,
+    max_t: Float,
+    max_hits: usize,
+    intersect: F,
+) -> Vec<Interaction<'a>>
+where
+    F: Fn(&Ray) -> Option<Interaction<'a>>,
+{
+    let mut hits = Vec::new();
+    let mut min_t = ray.min_t;
+    while hits.len() < max_hits {
+        let restarted = Ray {
+            min_t,
+            max_t,
+            ..*ray
+        };
+        let interaction = match intersect(&restarted) {
+            Some(interaction) => interaction,
+            None => break,
+        };
+        let t = interaction.geom.t;
+        if t > max_t {
+            break;
+        }
+        min_t = t + skip_distance(t);
+        hits.push(interaction);
+    }
+    hits
+}
+
+/// Insert `hit` into `hits`, which is sorted by distance, keeping only the `max_hits` nearest.
+/// Primitives referenced from multiple leaves (spatial splits) would otherwise be reported
+/// twice, so hits on a primitive which is already in there at the same distance are dropped.
+fn insert_hit<'a>(hits: &mut Vec<Interaction<'a>>, hit: Interaction<'a>, max_hits: usize) {
+    let t = hit.geom.t;
+    let duplicate = hits.iter().any(|other| {
+        Arc::ptr_eq(&other.primitive, &hit.primitive) && (other.geom.t - t).abs() < skip_distance(t)
+    });
+    if duplicate {
+        return;
+    }
+    let index = hits
+        .iter()
+        .position(|other| other.geom.t > t)
+        .unwrap_or(hits.len());
+    if index < max_hits {
+        hits.insert(index, hit);
+        hits.truncate(max_hits);
+    }
+}
+
+impl BVHLinearTree {
+    /// Every hit in `[ray.min_t, ray.max_t]` sorted by distance, or only the `max_hits` nearest.
+    /// Unlike [walk_hits](fn.walk_hits.html) the tree is only traversed once.
+    pub fn intersect_all(&self, ray: &Ray, max_hits: Option<usize>) -> Vec<Interaction<'_>> {
+        let max_hits = max_hits.unwrap_or(usize::MAX);
+        let mut hits = Vec::new();
+        if self.linear_nodes.is_empty() || max_hits == 0 {
+            return hits;
+        }
+        let pre = RayPrecompute::new(ray);
+        let mut current_task = 0;
+        let mut queue = FastStack::new();
+        // Once enough hits have been found, nodes further away than all of them can be skipped
+        let mut max_t = ray.max_t;
+        loop {
+            let n = &self.linear_nodes[current_task];
+            if n.bounding_box.intersect_precomputed(ray, &pre, max_t) {
+                if n.primitive_amount > 0 {
+                    for prim in
+                        &self.primitives[n.node_content..n.node_content + n.primitive_amount]
+                    {
+                        let prim_hits = walk_hits(ray, max_t, max_hits, |ray| {
+                            prim.intersect(ray).map(|geom| Interaction {
+                                geom,
+                                primitive: Arc::clone(prim),
+                            })
+                        });
+                        for hit in prim_hits {
+                            insert_hit(&mut hits, hit, max_hits);
+                        }
+                        if hits.len() == max_hits {
+                            max_t = hits[max_hits - 1].geom.t;
+                        }
+                    }
+                    match queue.pop() {
+                        None => return hits,
+                        Some(task) => current_task = task,
+                    };
+                } else if pre.dir_is_neg[n.axis] {
+                    queue.push(current_task + 1);
+                    current_task = n.node_content;
+                } else {
+                    queue.push(n.node_content);
+                    current_task += 1;
+                }
+            } else {
+                match queue.pop() {
+                    None => return hits,
+                    Some(task) => current_task = task,
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acceleration::bvh::{testing, BVHConstructionAlgorithm};
+    use crate::core::primitive::Primitive;
+    use crate::geometry::plane::Plane;
+    use crate::geometry::triangle::Triangle;
+
+    /// The distances of all hits, by stepping through every primitive on its own
+    fn brute_force(primitives: &[Arc<dyn Primitive + Sync + Send>], ray: &Ray) -> Vec<Float> {
+        let mut hits = Vec::new();
+        for primitive in primitives {
+            let mut min_t = ray.min_t;
+            while let Some(geom) = primitive.intersect(&Ray { min_t, ..*ray }) {
+                hits.push(geom.t);
+                min_t = geom.t + skip_distance(geom.t);
+            }
+        }
+        hits.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        hits
+    }
+
+    fn same_hits(hits: &[Interaction], expected: &[Float]) -> bool {
+        hits.len() == expected.len()
+            && hits
+                .iter()
+                .zip(expected)
+                .all(|(hit, &t)| testing::same_hit(Some(hit.geom.t), Some(t)))
+    }
+
+    #[test]
+    fn all_hits_match_brute_force() {
+        let mut primitives = testing::long_triangles(300, 21);
+        primitives.extend(testing::spheres(300, 22));
+        for &algorithm in &[
+            BVHConstructionAlgorithm::SAH,
+            BVHConstructionAlgorithm::SBVH(Default::default()),
+        ] {
+            let tree = BVHLinearTree::build(algorithm, primitives.clone());
+            if let BVHConstructionAlgorithm::SBVH(_) = algorithm {
+                // Some primitives are referenced from more than one leaf
+                assert!(tree.primitives.len() > primitives.len());
+            }
+            for ray in &testing::rays(500, 23) {
+                let expected = brute_force(&primitives, ray);
+                let all = tree.intersect_all(ray, None);
+                assert!(same_hits(&all, &expected), "{:?}", algorithm);
+                let nearest = tree.intersect_all(ray, Some(3));
+                assert!(same_hits(&nearest, &expected[..expected.len().min(3)]));
+            }
+        }
+    }
+
+    #[test]
+    fn close_surfaces_are_told_apart() {
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let triangle = |z| {
+            let vertex = |x, y| Vertex::new(Vec3::new(x, y, z), normal, Vec2::new(0.0, 0.0));
+            testing::primitive(Arc::new(Triangle::new(
+                vertex(-1.0, -1.0),
+                vertex(1.0, -1.0),
+                vertex(0.0, 1.0),
+            )))
+        };
+        let plane = |z| {
+            testing::primitive(Arc::new(Plane::new(
+                Point3::new(0.0, 0.0, z),
+                Normal::new(0.0, 0.0, 1.0),
+            )))
+        };
+        let ray = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        for primitives in [
+            vec![triangle(0.0), triangle(5e-4)],
+            vec![plane(-1.0), plane(-1.0 + 5e-4)],
+        ] {
+            let tree = BVHLinearTree::build(BVHConstructionAlgorithm::SAH, primitives.clone());
+            let expected = brute_force(&primitives, &ray);
+            assert_eq!(expected.len(), 2);
+            assert!(same_hits(&tree.intersect_all(&ray, None), &expected));
+            assert!(same_hits(
+                &tree.intersect_all(&ray, Some(1)),
+                &expected[..1]
+            ));
+        }
+    }
+}
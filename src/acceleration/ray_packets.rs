@@ -0,0 +1,259 @@
+// Ray packets and ray streams.
+//
+// Coherent rays (like the camera rays of a small tile, or shadow rays towards the same light)
+// visit mostly the same nodes. Traversing them together means every node is fetched once for the
+// whole packet, and only the rays which are still active (a bitmask) are tested against it.
+// Large, incoherent batches of rays are first sorted into coherent packets, see
+// [intersect_stream](../bvh/struct.BVHLinearTree.html#method.intersect_stream).
+
+use crate::acceleration::bvh::BVHLinearTree;
+use crate::acceleration::lbvh::encode_morton_3;
+use crate::algebra::prelude::*;
+use crate::algebra::ray::RayPrecompute;
+use crate::core::interaction::Interaction;
+
+use std::sync::Arc;
+
+/// The largest amount of rays in a packet, since the active rays are tracked in a `u64`
+pub const MAX_PACKET_SIZE: usize = 64;
+
+/// Mask with the first `amount` rays active
+fn full_mask(amount: usize) -> u64 {
+    if amount == MAX_PACKET_SIZE {
+        !0
+    } else {
+        (1 << amount) - 1
+    }
+}
+
+/// Calls `f` with the index of every active ray in `mask`
+fn for_each_active<F: FnMut(usize)>(mut mask: u64, mut f: F) {
+    while mask != 0 {
+        f(mask.trailing_zeros() as usize);
+        mask &= mask - 1;
+    }
+}
+
+impl BVHLinearTree {
+    /// The rays in `mask` which hit the bounds of node `n`, each before its own `max_t`
+    fn packet_hits(
+        &self,
+        n: usize,
+        rays: &[Ray],
+        pre: &[RayPrecompute],
+        max_t: &[Float],
+        mask: u64,
+    ) -> u64 {
+        let bounding_box = &self.linear_nodes[n].bounding_box;
+        let mut hits = 0;
+        for_each_active(mask, |i| {
+            if bounding_box.intersect_precomputed(&rays[i], &pre[i], max_t[i]) {
+                hits |= 1 << i;
+            }
+        });
+        hits
+    }
+
+    /// Intersect a packet of at most [MAX_PACKET_SIZE](constant.MAX_PACKET_SIZE.html) rays at
+    /// once. This works for any rays, but pays off when they are coherent: close together and
+    /// pointing in roughly the same direction.
+    pub fn intersect_packet(&self, rays: &[Ray]) -> Vec<Option<Interaction<'_>>> {
+        assert!(rays.len() <= MAX_PACKET_SIZE);
+        let mut closest: Vec<Option<Interaction>> = rays.iter().map(|_| None).collect();
+        if self.linear_nodes.is_empty() || rays.is_empty() {
+            return closest;
+        }
+        let pre: Vec<RayPrecompute> = rays.iter().map(RayPrecompute::new).collect();
+        let mut closest_t = vec![float::INFINITY; rays.len()];
+
+        let mut stack = [(0, 0u64); 64];
+        let mut end = 0;
+        let mut current = (0, full_mask(rays.len()));
+        loop {
+            let (task, mask) = current;
+            let hits = self.packet_hits(task, rays, &pre, &closest_t, mask);
+            if hits != 0 {
+                let n = &self.linear_nodes[task];
+                if n.primitive_amount > 0 {
+                    for prim in
+                        &self.primitives[n.node_content..n.node_content + n.primitive_amount]
+                    {
+                        for_each_active(hits, |i| {
+                            if let Some(geom) = prim.intersect(&rays[i]) {
+                                if geom.t < closest_t[i] {
+                                    closest_t[i] = geom.t;
+                                    closest[i] = Some(Interaction {
+                                        geom,
+                                        primitive: Arc::clone(prim),
+                                    });
+                                }
+                            }
+                        });
+                    }
+                } else {
+                    // The rays are expected to be coherent, so the first active one decides
+                    // which child is visited first
+                    let leader = hits.trailing_zeros() as usize;
+                    let (near, far) = if pre[leader].dir_is_neg[n.axis] {
+                        (n.node_content, task + 1)
+                    } else {
+                        (task + 1, n.node_content)
+                    };
+                    stack[end] = (far, hits);
+                    end += 1;
+                    current = (near, hits);
+                    continue;
+                }
+            }
+            if end == 0 {
+                return closest;
+            }
+            end -= 1;
+            current = stack[end];
+        }
+    }
+
+    /// Test a packet of at most [MAX_PACKET_SIZE](constant.MAX_PACKET_SIZE.html) rays for any
+    /// hit, like shadow rays. Rays drop out of the packet as soon as they hit something.
+    pub fn does_intersect_packet(&self, rays: &[Ray]) -> Vec<bool> {
+        assert!(rays.len() <= MAX_PACKET_SIZE);
+        let mut occluded = vec![false; rays.len()];
+        if self.linear_nodes.is_empty() || rays.is_empty() {
+            return occluded;
+        }
+        let pre: Vec<RayPrecompute> = rays.iter().map(RayPrecompute::new).collect();
+        let max_t = vec![float::INFINITY; rays.len()];
+        let mut remaining = full_mask(rays.len());
+
+        let mut stack = [(0, 0u64); 64];
+        let mut end = 0;
+        let mut current = (0, remaining);
+        loop {
+            let (task, mask) = current;
+            let hits = self.packet_hits(task, rays, &pre, &max_t, mask & remaining);
+            if hits != 0 {
+                let n = &self.linear_nodes[task];
+                if n.primitive_amount > 0 {
+                    for prim in
+                        &self.primitives[n.node_content..n.node_content + n.primitive_amount]
+                    {
+                        for_each_active(hits & remaining, |i| {
+                            if prim.does_intersect(&rays[i]) {
+                                occluded[i] = true;
+                                remaining &= !(1 << i);
+                            }
+                        });
+                    }
+                    if remaining == 0 {
+                        return occluded;
+                    }
+                } else {
+                    let leader = hits.trailing_zeros() as usize;
+                    let (near, far) = if pre[leader].dir_is_neg[n.axis] {
+                        (n.node_content, task + 1)
+                    } else {
+                        (task + 1, n.node_content)
+                    };
+                    stack[end] = (far, hits);
+                    end += 1;
+                    current = (near, hits);
+                    continue;
+                }
+            }
+            if end == 0 {
+                return occluded;
+            }
+            end -= 1;
+            current = stack[end];
+        }
+    }
+
+    /// Order in which to trace a stream of rays: grouped by the octant of their direction, and
+    /// along a Morton curve through their origins within that, so that neighbouring rays end up
+    /// in the same packet
+    fn stream_order(&self, rays: &[Ray]) -> Vec<usize> {
+        let bounds = rays.iter().fold(BoundingBox::EMPTY, |acc, ray| {
+            acc.merge_with_point(&ray.origin)
+        });
+        let mut keys: Vec<(u64, usize)> = rays
+            .iter()
+            .enumerate()
+            .map(|(i, ray)| {
+                let octant = (ray.direction.x < 0.0) as u64
+                    | ((ray.direction.y < 0.0) as u64) << 1
+                    | ((ray.direction.z < 0.0) as u64) << 2;
+                let morton = encode_morton_3(&(bounds.offset(&ray.origin) * 1023.0));
+                (octant << 32 | u64::from(morton), i)
+            })
+            .collect();
+        keys.sort_unstable();
+        keys.into_iter().map(|(_, i)| i).collect()
+    }
+
+    /// Intersect any amount of rays by sorting them into coherent packets first. The results
+    /// are in the same order as `rays`.
+    pub fn intersect_stream(&self, rays: &[Ray]) -> Vec<Option<Interaction<'_>>> {
+        let order = self.stream_order(rays);
+        let mut results: Vec<Option<Interaction>> = rays.iter().map(|_| None).collect();
+        let mut packet = Vec::with_capacity(MAX_PACKET_SIZE);
+        for indices in order.chunks(MAX_PACKET_SIZE) {
+            packet.clear();
+            packet.extend(indices.iter().map(|&i| Ray { ..rays[i] }));
+            for (&i, result) in indices.iter().zip(self.intersect_packet(&packet)) {
+                results[i] = result;
+            }
+        }
+        results
+    }
+
+    /// Test any amount of rays for any hit by sorting them into coherent packets first. The
+    /// results are in the same order as `rays`.
+    pub fn does_intersect_stream(&self, rays: &[Ray]) -> Vec<bool> {
+        let order = self.stream_order(rays);
+        let mut results = vec![false; rays.len()];
+        let mut packet = Vec::with_capacity(MAX_PACKET_SIZE);
+        for indices in order.chunks(MAX_PACKET_SIZE) {
+            packet.clear();
+            packet.extend(indices.iter().map(|&i| Ray { ..rays[i] }));
+            for (&i, result) in indices.iter().zip(self.does_intersect_packet(&packet)) {
+                results[i] = result;
+            }
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acceleration::bvh::testing;
+    use crate::acceleration::bvh::BVHConstructionAlgorithm;
+
+    #[test]
+    fn packets_and_streams_hit_like_single_rays() {
+        let tree = BVHLinearTree::build(BVHConstructionAlgorithm::SAH, testing::spheres(1000, 5));
+        let rays = testing::rays(1000, 6);
+        let check = |rays: &[Ray], hits: Vec<Option<Interaction>>, occluded: Vec<bool>| {
+            assert_eq!(hits.len(), rays.len());
+            for ((ray, hit), occluded) in rays.iter().zip(hits).zip(occluded) {
+                let expected = tree.intersect(ray).map(|i| i.geom.t);
+                assert!(testing::same_hit(hit.map(|i| i.geom.t), expected));
+                assert_eq!(occluded, tree.does_intersect(ray));
+            }
+        };
+
+        // Full packets, a partial one, and a single ray
+        for packet in rays.chunks(MAX_PACKET_SIZE).chain(vec![&rays[..1]]) {
+            check(
+                packet,
+                tree.intersect_packet(packet),
+                tree.does_intersect_packet(packet),
+            );
+        }
+        check(
+            &rays,
+            tree.intersect_stream(&rays),
+            tree.does_intersect_stream(&rays),
+        );
+    }
+}
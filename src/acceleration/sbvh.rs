@@ -0,0 +1,469 @@
+// Spatial split BVH construction, following "Spatial Splits in Bounding Volume Hierarchies"
+// (Stich et al. 2009).
+//
+// Object splits (like SAH) never split primitives, which means that large or diagonal primitives
+// make both children overlap a lot. A spatial split instead cuts space with a plane, and
+// primitives straddling it are referenced from both children, with their bounds clipped to each
+// side. This duplicates references, so it's only done when the overlap is significant and while
+// there's memory budget left.
+
+use crate::acceleration::bvh::{BVHBuildNode, BVHPrimitiveInfo, BucketInfo, TRAVERSAL_COST};
+use crate::algebra::prelude::*;
+use crate::core::primitive::Primitive;
+use crate::utils;
+
+use std::sync::Arc;
+
+/// Amount of bins used to evaluate splits on each axis
+const BIN_AMOUNT: usize = 16;
+/// Nodes with at most this many primitives may become leaves if that's cheaper than splitting
+const MAX_PRIMITIVES_IN_LEAF: usize = 4;
+/// Traversal uses a fixed-size stack, so the tree can't get deeper than this
+const MAX_DEPTH: usize = 48;
+
+/// Configuration for [SBVH](../bvh/enum.BVHConstructionAlgorithm.html) construction
+#[derive(Clone, Copy, Debug)]
+pub struct SpatialSplitConfig {
+    /// Spatial splits are only considered when the children of the best object split overlap by
+    /// more than this fraction of the root's surface area. Lower means more spatial splits
+    pub overlap_threshold: f64,
+    /// The maximum amount of extra primitive references created by spatial splits, relative to
+    /// the amount of primitives. `0.3` allows the tree to reference 30% more primitives
+    pub max_duplication: f64,
+}
+
+impl Default for SpatialSplitConfig {
+    fn default() -> Self {
+        Self {
+            overlap_threshold: 1e-5,
+            max_duplication: 0.3,
+        }
+    }
+}
+
+struct ObjectSplit {
+    cost: f64,
+    axis: usize,
+    bucket: usize,
+    centroid_bounds: BoundingBox,
+    left_bounds: BoundingBox,
+    right_bounds: BoundingBox,
+}
+
+struct SpatialSplit {
+    cost: f64,
+    axis: usize,
+    position: Float,
+    left_bounds: BoundingBox,
+    right_bounds: BoundingBox,
+    left_count: usize,
+    right_count: usize,
+}
+
+fn bounds_of(references: &[BVHPrimitiveInfo]) -> BoundingBox {
+    references
+        .iter()
+        .fold(BoundingBox::EMPTY, |acc: BoundingBox, r| {
+            acc.merge(&r.bounding_box)
+        })
+}
+
+fn centroid_bucket(
+    centroid_bounds: &BoundingBox,
+    reference: &BVHPrimitiveInfo,
+    axis: usize,
+) -> usize {
+    let b = (BIN_AMOUNT as Float * centroid_bounds.offset(&reference.centre)[axis]) as usize;
+    b.min(BIN_AMOUNT - 1)
+}
+
+fn split_cost(
+    left_count: usize,
+    left_bounds: &BoundingBox,
+    right_count: usize,
+    right_bounds: &BoundingBox,
+    node_area: f64,
+) -> f64 {
+    TRAVERSAL_COST
+        + (f64::from(left_count as u32) * left_bounds.surface_area()
+            + f64::from(right_count as u32) * right_bounds.surface_area())
+            / node_area
+}
+
+struct SpatialBuilder<'a> {
+    primitives: &'a [Arc<dyn Primitive + Sync + Send>],
+    config: SpatialSplitConfig,
+    root_area: f64,
+    /// Amount of references we may still duplicate
+    budget: usize,
+    total_nodes: usize,
+    ordering: Vec<usize>,
+}
+
+impl<'a> SpatialBuilder<'a> {
+    fn build(&mut self, mut references: Vec<BVHPrimitiveInfo>, depth: usize) -> BVHBuildNode {
+        self.total_nodes += 1;
+        let node_bounds = bounds_of(&references);
+        let len = references.len();
+        if len == 1 || depth >= MAX_DEPTH {
+            return self.make_leaf(&references, node_bounds);
+        }
+        let node_area = node_bounds.surface_area().max(f64::EPSILON);
+
+        let object = self.find_object_split(&references, node_area);
+        let try_spatial = self.budget > 0
+            && object.as_ref().is_none_or(|split| {
+                let overlap = split.left_bounds.intersection(&split.right_bounds);
+                !overlap.is_empty()
+                    && overlap.surface_area() / self.root_area > self.config.overlap_threshold
+            });
+        let spatial = if try_spatial {
+            self.find_spatial_split(&references, &node_bounds, node_area)
+        } else {
+            None
+        };
+
+        let object_cost = object.as_ref().map_or(f64::INFINITY, |s| s.cost);
+        let spatial_cost = spatial.as_ref().map_or(f64::INFINITY, |s| s.cost);
+        let best_cost = object_cost.min(spatial_cost);
+        if !best_cost.is_finite() || (len <= MAX_PRIMITIVES_IN_LEAF && best_cost >= len as f64) {
+            return self.make_leaf(&references, node_bounds);
+        }
+
+        if let Some(split) = spatial.filter(|_| spatial_cost < object_cost) {
+            let (left, right) = self.split_spatially(references, &split);
+            if !left.is_empty() && !right.is_empty() {
+                return self.make_branch(split.axis, left, right, depth);
+            }
+            // Every reference ended up on the same side, fall back to an object split
+            references = left;
+            references.extend(right);
+        }
+
+        match object {
+            Some(split) => {
+                let mut middle = utils::partition(&mut references, |r| {
+                    centroid_bucket(&split.centroid_bounds, r, split.axis) <= split.bucket
+                });
+                if middle == 0 || middle == len {
+                    middle = len / 2;
+                }
+                let right = references.split_off(middle);
+                self.make_branch(split.axis, references, right, depth)
+            }
+            None => self.make_leaf(&references, node_bounds),
+        }
+    }
+
+    fn make_branch(
+        &mut self,
+        axis: usize,
+        left: Vec<BVHPrimitiveInfo>,
+        right: Vec<BVHPrimitiveInfo>,
+        depth: usize,
+    ) -> BVHBuildNode {
+        BVHBuildNode::new_branch(
+            axis,
+            Box::new(self.build(left, depth + 1)),
+            Box::new(self.build(right, depth + 1)),
+        )
+    }
+
+    fn make_leaf(&mut self, references: &[BVHPrimitiveInfo], bounds: BoundingBox) -> BVHBuildNode {
+        let first_offset = self.ordering.len();
+        self.ordering.extend(references.iter().map(|r| r.index));
+        BVHBuildNode::new_leaf(first_offset, references.len(), bounds)
+    }
+
+    /// Find the best binned SAH split over the centroids on any axis
+    fn find_object_split(
+        &self,
+        references: &[BVHPrimitiveInfo],
+        node_area: f64,
+    ) -> Option<ObjectSplit> {
+        let centroid_bounds = references
+            .iter()
+            .fold(BoundingBox::EMPTY, |a: BoundingBox, b| {
+                a.merge_with_point(&b.centre)
+            });
+
+        let mut best: Option<ObjectSplit> = None;
+        for axis in 0..3 {
+            if centroid_bounds.max[axis] - centroid_bounds.min[axis] < float::EPSILON {
+                continue;
+            }
+            let mut buckets = vec![BucketInfo::default(); BIN_AMOUNT];
+            for reference in references {
+                let b = centroid_bucket(&centroid_bounds, reference, axis);
+                buckets[b].count += 1;
+                buckets[b].bounding_box = buckets[b].bounding_box.merge(&reference.bounding_box);
+            }
+
+            // Sweep from the right to know what's on the right side of each plane
+            let mut right_side = vec![BucketInfo::default(); BIN_AMOUNT];
+            let mut acc = BucketInfo::default();
+            for i in (1..BIN_AMOUNT).rev() {
+                acc.count += buckets[i].count;
+                acc.bounding_box = acc.bounding_box.merge(&buckets[i].bounding_box);
+                right_side[i] = acc.clone();
+            }
+
+            let mut left_side = BucketInfo::default();
+            for i in 0..BIN_AMOUNT - 1 {
+                left_side.count += buckets[i].count;
+                left_side.bounding_box = left_side.bounding_box.merge(&buckets[i].bounding_box);
+                let right = &right_side[i + 1];
+                if left_side.count == 0 || right.count == 0 {
+                    continue;
+                }
+                let cost = split_cost(
+                    left_side.count,
+                    &left_side.bounding_box,
+                    right.count,
+                    &right.bounding_box,
+                    node_area,
+                );
+                if best.as_ref().is_none_or(|b| cost < b.cost) {
+                    best = Some(ObjectSplit {
+                        cost,
+                        axis,
+                        bucket: i,
+                        centroid_bounds: centroid_bounds.clone(),
+                        left_bounds: left_side.bounding_box.clone(),
+                        right_bounds: right.bounding_box.clone(),
+                    });
+                }
+            }
+        }
+        best
+    }
+
+    /// Find the best split by chopping the node into equally sized bins on any axis, and
+    /// clipping each reference into the bins it spans
+    fn find_spatial_split(
+        &self,
+        references: &[BVHPrimitiveInfo],
+        node_bounds: &BoundingBox,
+        node_area: f64,
+    ) -> Option<SpatialSplit> {
+        let mut best: Option<SpatialSplit> = None;
+        for axis in 0..3 {
+            let min = node_bounds.min[axis];
+            let extent = node_bounds.max[axis] - min;
+            if extent < float::EPSILON {
+                continue;
+            }
+            let bin_width = extent / BIN_AMOUNT as Float;
+            let bin_of = |x: Float| (((x - min) / bin_width) as usize).min(BIN_AMOUNT - 1);
+            let plane = |i: usize| {
+                if i == BIN_AMOUNT {
+                    node_bounds.max[axis]
+                } else {
+                    min + bin_width * i as Float
+                }
+            };
+
+            let mut bins = vec![BoundingBox::EMPTY; BIN_AMOUNT];
+            let mut entries = [0; BIN_AMOUNT];
+            let mut exits = [0; BIN_AMOUNT];
+            for reference in references {
+                let first = bin_of(reference.bounding_box.min[axis]);
+                let last = bin_of(reference.bounding_box.max[axis]);
+                let primitive = &self.primitives[reference.index];
+                for (b, bin) in bins.iter_mut().enumerate().take(last + 1).skip(first) {
+                    let mut clip = reference.bounding_box.clone();
+                    clip.min[axis] = clip.min[axis].max(plane(b));
+                    clip.max[axis] = clip.max[axis].min(plane(b + 1));
+                    let clipped = primitive.clipped_bounds(&clip);
+                    if !clipped.is_empty() {
+                        *bin = bin.merge(&clipped);
+                    }
+                }
+                entries[first] += 1;
+                exits[last] += 1;
+            }
+
+            let mut right_bounds = vec![BoundingBox::EMPTY; BIN_AMOUNT];
+            let mut right_counts = [0; BIN_AMOUNT];
+            let (mut acc_bounds, mut acc_count) = (BoundingBox::EMPTY, 0);
+            for i in (1..BIN_AMOUNT).rev() {
+                acc_bounds = acc_bounds.merge(&bins[i]);
+                acc_count += exits[i];
+                right_bounds[i] = acc_bounds.clone();
+                right_counts[i] = acc_count;
+            }
+
+            let (mut left_bounds, mut left_count) = (BoundingBox::EMPTY, 0);
+            for i in 0..BIN_AMOUNT - 1 {
+                left_bounds = left_bounds.merge(&bins[i]);
+                left_count += entries[i];
+                let right_count = right_counts[i + 1];
+                if left_count == 0 || right_count == 0 {
+                    continue;
+                }
+                let cost = split_cost(
+                    left_count,
+                    &left_bounds,
+                    right_count,
+                    &right_bounds[i + 1],
+                    node_area,
+                );
+                if best.as_ref().is_none_or(|b| cost < b.cost) {
+                    best = Some(SpatialSplit {
+                        cost,
+                        axis,
+                        position: plane(i + 1),
+                        left_bounds: left_bounds.clone(),
+                        right_bounds: right_bounds[i + 1].clone(),
+                        left_count,
+                        right_count,
+                    });
+                }
+            }
+        }
+        best
+    }
+
+    /// Distribute references over both sides of the split plane. References straddling the plane
+    /// are either moved entirely to one side when that is cheaper (reference unsplitting), or
+    /// duplicated with their bounds clipped to each side while there's budget left.
+    fn split_spatially(
+        &mut self,
+        references: Vec<BVHPrimitiveInfo>,
+        split: &SpatialSplit,
+    ) -> (Vec<BVHPrimitiveInfo>, Vec<BVHPrimitiveInfo>) {
+        let axis = split.axis;
+        let mut left = Vec::with_capacity(split.left_count);
+        let mut right = Vec::with_capacity(split.right_count);
+        let mut left_bounds = split.left_bounds.clone();
+        let mut right_bounds = split.right_bounds.clone();
+        let (mut left_count, mut right_count) = (split.left_count as f64, split.right_count as f64);
+
+        for reference in references {
+            if reference.bounding_box.max[axis] <= split.position {
+                left.push(reference);
+                continue;
+            }
+            if reference.bounding_box.min[axis] >= split.position {
+                right.push(reference);
+                continue;
+            }
+
+            let with_left = left_bounds.merge(&reference.bounding_box);
+            let with_right = right_bounds.merge(&reference.bounding_box);
+            let split_cost =
+                left_bounds.surface_area() * left_count + right_bounds.surface_area() * right_count;
+            let left_cost = with_left.surface_area() * left_count
+                + right_bounds.surface_area() * (right_count - 1.0);
+            let right_cost = left_bounds.surface_area() * (left_count - 1.0)
+                + with_right.surface_area() * right_count;
+
+            if (left_cost < split_cost || self.budget == 0) && left_cost <= right_cost {
+                left_bounds = with_left;
+                right_count -= 1.0;
+                left.push(reference);
+            } else if right_cost < split_cost || self.budget == 0 {
+                right_bounds = with_right;
+                left_count -= 1.0;
+                right.push(reference);
+            } else {
+                let primitive = &self.primitives[reference.index];
+                let mut left_clip = reference.bounding_box.clone();
+                left_clip.max[axis] = split.position;
+                let mut right_clip = reference.bounding_box.clone();
+                right_clip.min[axis] = split.position;
+                let left_part = primitive.clipped_bounds(&left_clip);
+                let right_part = primitive.clipped_bounds(&right_clip);
+
+                match (left_part.is_empty(), right_part.is_empty()) {
+                    (false, false) => {
+                        self.budget -= 1;
+                        left.push(BVHPrimitiveInfo {
+                            centre: left_part.centre(),
+                            bounding_box: left_part,
+                            index: reference.index,
+                        });
+                        right.push(BVHPrimitiveInfo {
+                            centre: right_part.centre(),
+                            bounding_box: right_part,
+                            index: reference.index,
+                        });
+                    }
+                    (false, true) => left.push(BVHPrimitiveInfo {
+                        centre: left_part.centre(),
+                        bounding_box: left_part,
+                        index: reference.index,
+                    }),
+                    (true, false) => right.push(BVHPrimitiveInfo {
+                        centre: right_part.centre(),
+                        bounding_box: right_part,
+                        index: reference.index,
+                    }),
+                    // Only touches the plane, keep it as is
+                    (true, true) => left.push(reference),
+                }
+            }
+        }
+        (left, right)
+    }
+}
+
+/// Construct a BVH using both object and spatial splits.
+///
+/// Since primitives can be referenced from several leaves, the primitive ordering pushed to
+/// `ordering` may contain the same index multiple times.
+pub fn construct(
+    primitives: &[Arc<dyn Primitive + Sync + Send>],
+    primitive_info: Vec<BVHPrimitiveInfo>,
+    config: SpatialSplitConfig,
+    total_nodes: &mut usize,
+    ordering: &mut Vec<usize>,
+) -> BVHBuildNode {
+    assert!(!primitive_info.is_empty());
+    let root_area = bounds_of(&primitive_info).surface_area().max(f64::EPSILON);
+    let mut builder = SpatialBuilder {
+        primitives,
+        config,
+        root_area,
+        budget: (primitive_info.len() as f64 * config.max_duplication) as usize,
+        total_nodes: 0,
+        ordering: Vec::with_capacity(primitive_info.len()),
+    };
+    let node = builder.build(primitive_info, 0);
+    *total_nodes += builder.total_nodes;
+    ordering.append(&mut builder.ordering);
+    node
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::acceleration::bvh::testing::{brute_force, long_triangles, rays, same_hit};
+    use crate::acceleration::bvh::{BVHConstructionAlgorithm, BVHLinearTree};
+
+    #[test]
+    fn long_triangles_hit_like_sah() {
+        let primitives = long_triangles(500, 3);
+        let config = super::SpatialSplitConfig::default();
+        let sah = BVHLinearTree::build(BVHConstructionAlgorithm::SAH, primitives.clone());
+        let sbvh = BVHLinearTree::build(BVHConstructionAlgorithm::SBVH(config), primitives.clone());
+
+        // Spatial splits did happen, within the budget
+        assert!(sbvh.primitives.len() > primitives.len());
+        assert!(
+            sbvh.primitives.len()
+                <= primitives.len() + (primitives.len() as f64 * config.max_duplication) as usize
+        );
+        assert!(sbvh.sah_cost() < sah.sah_cost());
+
+        let mut hits = 0;
+        for ray in rays(2000, 9) {
+            let expected = brute_force(&primitives, &ray);
+            let nearest = sbvh.intersect(&ray).map(|i| i.geom.t);
+            assert!(same_hit(nearest, sah.intersect(&ray).map(|i| i.geom.t)));
+            assert!(same_hit(nearest, expected));
+            assert_eq!(sbvh.does_intersect(&ray), expected.is_some());
+            hits += expected.is_some() as usize;
+        }
+        assert!(hits > 100, "{}", hits);
+    }
+}
@@ -0,0 +1,488 @@
+// Wide BVHs (BVH4/BVH8).
+//
+// A binary BVH is collapsed into a tree where every node has up to four or eight children. The
+// bounds of the children are stored next to each other as f32s (structure of arrays), so one
+// node fits in a few cache lines and all of its children are tested against a ray at once with
+// SIMD, four lanes at a time. Wider nodes also mean a shallower tree, so fewer nodes are visited.
+
+use crate::acceleration::bvh::BVHLinearTree;
+use crate::algebra::prelude::*;
+use crate::core::interaction::{HitFilter, Interaction};
+use crate::core::primitive::Primitive;
+
+use std::sync::Arc;
+
+/// The amount of children per node
+#[derive(Clone, Copy, Debug)]
+pub enum BVHWidth {
+    BVH4,
+    BVH8,
+}
+
+impl BVHWidth {
+    pub fn lanes(self) -> usize {
+        match self {
+            BVHWidth::BVH4 => 4,
+            BVHWidth::BVH8 => 8,
+        }
+    }
+}
+
+/// Four children of a node, laid out so they can be tested in one go. BVH8 nodes are made up
+/// of two of these.
+#[derive(Debug, Clone)]
+#[repr(C, align(16))]
+pub struct NodeQuad {
+    pub min_x: [f32; 4],
+    pub min_y: [f32; 4],
+    pub min_z: [f32; 4],
+    pub max_x: [f32; 4],
+    pub max_y: [f32; 4],
+    pub max_z: [f32; 4],
+    /// The index of the child node, or of the first primitive for leaves
+    pub child: [u32; 4],
+    /// The amount of primitives in a leaf, 0 for inner nodes
+    pub primitive_amount: [u32; 4],
+}
+
+impl Default for NodeQuad {
+    /// Empty lanes have inverted bounds, so rays never hit them
+    fn default() -> Self {
+        Self {
+            min_x: [f32::INFINITY; 4],
+            min_y: [f32::INFINITY; 4],
+            min_z: [f32::INFINITY; 4],
+            max_x: [f32::NEG_INFINITY; 4],
+            max_y: [f32::NEG_INFINITY; 4],
+            max_z: [f32::NEG_INFINITY; 4],
+            child: [0; 4],
+            primitive_amount: [0; 4],
+        }
+    }
+}
+
+/// The ray in the precision the nodes are stored in
+struct WideRay {
+    /// The origin used for the planes a box is entered through, rounded so boxes are entered
+    /// no later than they would be without rounding
+    near_origin: [f32; 3],
+    /// The origin used for the planes a box is left through, rounded the other way
+    far_origin: [f32; 3],
+    inv_dir: [f32; 3],
+    dir_is_neg: [bool; 3],
+}
+
+impl WideRay {
+    fn new(ray: &Ray) -> Self {
+        let inv_dir = [
+            (1.0 / ray.direction.x) as f32,
+            (1.0 / ray.direction.y) as f32,
+            (1.0 / ray.direction.z) as f32,
+        ];
+        let dir_is_neg = [inv_dir[0] < 0.0, inv_dir[1] < 0.0, inv_dir[2] < 0.0];
+        let mut near_origin = [0.0; 3];
+        let mut far_origin = [0.0; 3];
+        for axis in 0..3 {
+            let origin = float::to_f64(ray.origin[axis]);
+            if dir_is_neg[axis] {
+                near_origin[axis] = round_down(origin);
+                far_origin[axis] = round_up(origin);
+            } else {
+                near_origin[axis] = round_up(origin);
+                far_origin[axis] = round_down(origin);
+            }
+        }
+        Self {
+            near_origin,
+            far_origin,
+            inv_dir,
+            dir_is_neg,
+        }
+    }
+}
+
+/// The slab distances are computed in f32, which rounds each of them a few times. Scaling the
+/// exit distance up by this much makes sure that never turns a hit into a miss.
+const EXIT_SCALE: f32 = 1.0 + 4.0 * f32::EPSILON;
+
+/// Deepest stack a traversal can need: up to seven children pushed for every level
+const STACK_SIZE: usize = 512;
+
+#[derive(Debug)]
+pub struct WideBVH {
+    pub width: BVHWidth,
+    pub bounds: BoundingBox,
+    /// Node `i` consists of the quads `i * width / 4 .. (i + 1) * width / 4`
+    pub quads: Vec<NodeQuad>,
+    pub primitives: Vec<Arc<dyn Primitive + Sync + Send>>,
+}
+
+impl WideBVH {
+    /// Collapse a binary tree into a wide one. The binary tree is left as it is; the primitives
+    /// are shared between the two.
+    ///
+    /// Since the bounds are stored as f32, they are rounded outwards. Rounding of the rays is
+    /// dealt with during traversal, see `WideRay`. After refitting the binary tree, the wide tree
+    /// has to be collapsed again.
+    pub fn from_tree(tree: &BVHLinearTree, width: BVHWidth) -> Self {
+        let mut wide = Self {
+            width,
+            bounds: tree.bounds.clone(),
+            quads: Vec::new(),
+            primitives: tree.primitives.clone(),
+        };
+        if tree.linear_nodes.is_empty() {
+            return wide;
+        }
+        wide.collapse(tree, 0);
+        wide
+    }
+
+    /// Open up binary nodes below `node` until there are as many children as fit in a wide node,
+    /// always opening the one with the largest surface area. Returns the index of the wide node.
+    fn collapse(&mut self, tree: &BVHLinearTree, node: usize) -> usize {
+        let lanes = self.width.lanes();
+        let root = &tree.linear_nodes[node];
+        let mut children = if root.primitive_amount > 0 {
+            vec![node]
+        } else {
+            vec![node + 1, root.node_content]
+        };
+        while children.len() < lanes {
+            let widest = children
+                .iter()
+                .enumerate()
+                .filter(|(_, &child)| tree.linear_nodes[child].primitive_amount == 0)
+                .max_by(|(_, &a), (_, &b)| {
+                    let area_a = tree.linear_nodes[a].bounding_box.surface_area();
+                    let area_b = tree.linear_nodes[b].bounding_box.surface_area();
+                    area_a
+                        .partial_cmp(&area_b)
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                })
+                .map(|(i, _)| i);
+            match widest {
+                Some(i) => {
+                    let opened = children.swap_remove(i);
+                    children.push(opened + 1);
+                    children.push(tree.linear_nodes[opened].node_content);
+                }
+                None => break,
+            }
+        }
+
+        // Reserve the quads before recursing, so the index of this node stays put
+        let groups = lanes / 4;
+        let index = self.quads.len() / groups;
+        for _ in 0..groups {
+            self.quads.push(NodeQuad::default());
+        }
+
+        for (lane, &child) in children.iter().enumerate() {
+            let binary = &tree.linear_nodes[child];
+            let (content, amount) = if binary.primitive_amount > 0 {
+                (binary.node_content, binary.primitive_amount)
+            } else {
+                (self.collapse(tree, child), 0)
+            };
+            let bounds = &binary.bounding_box;
+            let quad = &mut self.quads[index * groups + lane / 4];
+            let l = lane % 4;
+            quad.min_x[l] = round_down(float::to_f64(bounds.min.x));
+            quad.min_y[l] = round_down(float::to_f64(bounds.min.y));
+            quad.min_z[l] = round_down(float::to_f64(bounds.min.z));
+            quad.max_x[l] = round_up(float::to_f64(bounds.max.x));
+            quad.max_y[l] = round_up(float::to_f64(bounds.max.y));
+            quad.max_z[l] = round_up(float::to_f64(bounds.max.z));
+            quad.child[l] = content as u32;
+            quad.primitive_amount[l] = amount as u32;
+        }
+        index
+    }
+
+    /// Test all children of a node, returning the lanes which were hit (as a bitmask) together
+    /// with the distance at which each lane's box was entered
+    fn test_node(&self, node: usize, ray: &WideRay, max_t: f32) -> (u32, [f32; 8]) {
+        let groups = self.width.lanes() / 4;
+        let mut mask = 0;
+        let mut entry = [0.0; 8];
+        for group in 0..groups {
+            let quad = &self.quads[node * groups + group];
+            let mut t0 = [0.0; 4];
+            let hits = intersect_quad(quad, ray, max_t, &mut t0);
+            mask |= hits << (group * 4);
+            entry[group * 4..group * 4 + 4].copy_from_slice(&t0);
+        }
+        (mask, entry)
+    }
+
+    pub fn does_intersect(&self, ray: &Ray) -> bool {
+        if self.quads.is_empty() {
+            return false;
+        }
+        let wide_ray = WideRay::new(ray);
+        let groups = self.width.lanes() / 4;
+        let mut stack = [0u32; STACK_SIZE];
+        let mut end = 1;
+        while end > 0 {
+            end -= 1;
+            let node = stack[end] as usize;
+            let (mask, _) = self.test_node(node, &wide_ray, f32::INFINITY);
+            for lane in 0..self.width.lanes() {
+                if mask & (1 << lane) == 0 {
+                    continue;
+                }
+                let quad = &self.quads[node * groups + lane / 4];
+                let amount = quad.primitive_amount[lane % 4] as usize;
+                let child = quad.child[lane % 4] as usize;
+                if amount > 0 {
+                    for prim in &self.primitives[child..child + amount] {
+                        if prim.does_intersect(ray) {
+                            return true;
+                        }
+                    }
+                } else {
+                    stack[end] = child as u32;
+                    end += 1;
+                }
+            }
+        }
+        false
+    }
+
+    pub fn intersect(&self, ray: &Ray) -> Option<Interaction<'_>> {
+        self.intersect_with(ray, |_| true, false)
+    }
+
+    /// The nearest hit accepted by `filter`
+    pub fn intersect_filtered(&self, ray: &Ray, filter: &HitFilter) -> Option<Interaction<'_>> {
+        self.intersect_with(ray, filter, false)
+    }
+
+    /// Any hit accepted by `filter`, stopping at the first one
+    pub fn intersect_any(&self, ray: &Ray, filter: &HitFilter) -> Option<Interaction<'_>> {
+        self.intersect_with(ray, filter, true)
+    }
+
+    fn intersect_with<F>(&self, ray: &Ray, accept: F, any_hit: bool) -> Option<Interaction<'_>>
+    where
+        F: Fn(&Interaction) -> bool,
+    {
+        if self.quads.is_empty() {
+            return None;
+        }
+        let wide_ray = WideRay::new(ray);
+        let groups = self.width.lanes() / 4;
+        let mut closest: Option<Interaction> = None;
+        let mut closest_t = float::INFINITY;
+        // Nodes are stored along with the distance at which they were entered, so they can be
+        // skipped when something closer has been found in the meantime
+        let mut stack = [(0u32, 0.0f32); STACK_SIZE];
+        let mut end = 1;
+        while end > 0 {
+            end -= 1;
+            let (node, node_t) = stack[end];
+            if Float::from(node_t) > closest_t {
+                continue;
+            }
+            let node = node as usize;
+            let (mask, entry) = self.test_node(node, &wide_ray, round_up(float::to_f64(closest_t)));
+
+            // Leaves are intersected right away, inner children are visited nearest first
+            let mut inner = [(0u32, 0.0f32); 8];
+            let mut inner_amount = 0;
+            for (lane, &lane_t) in entry.iter().enumerate().take(self.width.lanes()) {
+                if mask & (1 << lane) == 0 {
+                    continue;
+                }
+                let quad = &self.quads[node * groups + lane / 4];
+                let amount = quad.primitive_amount[lane % 4] as usize;
+                let child = quad.child[lane % 4] as usize;
+                if amount > 0 {
+                    for prim in &self.primitives[child..child + amount] {
+                        if let Some(geom) = prim.intersect(ray) {
+                            if geom.t < closest_t {
+                                let interaction = Interaction {
+                                    geom,
+                                    primitive: Arc::clone(prim),
+                                };
+                                if accept(&interaction) {
+                                    if any_hit {
+                                        return Some(interaction);
+                                    }
+                                    closest_t = interaction.geom.t;
+                                    closest = Some(interaction);
+                                }
+                            }
+                        }
+                    }
+                } else {
+                    inner[inner_amount] = (child as u32, lane_t);
+                    inner_amount += 1;
+                }
+            }
+            // Sort far to near, so the nearest child ends up on top of the stack
+            let inner = &mut inner[..inner_amount];
+            inner.sort_unstable_by(|a, b| {
+                b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal)
+            });
+            for &task in inner.iter() {
+                stack[end] = task;
+                end += 1;
+            }
+        }
+        closest
+    }
+}
+
+/// Slab test of four boxes at once. Stores the entry distance of each box in `t0` and returns
+/// a bitmask of the boxes which were hit within `[0, max_t]`.
+#[cfg(target_arch = "x86_64")]
+fn intersect_quad(quad: &NodeQuad, ray: &WideRay, max_t: f32, t0: &mut [f32; 4]) -> u32 {
+    use std::arch::x86_64::*;
+
+    let (near_x, far_x) = if ray.dir_is_neg[0] {
+        (&quad.max_x, &quad.min_x)
+    } else {
+        (&quad.min_x, &quad.max_x)
+    };
+    let (near_y, far_y) = if ray.dir_is_neg[1] {
+        (&quad.max_y, &quad.min_y)
+    } else {
+        (&quad.min_y, &quad.max_y)
+    };
+    let (near_z, far_z) = if ray.dir_is_neg[2] {
+        (&quad.max_z, &quad.min_z)
+    } else {
+        (&quad.min_z, &quad.max_z)
+    };
+
+    // SSE2 is part of x86_64, so these are always available
+    unsafe {
+        let slab = |plane: &[f32; 4], origin: &[f32; 3], axis: usize| {
+            _mm_mul_ps(
+                _mm_sub_ps(_mm_loadu_ps(plane.as_ptr()), _mm_set1_ps(origin[axis])),
+                _mm_set1_ps(ray.inv_dir[axis]),
+            )
+        };
+        // When either operand is NaN, max and min return the second one. Keeping the running
+        // value second means a NaN (a ray lying in one of the planes) leaves it alone.
+        let mut enter = _mm_setzero_ps();
+        enter = _mm_max_ps(slab(near_x, &ray.near_origin, 0), enter);
+        enter = _mm_max_ps(slab(near_y, &ray.near_origin, 1), enter);
+        enter = _mm_max_ps(slab(near_z, &ray.near_origin, 2), enter);
+        let mut exit = _mm_set1_ps(max_t);
+        exit = _mm_min_ps(slab(far_x, &ray.far_origin, 0), exit);
+        exit = _mm_min_ps(slab(far_y, &ray.far_origin, 1), exit);
+        exit = _mm_min_ps(slab(far_z, &ray.far_origin, 2), exit);
+        exit = _mm_mul_ps(exit, _mm_set1_ps(EXIT_SCALE));
+        _mm_storeu_ps(t0.as_mut_ptr(), enter);
+        _mm_movemask_ps(_mm_cmple_ps(enter, exit)) as u32
+    }
+}
+
+/// Slab test of four boxes at once. Stores the entry distance of each box in `t0` and returns
+/// a bitmask of the boxes which were hit within `[0, max_t]`.
+#[cfg(not(target_arch = "x86_64"))]
+fn intersect_quad(quad: &NodeQuad, ray: &WideRay, max_t: f32, t0: &mut [f32; 4]) -> u32 {
+    let planes = [
+        (&quad.min_x, &quad.max_x),
+        (&quad.min_y, &quad.max_y),
+        (&quad.min_z, &quad.max_z),
+    ];
+    let mut mask = 0;
+    for lane in 0..4 {
+        let mut enter = 0.0;
+        let mut exit = max_t;
+        for (axis, &(min, max)) in planes.iter().enumerate() {
+            let (near, far) = if ray.dir_is_neg[axis] {
+                (max[lane], min[lane])
+            } else {
+                (min[lane], max[lane])
+            };
+            let t_near = (near - ray.near_origin[axis]) * ray.inv_dir[axis];
+            let t_far = (far - ray.far_origin[axis]) * ray.inv_dir[axis];
+            if t_near > enter {
+                enter = t_near;
+            }
+            if t_far < exit {
+                exit = t_far;
+            }
+        }
+        t0[lane] = enter;
+        if enter <= exit * EXIT_SCALE {
+            mask |= 1 << lane;
+        }
+    }
+    mask
+}
+
+/// The largest f32 which is not above `x`
+fn round_down(x: f64) -> f32 {
+    let f = x as f32;
+    if f64::from(f) <= x {
+        f
+    } else if f > 0.0 {
+        f32::from_bits(f.to_bits() - 1)
+    } else if f < 0.0 {
+        f32::from_bits(f.to_bits() + 1)
+    } else {
+        -f32::MIN_POSITIVE
+    }
+}
+
+/// The smallest f32 which is not below `x`
+fn round_up(x: f64) -> f32 {
+    -round_down(-x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acceleration::bvh::testing;
+    use crate::acceleration::bvh::BVHConstructionAlgorithm;
+
+    #[test]
+    fn wide_trees_hit_like_the_binary_one() {
+        let tree = BVHLinearTree::build(BVHConstructionAlgorithm::SAH, testing::spheres(1000, 3));
+        let mut rays = testing::rays(1000, 4);
+        // Starting far away, where rounding the origin to f32 is off by a lot more than the
+        // rounding of the bounds
+        let far: Vec<Ray> = rays
+            .iter()
+            .map(|ray| Ray::new(ray.origin - ray.direction * 1e4, ray.direction))
+            .collect();
+        rays.extend(far);
+        // Axis-parallel, where the inverse direction is infinite
+        for i in 0..200 {
+            let origin = Point3::new((i % 20) as Float * 5.0, (i / 20) as Float * 10.0, 50.0);
+            let mut direction = Vec3::new(0.0, 0.0, 0.0);
+            direction[i % 3] = if i % 2 == 0 { 1.0 } else { -1.0 };
+            rays.push(Ray::new(origin, direction));
+        }
+
+        for &width in &[BVHWidth::BVH4, BVHWidth::BVH8] {
+            let wide = WideBVH::from_tree(&tree, width);
+            for ray in &rays {
+                let expected = tree.intersect(ray).map(|i| i.geom.t);
+                let nearest = wide.intersect(ray).map(|i| i.geom.t);
+                assert!(
+                    testing::same_hit(nearest, expected),
+                    "{:?} {:?} {:?}",
+                    width,
+                    nearest,
+                    expected
+                );
+                assert_eq!(wide.does_intersect(ray), tree.does_intersect(ray));
+            }
+        }
+    }
+
+    #[test]
+    fn rounding_is_conservative() {
+        for &x in &[0.1, -0.1, 1e-40, -1e-40, 123_456.789, 0.0, 1e300] {
+            assert!(f64::from(round_down(x)) <= x);
+            assert!(f64::from(round_up(x)) >= x);
+        }
+    }
+}
@@ -1,6 +1,9 @@
 /// The prelude exports all the important and often used algebra components.
 pub mod prelude;
 
+/// The floating point type of all geometry, f64 unless the `single-precision` feature is on
+pub mod float;
+
 /// Bounding boxes!
 pub mod bounds;
 
@@ -15,9 +18,15 @@ pub mod ray;
 /// Matrices and their functions
 pub mod matrix;
 
+/// SIMD lanes backing the vector and matrix kernels
+pub mod simd;
+
 /// Transforms
 pub mod transform;
 
+/// Quaternions, for rotations which can be interpolated
+pub mod quaternion;
+
 /// Surface normals
 pub mod normal;
 
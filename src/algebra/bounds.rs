@@ -1,4 +1,5 @@
 use crate::algebra::prelude::*;
+use crate::algebra::ray::RayPrecompute;
 use serde::{Deserialize, Serialize};
 
 /// A Bounding Box to represent the maximum range of an object, this is useful for Ray intersection
@@ -17,20 +18,20 @@ pub struct BoundingBox {
 impl BoundingBox {
     pub const EMPTY: Self = Self {
         min: Point3 {
-            x: std::f64::MAX,
-            y: std::f64::MAX,
-            z: std::f64::MAX,
+            x: float::MAX,
+            y: float::MAX,
+            z: float::MAX,
         },
         max: Point3 {
-            x: std::f64::MIN,
-            y: std::f64::MIN,
-            z: std::f64::MIN,
+            x: float::MIN,
+            y: float::MIN,
+            z: float::MIN,
         },
     };
 
     // TODO: take a look at this
     /// Extract a bound
-    fn bounds(&self, sign: f64) -> Point3 {
+    fn bounds(&self, sign: Float) -> Point3 {
         if sign >= 1.0 {
             self.min
         } else {
@@ -97,9 +98,94 @@ impl BoundingBox {
         o
     }
 
+    /// Always in double precision, since it ends up in sums of costs over whole trees
     pub fn surface_area(&self) -> f64 {
         let d = self.diagonal();
-        2.0 * (d.x * d.y + d.x * d.z + d.y * d.z)
+        let (x, y, z) = (float::to_f64(d.x), float::to_f64(d.y), float::to_f64(d.z));
+        2.0 * (x * y + x * z + y * z)
+    }
+
+    /// The overlapping region of two BoundingBoxes. This might be empty
+    pub fn intersection(&self, other: &BoundingBox) -> Self {
+        Self {
+            min: self.min.max(&other.min),
+            max: self.max.min(&other.max),
+        }
+    }
+
+    /// Whether the BoundingBox contains no points at all
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    /// Slab test using values precomputed for the ray. Only hits in `[0, max_t]` count, which
+    /// lets traversal skip boxes that lie beyond the closest hit found so far.
+    #[inline]
+    pub fn intersect_precomputed(&self, ray: &Ray, pre: &RayPrecompute, max_t: Float) -> bool {
+        self.intersect_range(ray, pre, max_t).is_some()
+    }
+
+    /// Like [intersect_precomputed](#method.intersect_precomputed), but returns the distances
+    /// at which the ray enters and leaves the box
+    #[inline]
+    pub fn intersect_range(
+        &self,
+        ray: &Ray,
+        pre: &RayPrecompute,
+        max_t: Float,
+    ) -> Option<(Float, Float)> {
+        let mut t0 = 0.0;
+        let mut t1 = max_t;
+        for axis in 0..3 {
+            let (near, far) = if pre.dir_is_neg[axis] {
+                (self.max[axis], self.min[axis])
+            } else {
+                (self.min[axis], self.max[axis])
+            };
+            let t_near = (near - ray.origin[axis]) * pre.inv_dir[axis];
+            // The distances are rounded a few times, scaling the far one up by a few ulps makes
+            // sure that never turns a hit into a miss
+            let t_far = (far - ray.origin[axis]) * pre.inv_dir[axis] * (1.0 + 4.0 * float::EPSILON);
+            // Written so that a NaN (a ray lying in one of the planes) leaves the range alone
+            if t_near > t0 {
+                t0 = t_near;
+            }
+            if t_far < t1 {
+                t1 = t_far;
+            }
+            if t0 > t1 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
+}
+
+/// Transforming a BoundingBox bounds all of its transformed corners
+impl Transformable for BoundingBox {
+    fn apply_t(self, trans: &Transform) -> Self {
+        let mut bounds = BoundingBox::EMPTY;
+        for corner in 0..8 {
+            let p = Point3::new(
+                if corner & 1 == 0 {
+                    self.min.x
+                } else {
+                    self.max.x
+                },
+                if corner & 2 == 0 {
+                    self.min.y
+                } else {
+                    self.max.y
+                },
+                if corner & 4 == 0 {
+                    self.min.z
+                } else {
+                    self.max.z
+                },
+            );
+            bounds = bounds.merge_with_point(&p.apply_t(trans));
+        }
+        bounds
     }
 }
 
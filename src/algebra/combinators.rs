@@ -1,26 +1,32 @@
 use crate::algebra::prelude::*;
 use std::ops::{Add, Index, Mul};
 
-pub fn to_radians(deg: f64) -> f64 {
-    deg / 180.0 * std::f64::consts::PI
+pub fn to_radians(deg: Float) -> Float {
+    deg / 180.0 * float::consts::PI
 }
 
-pub fn to_degrees(rad: f64) -> f64 {
-    rad / std::f64::consts::PI * 180.0
+pub fn to_degrees(rad: Float) -> Float {
+    rad / float::consts::PI * 180.0
 }
 
 /// Compute the dot product between two elements
-pub fn dot<A: Index<usize, Output = f64>, B: Index<usize, Output = f64>>(a: &A, b: &B) -> f64 {
+pub fn dot<A: Index<usize, Output = Float>, B: Index<usize, Output = Float>>(
+    a: &A,
+    b: &B,
+) -> Float {
     a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
 }
 
 /// Interpolate between two elements
-pub fn lerp<T: Mul<f64, Output = T> + Add<Output = T> + Copy>(t: f64, a: &T, b: &T) -> T {
+pub fn lerp<T: Mul<Float, Output = T> + Add<Output = T> + Copy>(t: Float, a: &T, b: &T) -> T {
     *a * (1.0 - t) + *b * t
 }
 
 /// Calculate the cross-vector
-pub fn cross<A: Index<usize, Output = f64>, B: Index<usize, Output = f64>>(a: &A, b: &B) -> Vec3 {
+pub fn cross<A: Index<usize, Output = Float>, B: Index<usize, Output = Float>>(
+    a: &A,
+    b: &B,
+) -> Vec3 {
     Vec3::new(
         a[1] * b[2] - a[2] * b[1],
         a[2] * b[0] - a[0] * b[2],
@@ -28,6 +34,17 @@ pub fn cross<A: Index<usize, Output = f64>, B: Index<usize, Output = f64>>(a: &A
     )
 }
 
+/// Two normalized vectors which together with the normalized `n` make up a right-handed
+/// orthonormal basis
+pub fn coordinate_system(n: &Vec3) -> (Vec3, Vec3) {
+    let s = if n.x.abs() > n.y.abs() {
+        Vec3::new(n.z, 0.0, -n.x) / (n.x * n.x + n.z * n.z).sqrt()
+    } else {
+        Vec3::new(0.0, -n.z, n.y) / (n.y * n.y + n.z * n.z).sqrt()
+    };
+    (s, cross(n, &s))
+}
+
 /// Make an object clampable between two instances of itself
 /// # Example:
 /// ```
@@ -41,26 +58,32 @@ pub trait Clampable {
     fn clamp_to(self, min: Self, max: Self) -> Self;
 }
 
-impl Clampable for f64 {
-    fn clamp_to(self, min: f64, max: f64) -> f64 {
-        if self > max {
-            return max;
+macro_rules! impl_clampable {
+    ($($t:ty),*) => {$(
+        impl Clampable for $t {
+            fn clamp_to(self, min: $t, max: $t) -> $t {
+                if self > max {
+                    return max;
+                }
+                if self < min {
+                    return min;
+                }
+                self
+            }
         }
-        if self < min {
-            return min;
-        }
-        self
-    }
+    )*};
 }
 
-pub fn spherical_direction(sin_theta: f64, cos_theta: f64, phi: f64) -> Vec3 {
+impl_clampable!(f32, f64);
+
+pub fn spherical_direction(sin_theta: Float, cos_theta: Float, phi: Float) -> Vec3 {
     Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
 }
 
 pub fn spherical_direction_axes(
-    sin_theta: f64,
-    cos_theta: f64,
-    phi: f64,
+    sin_theta: Float,
+    cos_theta: Float,
+    phi: Float,
     x: &Vec3,
     y: &Vec3,
     z: &Vec3,
@@ -68,14 +91,14 @@ pub fn spherical_direction_axes(
     sin_theta * phi.cos() * *x + sin_theta * phi.sin() * *y + cos_theta * *z
 }
 
-pub fn spherical_theta(v: &Vec3) -> f64 {
+pub fn spherical_theta(v: &Vec3) -> Float {
     v.z.clamp_to(-1.0, 1.0).acos()
 }
 
-pub fn spherical_phi(v: &Vec3) -> f64 {
+pub fn spherical_phi(v: &Vec3) -> Float {
     let p = v.x.atan2(v.y);
     if p < 0.0 {
-        p + 2.0 * std::f64::consts::PI
+        p + 2.0 * float::consts::PI
     } else {
         p
     }
@@ -0,0 +1,46 @@
+// The floating point type used for geometry.
+//
+// Double precision by default. The `single-precision` feature switches all geometry to f32,
+// which halves the memory used by meshes and acceleration structures. Values which are summed
+// up over many samples or primitives, like radiance and SAH costs, stay f64 either way.
+
+#[cfg(not(feature = "single-precision"))]
+pub use std::f64::consts;
+#[cfg(not(feature = "single-precision"))]
+pub type Float = f64;
+
+#[cfg(feature = "single-precision")]
+pub use std::f32::consts;
+#[cfg(feature = "single-precision")]
+pub type Float = f32;
+
+pub const EPSILON: Float = Float::EPSILON;
+pub const INFINITY: Float = Float::INFINITY;
+pub const MAX: Float = Float::MAX;
+pub const MIN: Float = Float::MIN;
+pub const NAN: Float = Float::NAN;
+pub const NEG_INFINITY: Float = Float::NEG_INFINITY;
+
+/// Widens a geometry value for the parts which always work in f64, this is a no-op in double
+/// precision
+#[allow(clippy::useless_conversion)]
+#[inline]
+pub fn to_f64(x: Float) -> f64 {
+    f64::from(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn precision_follows_the_feature() {
+        let bytes = if cfg!(feature = "single-precision") {
+            4
+        } else {
+            8
+        };
+        assert_eq!(std::mem::size_of::<Float>(), bytes);
+        assert_eq!(std::mem::size_of_val(&to_f64(1.0)), 8);
+    }
+}
@@ -1,9 +1,11 @@
+use crate::algebra::float::Float;
+use crate::algebra::simd::Lanes;
 use std::iter::FromIterator;
 use std::slice::{Iter, IterMut};
 
 #[derive(PartialEq, Debug, Clone, Copy)]
 pub struct Mat4x4 {
-    pub m: [f64; 16],
+    pub m: [Float; 16],
 }
 
 impl Default for Mat4x4 {
@@ -27,7 +29,7 @@ impl Mat4x4 {
         0.0, 0.0, 0.0, 1.0, //
     ]);
 
-    pub const fn new(m: [f64; 16]) -> Self {
+    pub const fn new(m: [Float; 16]) -> Self {
         Self { m }
     }
 
@@ -41,152 +43,86 @@ impl Mat4x4 {
         res
     }
 
-    pub fn at(&self, i: usize, j: usize) -> &f64 {
+    pub fn at(&self, i: usize, j: usize) -> &Float {
         &self.m[i * 4 + j]
     }
 
-    pub fn at_mut(&mut self, i: usize, j: usize) -> &mut f64 {
+    pub fn at_mut(&mut self, i: usize, j: usize) -> &mut Float {
         &mut self.m[i * 4 + j]
     }
 
-    pub fn inverse(&self) -> Self {
-        let mut i = Self::EMPTY;
-        i.m[0] = self.m[5] * self.m[10] * self.m[15]
-            - self.m[5] * self.m[11] * self.m[14]
-            - self.m[9] * self.m[6] * self.m[15]
-            + self.m[9] * self.m[7] * self.m[14]
-            + self.m[13] * self.m[6] * self.m[11]
-            - self.m[13] * self.m[7] * self.m[10];
-
-        i.m[4] = -self.m[4] * self.m[10] * self.m[15]
-            + self.m[4] * self.m[11] * self.m[14]
-            + self.m[8] * self.m[6] * self.m[15]
-            - self.m[8] * self.m[7] * self.m[14]
-            - self.m[12] * self.m[6] * self.m[11]
-            + self.m[12] * self.m[7] * self.m[10];
-
-        i.m[8] = self.m[4] * self.m[9] * self.m[15]
-            - self.m[4] * self.m[11] * self.m[13]
-            - self.m[8] * self.m[5] * self.m[15]
-            + self.m[8] * self.m[7] * self.m[13]
-            + self.m[12] * self.m[5] * self.m[11]
-            - self.m[12] * self.m[7] * self.m[9];
-
-        i.m[12] = -self.m[4] * self.m[9] * self.m[14]
-            + self.m[4] * self.m[10] * self.m[13]
-            + self.m[8] * self.m[5] * self.m[14]
-            - self.m[8] * self.m[6] * self.m[13]
-            - self.m[12] * self.m[5] * self.m[10]
-            + self.m[12] * self.m[6] * self.m[9];
-
-        i.m[1] = -self.m[1] * self.m[10] * self.m[15]
-            + self.m[1] * self.m[11] * self.m[14]
-            + self.m[9] * self.m[2] * self.m[15]
-            - self.m[9] * self.m[3] * self.m[14]
-            - self.m[13] * self.m[2] * self.m[11]
-            + self.m[13] * self.m[3] * self.m[10];
-
-        i.m[5] = self.m[0] * self.m[10] * self.m[15]
-            - self.m[0] * self.m[11] * self.m[14]
-            - self.m[8] * self.m[2] * self.m[15]
-            + self.m[8] * self.m[3] * self.m[14]
-            + self.m[12] * self.m[2] * self.m[11]
-            - self.m[12] * self.m[3] * self.m[10];
-
-        i.m[9] = -self.m[0] * self.m[9] * self.m[15]
-            + self.m[0] * self.m[11] * self.m[13]
-            + self.m[8] * self.m[1] * self.m[15]
-            - self.m[8] * self.m[3] * self.m[13]
-            - self.m[12] * self.m[1] * self.m[11]
-            + self.m[12] * self.m[3] * self.m[9];
-
-        i.m[13] = self.m[0] * self.m[9] * self.m[14]
-            - self.m[0] * self.m[10] * self.m[13]
-            - self.m[8] * self.m[1] * self.m[14]
-            + self.m[8] * self.m[2] * self.m[13]
-            + self.m[12] * self.m[1] * self.m[10]
-            - self.m[12] * self.m[2] * self.m[9];
-
-        i.m[2] = self.m[1] * self.m[6] * self.m[15]
-            - self.m[1] * self.m[7] * self.m[14]
-            - self.m[5] * self.m[2] * self.m[15]
-            + self.m[5] * self.m[3] * self.m[14]
-            + self.m[13] * self.m[2] * self.m[7]
-            - self.m[13] * self.m[3] * self.m[6];
-
-        i.m[6] = -self.m[0] * self.m[6] * self.m[15]
-            + self.m[0] * self.m[7] * self.m[14]
-            + self.m[4] * self.m[2] * self.m[15]
-            - self.m[4] * self.m[3] * self.m[14]
-            - self.m[12] * self.m[2] * self.m[7]
-            + self.m[12] * self.m[3] * self.m[6];
-
-        i.m[10] = self.m[0] * self.m[5] * self.m[15]
-            - self.m[0] * self.m[7] * self.m[13]
-            - self.m[4] * self.m[1] * self.m[15]
-            + self.m[4] * self.m[3] * self.m[13]
-            + self.m[12] * self.m[1] * self.m[7]
-            - self.m[12] * self.m[3] * self.m[5];
-
-        i.m[14] = -self.m[0] * self.m[5] * self.m[14]
-            + self.m[0] * self.m[6] * self.m[13]
-            + self.m[4] * self.m[1] * self.m[14]
-            - self.m[4] * self.m[2] * self.m[13]
-            - self.m[12] * self.m[1] * self.m[6]
-            + self.m[12] * self.m[2] * self.m[5];
+    /// Row `i` as SIMD lanes
+    #[inline]
+    pub fn row(&self, i: usize) -> Lanes {
+        Lanes::load(&self.m[i * 4..])
+    }
 
-        i.m[3] = -self.m[1] * self.m[6] * self.m[11]
-            + self.m[1] * self.m[7] * self.m[10]
-            + self.m[5] * self.m[2] * self.m[11]
-            - self.m[5] * self.m[3] * self.m[10]
-            - self.m[9] * self.m[2] * self.m[7]
-            + self.m[9] * self.m[3] * self.m[6];
+    /// Column `j` as SIMD lanes
+    #[inline]
+    pub fn column(&self, j: usize) -> Lanes {
+        Lanes::new(self.m[j], self.m[4 + j], self.m[8 + j], self.m[12 + j])
+    }
 
-        i.m[7] = self.m[0] * self.m[6] * self.m[11]
-            - self.m[0] * self.m[7] * self.m[10]
-            - self.m[4] * self.m[2] * self.m[11]
-            + self.m[4] * self.m[3] * self.m[10]
-            + self.m[8] * self.m[2] * self.m[7]
-            - self.m[8] * self.m[3] * self.m[6];
+    /// The product with `rhs` on SIMD lanes. LLVM already vectorizes the scalar product well, so
+    /// `*` doesn't use this; the algebra benchmark compares the two.
+    pub fn mul_lanes(&self, rhs: &Mat4x4) -> Mat4x4 {
+        let rows = [rhs.row(0), rhs.row(1), rhs.row(2), rhs.row(3)];
+        let mut res = Mat4x4::EMPTY;
+        for (out, a) in res.m.chunks_exact_mut(4).zip(self.m.chunks_exact(4)) {
+            let row = rows[0] * a[0] + rows[1] * a[1] + rows[2] * a[2] + rows[3] * a[3];
+            out.copy_from_slice(&row.to_array());
+        }
+        res
+    }
 
-        i.m[11] = -self.m[0] * self.m[5] * self.m[11]
-            + self.m[0] * self.m[7] * self.m[9]
-            + self.m[4] * self.m[1] * self.m[11]
-            - self.m[4] * self.m[3] * self.m[9]
-            - self.m[8] * self.m[1] * self.m[7]
-            + self.m[8] * self.m[3] * self.m[5];
+    /// Invert using the 3D vector formulation from Lengyel's Foundations of Game Engine
+    /// Development, which maps onto cross and dot products of the columns
+    pub fn inverse(&self) -> Self {
+        let (a, b, c, d) = (
+            self.column(0),
+            self.column(1),
+            self.column(2),
+            self.column(3),
+        );
+        let (x, y, z, w) = (self.m[12], self.m[13], self.m[14], self.m[15]);
 
-        i.m[15] = self.m[0] * self.m[5] * self.m[10]
-            - self.m[0] * self.m[6] * self.m[9]
-            - self.m[4] * self.m[1] * self.m[10]
-            + self.m[4] * self.m[2] * self.m[9]
-            + self.m[8] * self.m[1] * self.m[6]
-            - self.m[8] * self.m[2] * self.m[5];
+        let s = a.cross3(b);
+        let t = c.cross3(d);
+        let u = a * y - b * x;
+        let v = c * w - d * z;
 
-        let mut det =
-            self.m[0] * i.m[0] + self.m[1] * i.m[4] + self.m[2] * i.m[8] + self.m[3] * i.m[12];
+        let det = s.dot3(v) + t.dot3(u);
         assert!(det != 0.0);
-        det = 1.0 / det;
-
-        for x in &mut i.m {
-            *x *= det;
+        let inv_det = 1.0 / det;
+        let (s, t, u, v) = (s * inv_det, t * inv_det, u * inv_det, v * inv_det);
+
+        let rows = [
+            (b.cross3(v) + t * y, -b.dot3(t)),
+            (v.cross3(a) - t * x, a.dot3(t)),
+            (d.cross3(u) + s * w, -d.dot3(s)),
+            (u.cross3(c) - s * z, c.dot3(s)),
+        ];
+        let mut i = Self::EMPTY;
+        for (out, (xyz, last)) in i.m.chunks_exact_mut(4).zip(rows.iter()) {
+            out.copy_from_slice(&xyz.to_array());
+            out[3] = *last;
         }
         i
     }
 
     /// Get iterator over mat
-    pub fn iter(&self) -> Iter<f64> {
+    pub fn iter(&self) -> Iter<Float> {
         self.m.iter()
     }
 
     /// Get mutable iterator over mat
-    pub fn iter_mut(&mut self) -> IterMut<f64> {
+    pub fn iter_mut(&mut self) -> IterMut<Float> {
         self.m.iter_mut()
     }
 }
 
-impl FromIterator<f64> for Mat4x4 {
-    fn from_iter<T: IntoIterator<Item = f64>>(it: T) -> Mat4x4 {
+impl FromIterator<Float> for Mat4x4 {
+    fn from_iter<T: IntoIterator<Item = Float>>(it: T) -> Mat4x4 {
         let mut m = Mat4x4::EMPTY;
         for (r, x) in m.iter_mut().zip(it.into_iter()) {
             *r = x;
@@ -195,8 +131,8 @@ impl FromIterator<f64> for Mat4x4 {
     }
 }
 
-impl<'a> FromIterator<&'a f64> for Mat4x4 {
-    fn from_iter<T: IntoIterator<Item = &'a f64>>(it: T) -> Mat4x4 {
+impl<'a> FromIterator<&'a Float> for Mat4x4 {
+    fn from_iter<T: IntoIterator<Item = &'a Float>>(it: T) -> Mat4x4 {
         let mut m = Mat4x4::EMPTY;
         for (r, x) in m.iter_mut().zip(it.into_iter()) {
             *r = *x;
@@ -236,14 +172,14 @@ impl std::ops::Mul<Mat4x4> for Mat4x4 {
     }
 }
 
-impl std::ops::Mul<f64> for Mat4x4 {
+impl std::ops::Mul<Float> for Mat4x4 {
     type Output = Mat4x4;
-    fn mul(self, rhs: f64) -> Self {
+    fn mul(self, rhs: Float) -> Self {
         self.iter().map(|a| a * rhs).collect()
     }
 }
 
-impl std::ops::Mul<Mat4x4> for f64 {
+impl std::ops::Mul<Mat4x4> for Float {
     type Output = Mat4x4;
     fn mul(self, rhs: Mat4x4) -> Mat4x4 {
         rhs.iter().map(|a| a * self).collect()
@@ -290,6 +226,18 @@ mod tests {
             16.0,
         ]);
         assert!(a * b == c);
+        assert!(a.mul_lanes(&b) == c);
+    }
+
+    #[test]
+    fn inverse() {
+        let a = Mat4x4::new([
+            1.0, 2.0, 1.0, 0.0, 3.0, 1.0, 4.0, 2.0, 1.0, 2.0, -5.0, 4.0, 3.0, 2.0, 4.0, 1.0,
+        ]);
+        for (x, y) in (a * a.inverse()).iter().zip(Mat4x4::IDENTITY.iter()) {
+            assert!((x - y).abs() < 1e-5);
+        }
+        assert_eq!(Mat4x4::IDENTITY.inverse(), Mat4x4::IDENTITY);
     }
 
     #[test]
@@ -2,9 +2,9 @@ use crate::algebra::prelude::*;
 
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Normal {
-    pub x: f64,
-    pub y: f64,
-    pub z: f64,
+    pub x: Float,
+    pub y: Float,
+    pub z: Float,
 }
 
 impl Normal {
@@ -18,16 +18,16 @@ impl Normal {
         self.x.is_nan() || self.y.is_nan() || self.z.is_nan()
     }
 
-    pub const fn new(x: f64, y: f64, z: f64) -> Self {
+    pub const fn new(x: Float, y: Float, z: Float) -> Self {
         Normal { x, y, z }
     }
 
-    pub fn length2(&self) -> f64 {
+    pub fn length2(&self) -> Float {
         self.x * self.x + self.y * self.y + self.z * self.z
     }
 
     /// The magnitude/length of a Vector
-    pub fn length(&self) -> f64 {
+    pub fn length(&self) -> Float {
         self.length2().sqrt()
     }
 
@@ -38,7 +38,7 @@ impl Normal {
         *self / mag
     }
 
-    pub fn dot(&self, rhs: &Self) -> f64 {
+    pub fn dot(&self, rhs: &Self) -> Float {
         self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
     }
 }
@@ -69,9 +69,9 @@ impl std::ops::Add<Normal> for Normal {
     }
 }
 
-impl std::ops::Mul<f64> for Normal {
+impl std::ops::Mul<Float> for Normal {
     type Output = Normal;
-    fn mul(self, rhs: f64) -> Normal {
+    fn mul(self, rhs: Float) -> Normal {
         Normal::new(self.x * rhs, self.y * rhs, self.z * rhs)
     }
 }
@@ -83,16 +83,16 @@ impl std::ops::Mul<Normal> for Normal {
     }
 }
 
-impl std::ops::Mul<Normal> for f64 {
+impl std::ops::Mul<Normal> for Float {
     type Output = Normal;
     fn mul(self, rhs: Normal) -> Normal {
         Normal::new(rhs.x * self, rhs.y * self, rhs.z * self)
     }
 }
 
-impl std::ops::Div<f64> for Normal {
+impl std::ops::Div<Float> for Normal {
     type Output = Normal;
-    fn div(self, rhs: f64) -> Normal {
+    fn div(self, rhs: Float) -> Normal {
         Normal::new(self.x / rhs, self.y / rhs, self.z / rhs)
     }
 }
@@ -105,7 +105,8 @@ impl std::ops::Neg for Normal {
 }
 
 impl std::ops::Index<usize> for Normal {
-    type Output = f64;
+    type Output = Float;
+    #[inline]
     fn index(&self, i: usize) -> &Self::Output {
         match i {
             0 => &self.x,
@@ -117,6 +118,7 @@ impl std::ops::Index<usize> for Normal {
 }
 
 impl std::ops::IndexMut<usize> for Normal {
+    #[inline]
     fn index_mut(&mut self, i: usize) -> &mut Self::Output {
         match i {
             0 => &mut self.x,
@@ -3,12 +3,12 @@ use crate::algebra::prelude::*;
 use serde::{Deserialize, Serialize};
 #[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
 pub struct Point2 {
-    pub x: f64,
-    pub y: f64,
+    pub x: Float,
+    pub y: Float,
 }
 
 impl Point2 {
-    pub const fn new(x: f64, y: f64) -> Self {
+    pub const fn new(x: Float, y: Float) -> Self {
         Self { x, y }
     }
 
@@ -16,7 +16,7 @@ impl Point2 {
     /// # Example:
     /// ```
     /// use thruster::algebra::prelude::*;
-    /// assert!(Point2::new(std::f64::NAN, 0.0).has_nans());
+    /// assert!(Point2::new(float::NAN, 0.0).has_nans());
     /// assert!(!Point2::new(0.0, 5.0).has_nans());
     /// ```
     pub fn has_nans(&self) -> bool {
@@ -46,15 +46,15 @@ impl From<Vec2> for Point2 {
 
 #[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
 pub struct Point3 {
-    pub x: f64,
-    pub y: f64,
-    pub z: f64,
+    pub x: Float,
+    pub y: Float,
+    pub z: Float,
 }
 
 impl Point3 {
     pub const ORIGIN: Self = Self::new(0.0, 0.0, 0.0);
 
-    pub const fn new(x: f64, y: f64, z: f64) -> Self {
+    pub const fn new(x: Float, y: Float, z: Float) -> Self {
         Self { x, y, z }
     }
 
@@ -62,19 +62,19 @@ impl Point3 {
     /// # Example:
     /// ```
     /// use thruster::algebra::prelude::*;
-    /// assert!(Point3::new(0.0, std::f64::NAN, 0.0).has_nans());
+    /// assert!(Point3::new(0.0, float::NAN, 0.0).has_nans());
     /// assert!(!Point3::new(4.0, 0.0, 5.0).has_nans());
     /// ```
     pub fn has_nans(&self) -> bool {
         self.x.is_nan() || self.y.is_nan() || self.z.is_nan()
     }
 
-    pub fn distance2(&self, rhs: &Self) -> f64 {
+    pub fn distance2(&self, rhs: &Self) -> Float {
         (*self - *rhs).length2()
     }
 
     /// Get the distance between two points
-    pub fn distance(&self, rhs: &Self) -> f64 {
+    pub fn distance(&self, rhs: &Self) -> Float {
         (*self - *rhs).length()
     }
 
@@ -124,16 +124,16 @@ impl std::ops::Mul<Point3> for Vec3 {
     }
 }
 
-impl std::ops::Mul<f64> for Point3 {
+impl std::ops::Mul<Float> for Point3 {
     type Output = Point3;
-    fn mul(self, rhs: f64) -> Self {
+    fn mul(self, rhs: Float) -> Self {
         Self::new(self.x * rhs, self.y * rhs, self.z * rhs)
     }
 }
 
-impl std::ops::Div<f64> for Point3 {
+impl std::ops::Div<Float> for Point3 {
     type Output = Point3;
-    fn div(self, rhs: f64) -> Self {
+    fn div(self, rhs: Float) -> Self {
         Self::new(self.x / rhs, self.y / rhs, self.z / rhs)
     }
 }
@@ -184,7 +184,8 @@ impl std::ops::MulAssign<Vec3> for Point3 {
 }
 
 impl std::ops::Index<usize> for Point3 {
-    type Output = f64;
+    type Output = Float;
+    #[inline]
     fn index(&self, i: usize) -> &Self::Output {
         match i {
             0 => &self.x,
@@ -196,6 +197,7 @@ impl std::ops::Index<usize> for Point3 {
 }
 
 impl std::ops::IndexMut<usize> for Point3 {
+    #[inline]
     fn index_mut(&mut self, i: usize) -> &mut Self::Output {
         match i {
             0 => &mut self.x,
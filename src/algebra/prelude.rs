@@ -2,10 +2,12 @@
 pub use super::bounds::BoundingBox;
 pub use super::combinators as comb;
 pub use super::combinators::Clampable;
+pub use super::float::{self, Float};
 pub use super::matrix::Mat4x4;
 pub use super::normal::Normal;
 pub use super::pixel::Pixel;
 pub use super::points::{Point2, Point3};
+pub use super::quaternion::Quaternion;
 pub use super::ray::Ray;
 pub use super::transform::{Transform, Transformable};
 pub use super::vectors::{Vec2, Vec3, Vertex};
@@ -0,0 +1,311 @@
+// Quaternions for representing and interpolating rotations.
+//
+// Unit quaternions describe a rotation of `theta` about an axis as `(sin(theta / 2) axis,
+// cos(theta / 2))`. Unlike rotation matrices they can be interpolated without skewing, which
+// is what transform interpolation for animation and motion blur builds on.
+
+use crate::algebra::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Quaternion {
+    /// The imaginary part
+    pub v: Vec3,
+    /// The real part
+    pub w: Float,
+}
+
+impl Default for Quaternion {
+    /// No rotation
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Quaternion {
+    pub const IDENTITY: Self = Self::new(Vec3::new(0.0, 0.0, 0.0), 1.0);
+
+    pub const fn new(v: Vec3, w: Float) -> Self {
+        Self { v, w }
+    }
+
+    /// Rotation of `theta` radians about `axis`, which doesn't need to be normalized
+    pub fn from_axis_angle(axis: &Vec3, theta: Float) -> Self {
+        let half = theta / 2.0;
+        Self::new(axis.normalized() * half.sin(), half.cos())
+    }
+
+    /// The axis and angle in radians of the rotation. The axis is arbitrary for the identity.
+    pub fn to_axis_angle(&self) -> (Vec3, Float) {
+        let q = self.normalized();
+        let theta = 2.0 * q.w.clamp(-1.0, 1.0).acos();
+        let sin_half = (1.0 - q.w * q.w).max(0.0).sqrt();
+        if sin_half < float::EPSILON {
+            (Vec3::new(1.0, 0.0, 0.0), theta)
+        } else {
+            (q.v / sin_half, theta)
+        }
+    }
+
+    /// Rotation about X, then Y, then Z by the given angles in radians, the same as
+    /// `Transform::rotate_z(z) * Transform::rotate_y(y) * Transform::rotate_x(x)`
+    pub fn from_euler(x: Float, y: Float, z: Float) -> Self {
+        Self::from_axis_angle(&Vec3::new(0.0, 0.0, 1.0), z)
+            * Self::from_axis_angle(&Vec3::new(0.0, 1.0, 0.0), y)
+            * Self::from_axis_angle(&Vec3::new(1.0, 0.0, 0.0), x)
+    }
+
+    /// The angles for `from_euler`. Y is within [-pi/2, pi/2], and when it's at either end the
+    /// rotation about Z is folded into the one about X.
+    pub fn to_euler(&self) -> Vec3 {
+        let m = self.to_matrix();
+        let sin_y = (-m.at(2, 0)).clamp(-1.0, 1.0);
+        let y = sin_y.asin();
+        if sin_y.abs() > 1.0 - 1e-6 {
+            let x = (sin_y * m.at(0, 1)).atan2(*m.at(1, 1));
+            Vec3::new(x, y, 0.0)
+        } else {
+            Vec3::new(
+                m.at(2, 1).atan2(*m.at(2, 2)),
+                y,
+                m.at(1, 0).atan2(*m.at(0, 0)),
+            )
+        }
+    }
+
+    /// Extract the rotation from the upper 3x3 of `m`, which has to be a pure rotation
+    pub fn from_matrix(m: &Mat4x4) -> Self {
+        let trace = m.at(0, 0) + m.at(1, 1) + m.at(2, 2);
+        if trace > 0.0 {
+            let s = (trace + 1.0).sqrt();
+            let w = s / 2.0;
+            let s = 0.5 / s;
+            Self::new(
+                Vec3::new(
+                    (m.at(2, 1) - m.at(1, 2)) * s,
+                    (m.at(0, 2) - m.at(2, 0)) * s,
+                    (m.at(1, 0) - m.at(0, 1)) * s,
+                ),
+                w,
+            )
+        } else {
+            // Start from the largest diagonal element to keep the square root away from zero
+            let mut i = 0;
+            if m.at(1, 1) > m.at(0, 0) {
+                i = 1;
+            }
+            if m.at(2, 2) > m.at(i, i) {
+                i = 2;
+            }
+            let j = (i + 1) % 3;
+            let k = (j + 1) % 3;
+            let mut s = (m.at(i, i) - (m.at(j, j) + m.at(k, k)) + 1.0).sqrt();
+            let mut v = Vec3::ORIGIN;
+            v[i] = s * 0.5;
+            if s != 0.0 {
+                s = 0.5 / s;
+            }
+            v[j] = (m.at(j, i) + m.at(i, j)) * s;
+            v[k] = (m.at(k, i) + m.at(i, k)) * s;
+            Self::new(v, (m.at(k, j) - m.at(j, k)) * s)
+        }
+    }
+
+    /// The rotation matrix of a unit quaternion
+    pub fn to_matrix(&self) -> Mat4x4 {
+        let Vec3 { x, y, z } = self.v;
+        let w = self.w;
+        let (xx, yy, zz) = (x * x, y * y, z * z);
+        let (xy, xz, yz) = (x * y, x * z, y * z);
+        let (wx, wy, wz) = (w * x, w * y, w * z);
+        Mat4x4::new([
+            1.0 - 2.0 * (yy + zz),
+            2.0 * (xy - wz),
+            2.0 * (xz + wy),
+            0.0,
+            2.0 * (xy + wz),
+            1.0 - 2.0 * (xx + zz),
+            2.0 * (yz - wx),
+            0.0,
+            2.0 * (xz - wy),
+            2.0 * (yz + wx),
+            1.0 - 2.0 * (xx + yy),
+            0.0,
+            0.0,
+            0.0,
+            0.0,
+            1.0,
+        ])
+    }
+
+    /// The rotation as a transform, whose inverse is simply the transpose
+    pub fn to_transform(&self) -> Transform {
+        let mat = self.to_matrix();
+        Transform::new(mat, mat.transpose())
+    }
+
+    pub fn dot(&self, other: &Self) -> Float {
+        comb::dot(&self.v, &other.v) + self.w * other.w
+    }
+
+    pub fn length(&self) -> Float {
+        self.dot(self).sqrt()
+    }
+
+    pub fn normalized(&self) -> Self {
+        *self / self.length()
+    }
+
+    /// The inverse rotation of a unit quaternion
+    pub fn conjugate(&self) -> Self {
+        Self::new(-self.v, self.w)
+    }
+
+    /// Spherical linear interpolation between two unit quaternions, always taking the shortest
+    /// way around
+    pub fn slerp(t: Float, a: &Self, b: &Self) -> Self {
+        let mut cos_theta = a.dot(b);
+        // `b` and `-b` are the same rotation, pick the one on the same side as `a`
+        let b = if cos_theta < 0.0 {
+            cos_theta = -cos_theta;
+            -*b
+        } else {
+            *b
+        };
+        if cos_theta > 0.9995 {
+            // Nearly parallel, which would make `perpendicular` unstable
+            (*a * (1.0 - t) + b * t).normalized()
+        } else {
+            let theta = cos_theta.min(1.0).acos();
+            let perpendicular = (b - *a * cos_theta).normalized();
+            *a * (theta * t).cos() + perpendicular * (theta * t).sin()
+        }
+    }
+}
+
+impl std::ops::Add for Quaternion {
+    type Output = Quaternion;
+    fn add(self, rhs: Quaternion) -> Quaternion {
+        Quaternion::new(self.v + rhs.v, self.w + rhs.w)
+    }
+}
+
+impl std::ops::Sub for Quaternion {
+    type Output = Quaternion;
+    fn sub(self, rhs: Quaternion) -> Quaternion {
+        Quaternion::new(self.v - rhs.v, self.w - rhs.w)
+    }
+}
+
+impl std::ops::Neg for Quaternion {
+    type Output = Quaternion;
+    fn neg(self) -> Quaternion {
+        Quaternion::new(-self.v, -self.w)
+    }
+}
+
+/// The Hamilton product, rotating by `rhs` first and then by `self`
+impl std::ops::Mul for Quaternion {
+    type Output = Quaternion;
+    fn mul(self, rhs: Quaternion) -> Quaternion {
+        Quaternion::new(
+            rhs.v * self.w + self.v * rhs.w + comb::cross(&self.v, &rhs.v),
+            self.w * rhs.w - comb::dot(&self.v, &rhs.v),
+        )
+    }
+}
+
+impl std::ops::Mul<Float> for Quaternion {
+    type Output = Quaternion;
+    fn mul(self, rhs: Float) -> Quaternion {
+        Quaternion::new(self.v * rhs, self.w * rhs)
+    }
+}
+
+impl std::ops::Div<Float> for Quaternion {
+    type Output = Quaternion;
+    fn div(self, rhs: Float) -> Quaternion {
+        Quaternion::new(self.v / rhs, self.w / rhs)
+    }
+}
+
+impl From<Quaternion> for Transform {
+    fn from(q: Quaternion) -> Self {
+        q.to_transform()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOLERANCE: Float = 1e-5;
+
+    fn assert_mat_eq(a: &Mat4x4, b: &Mat4x4) {
+        for (x, y) in a.iter().zip(b.iter()) {
+            assert!((x - y).abs() < TOLERANCE, "{:?} != {:?}", a, b);
+        }
+    }
+
+    fn assert_same_rotation(a: &Quaternion, b: &Quaternion) {
+        assert!(
+            (a.dot(b).abs() - 1.0).abs() < TOLERANCE,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn matches_axis_rotations() {
+        assert_mat_eq(
+            &Quaternion::from_axis_angle(&Vec3::new(1.0, 0.0, 0.0), 0.3).to_matrix(),
+            &Transform::rotate_x(0.3).mat,
+        );
+        assert_mat_eq(
+            &Quaternion::from_axis_angle(&Vec3::new(0.0, 2.0, 0.0), -1.2).to_matrix(),
+            &Transform::rotate_y(-1.2).mat,
+        );
+        assert_mat_eq(
+            &Quaternion::from_euler(0.1, 0.2, 0.3).to_matrix(),
+            &(Transform::rotate_z(0.3) * Transform::rotate_y(0.2) * Transform::rotate_x(0.1)).mat,
+        );
+    }
+
+    #[test]
+    fn conversions() {
+        let axis = Vec3::new(1.0, -2.0, 0.5).normalized();
+        let q = Quaternion::from_axis_angle(&axis, 2.0);
+        let (back_axis, theta) = q.to_axis_angle();
+        assert!((back_axis - axis).length() < TOLERANCE);
+        assert!((theta - 2.0).abs() < TOLERANCE);
+
+        assert_same_rotation(&Quaternion::from_matrix(&q.to_matrix()), &q);
+        // Trace below zero
+        let half_turn = Quaternion::from_axis_angle(&Vec3::new(0.0, 1.0, 1.0), 3.0);
+        assert_same_rotation(&Quaternion::from_matrix(&half_turn.to_matrix()), &half_turn);
+
+        let euler = Quaternion::from_euler(0.4, -0.7, 2.5).to_euler();
+        assert!((euler - Vec3::new(0.4, -0.7, 2.5)).length() < TOLERANCE);
+        let locked = Quaternion::from_euler(0.4, float::consts::FRAC_PI_2, 0.0).to_euler();
+        assert!((locked - Vec3::new(0.4, float::consts::FRAC_PI_2, 0.0)).length() < 1e-3);
+    }
+
+    #[test]
+    fn slerp() {
+        let axis = Vec3::new(0.0, 0.0, 1.0);
+        let a = Quaternion::from_axis_angle(&axis, 0.2);
+        let b = Quaternion::from_axis_angle(&axis, 1.4);
+        assert_same_rotation(&Quaternion::slerp(0.0, &a, &b), &a);
+        assert_same_rotation(&Quaternion::slerp(1.0, &a, &b), &b);
+        assert_same_rotation(
+            &Quaternion::slerp(0.25, &a, &b),
+            &Quaternion::from_axis_angle(&axis, 0.5),
+        );
+        // The negated quaternion is the same rotation, interpolation shouldn't go the long way
+        assert_same_rotation(
+            &Quaternion::slerp(0.25, &a, &-b),
+            &Quaternion::from_axis_angle(&axis, 0.5),
+        );
+    }
+}
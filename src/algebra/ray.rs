@@ -6,10 +6,10 @@ pub struct Ray {
     pub origin: Point3,
     pub direction: Vec3,
     /// Time ray was cast at
-    pub time: f64,
+    pub time: Float,
     //pub medium: Box<dyn Medium + 'a>,
-    pub min_t: f64,
-    pub max_t: f64,
+    pub min_t: Float,
+    pub max_t: Float,
 }
 
 impl Default for Ray {
@@ -20,7 +20,7 @@ impl Default for Ray {
             time: 0.0,
             //medium: Box::new(HomogeneousMedium::default()),
             min_t: 0.0,
-            max_t: std::f64::INFINITY,
+            max_t: float::INFINITY,
         }
     }
 }
@@ -34,7 +34,7 @@ impl Ray {
         }
     }
 
-    pub fn new_with_time(origin: Point3, direction: Vec3, time: f64) -> Self {
+    pub fn new_with_time(origin: Point3, direction: Vec3, time: Float) -> Self {
         Self {
             time,
             ..Self::new(origin, direction)
@@ -42,6 +42,31 @@ impl Ray {
     }
 }
 
+/// Values derived from a ray which stay the same for every bounding box it is tested against.
+/// Computing these once per ray instead of once per box speeds up BVH traversal quite a bit.
+#[derive(Debug, Clone, Copy)]
+pub struct RayPrecompute {
+    pub inv_dir: Vec3,
+    /// Whether the direction is negative along each axis, so the near and far planes of a box
+    /// can be picked without comparing
+    pub dir_is_neg: [bool; 3],
+}
+
+impl RayPrecompute {
+    pub fn new(ray: &Ray) -> Self {
+        let inv_dir = Vec3::new(
+            1.0 / ray.direction.x,
+            1.0 / ray.direction.y,
+            1.0 / ray.direction.z,
+        );
+        Self {
+            inv_dir,
+            // Checking the inverse keeps the sign of -0.0
+            dir_is_neg: [inv_dir.x < 0.0, inv_dir.y < 0.0, inv_dir.z < 0.0],
+        }
+    }
+}
+
 impl Transformable for Ray {
     fn apply_t(self, trans: &Transform) -> Self {
         Ray {
@@ -1,4 +1,5 @@
 use crate::algebra::prelude::*;
+use crate::algebra::quaternion::Quaternion;
 
 #[derive(Debug, Clone)]
 pub struct Transform {
@@ -45,7 +46,7 @@ impl Transform {
         Self::new(mat, inv_mat)
     }
 
-    pub fn scaling(x: f64, y: f64, z: f64) -> Self {
+    pub fn scaling(x: Float, y: Float, z: Float) -> Self {
         let mat = Mat4x4::new([
             x, 0.0, 0.0, 0.0, //
             0.0, y, 0.0, 0.0, //
@@ -74,7 +75,7 @@ impl Transform {
         Self::new(mat, inv_mat)
     }
 
-    pub fn rotate_x(theta: f64) -> Self {
+    pub fn rotate_x(theta: Float) -> Self {
         let sin_theta = theta.sin();
         let cos_theta = theta.cos();
 
@@ -88,7 +89,7 @@ impl Transform {
         Self::new(mat, inv_mat)
     }
 
-    pub fn rotate_y(theta: f64) -> Self {
+    pub fn rotate_y(theta: Float) -> Self {
         let sin_theta = theta.sin();
         let cos_theta = theta.cos();
 
@@ -102,7 +103,7 @@ impl Transform {
         Self::new(mat, inv_mat)
     }
 
-    pub fn rotate_z(theta: f64) -> Self {
+    pub fn rotate_z(theta: Float) -> Self {
         let sin_theta = theta.sin();
         let cos_theta = theta.cos();
 
@@ -116,6 +117,34 @@ impl Transform {
         Self::new(mat, inv_mat)
     }
 
+    /// Rotation of `theta` radians about an arbitrary `axis`
+    pub fn rotate(axis: &Vec3, theta: Float) -> Self {
+        let a = axis.normalized();
+        let sin_theta = theta.sin();
+        let cos_theta = theta.cos();
+
+        let mat = Mat4x4::new([
+            a.x * a.x + (1.0 - a.x * a.x) * cos_theta,
+            a.x * a.y * (1.0 - cos_theta) - a.z * sin_theta,
+            a.x * a.z * (1.0 - cos_theta) + a.y * sin_theta,
+            0.0,
+            a.x * a.y * (1.0 - cos_theta) + a.z * sin_theta,
+            a.y * a.y + (1.0 - a.y * a.y) * cos_theta,
+            a.y * a.z * (1.0 - cos_theta) - a.x * sin_theta,
+            0.0,
+            a.x * a.z * (1.0 - cos_theta) - a.y * sin_theta,
+            a.y * a.z * (1.0 - cos_theta) + a.x * sin_theta,
+            a.z * a.z + (1.0 - a.z * a.z) * cos_theta,
+            0.0,
+            0.0,
+            0.0,
+            0.0,
+            1.0,
+        ]);
+        let inv_mat = mat.transpose();
+        Self::new(mat, inv_mat)
+    }
+
     pub fn look_at(pos: &Vec3, look: &Vec3, up: &Vec3) -> Self {
         let dir = (*look - *pos).normalized();
         let right = comb::cross(&up.normalized(), &dir.normalized());
@@ -132,7 +161,7 @@ impl Transform {
 
     /// Generate perspective transform
     /// `fov` is in degrees
-    pub fn perspective(fov: f64, n: f64, f: f64) -> Self {
+    pub fn perspective(fov: Float, n: Float, f: Float) -> Self {
         let persp = Mat4x4::new([
             1.0,
             0.0,
@@ -155,8 +184,140 @@ impl Transform {
         Self::scaling(inv_tan_ang, inv_tan_ang, 1.0) * Self::from_mat(persp)
     }
 
+    /// Transform a point on SIMD lanes. Like the lanes matrix product this is only an
+    /// alternative to `apply_t` for the algebra benchmark to compare against.
+    pub fn apply_lanes(&self, p: &Point3) -> Point3 {
+        let m = &self.mat;
+        let [x, y, z, w] =
+            (m.column(0) * p.x + m.column(1) * p.y + m.column(2) * p.z + m.column(3)).to_array();
+        if w == 1.0 {
+            Point3::new(x, y, z)
+        } else {
+            Point3::new(x, y, z) / w
+        }
+    }
+
     pub fn compose(self, rhs: &Self) -> Self {
-        Self::new(self.mat * rhs.mat, self.inv_mat * rhs.inv_mat)
+        // (AB)^-1 = B^-1 A^-1
+        Self::new(self.mat * rhs.mat, rhs.inv_mat * self.inv_mat)
+    }
+
+    /// Split into translation, rotation and scale, such that the transform is `T * R * S`.
+    /// Any shear ends up in the scale matrix. Projective transforms can't be decomposed.
+    pub fn decompose(&self) -> Decomposition {
+        let translation = Vec3::new(*self.mat.at(0, 3), *self.mat.at(1, 3), *self.mat.at(2, 3));
+
+        let mut m = self.mat;
+        for i in 0..3 {
+            *m.at_mut(i, 3) = 0.0;
+            *m.at_mut(3, i) = 0.0;
+        }
+        *m.at_mut(3, 3) = 1.0;
+
+        // Polar decomposition: averaging a matrix with its inverse transpose converges to
+        // the closest rotation
+        let mut r = m;
+        for _ in 0..100 {
+            let next = (r + r.inverse().transpose()) * 0.5;
+            let norm = (0..3)
+                .map(|i| {
+                    (0..3)
+                        .map(|j| (r.at(i, j) - next.at(i, j)).abs())
+                        .sum::<Float>()
+                })
+                .fold(0.0, Float::max);
+            r = next;
+            if norm < 1e-4 {
+                break;
+            }
+        }
+
+        Decomposition {
+            translation,
+            rotation: Quaternion::from_matrix(&r),
+            scale: r.inverse() * m,
+        }
+    }
+
+    /// Interpolate between two transforms by interpolating their decompositions, so rotations
+    /// stay rigid instead of shearing like a plain matrix interpolation would
+    pub fn interpolate(t: Float, a: &Self, b: &Self) -> Self {
+        Decomposition::interpolate(t, &a.decompose(), &b.decompose()).to_transform()
+    }
+}
+
+/// A transform split into its translation, rotation and scale, see `Transform::decompose`
+#[derive(Debug, Clone)]
+pub struct Decomposition {
+    pub translation: Vec3,
+    pub rotation: Quaternion,
+    /// Scale and possibly shear
+    pub scale: Mat4x4,
+}
+
+impl Decomposition {
+    /// Compose back into `T * R * S`
+    pub fn to_transform(&self) -> Transform {
+        Transform::translation(&self.translation)
+            * self.rotation.to_transform()
+            * Transform::from_mat(self.scale)
+    }
+
+    pub fn interpolate(t: Float, a: &Self, b: &Self) -> Self {
+        Self {
+            translation: comb::lerp(t, &a.translation, &b.translation),
+            rotation: Quaternion::slerp(t, &a.rotation, &b.rotation),
+            scale: comb::lerp(t, &a.scale, &b.scale),
+        }
+    }
+}
+
+/// A transform which changes from `start` to `end` over a span of time, for animation and
+/// motion blur
+#[derive(Debug, Clone)]
+pub struct AnimatedTransform {
+    pub start: Transform,
+    pub end: Transform,
+    pub start_time: Float,
+    pub end_time: Float,
+    /// Decompositions of `start` and `end`, kept around since decomposing is expensive
+    decomposed: Option<(Decomposition, Decomposition)>,
+}
+
+impl AnimatedTransform {
+    pub fn new(start: Transform, start_time: Float, end: Transform, end_time: Float) -> Self {
+        let decomposed = if start.mat != end.mat {
+            Some((start.decompose(), end.decompose()))
+        } else {
+            None
+        };
+        Self {
+            start,
+            end,
+            start_time,
+            end_time,
+            decomposed,
+        }
+    }
+
+    /// A transform which doesn't change over time
+    pub fn fixed(transform: Transform) -> Self {
+        Self::new(transform.clone(), 0.0, transform, 1.0)
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.decomposed.is_some()
+    }
+
+    /// The transform at `time`, which is clamped to the span of the animation
+    pub fn interpolate(&self, time: Float) -> Transform {
+        let (start, end) = match &self.decomposed {
+            Some((start, end)) if time > self.start_time && time < self.end_time => (start, end),
+            _ if time >= self.end_time => return self.end.clone(),
+            _ => return self.start.clone(),
+        };
+        let t = (time - self.start_time) / (self.end_time - self.start_time);
+        Decomposition::interpolate(t, start, end).to_transform()
     }
 }
 
@@ -193,4 +354,62 @@ mod tests {
         let transformed = a.apply_t(&both);
         assert_eq!(transformed, Point3::new(-1.0, 1.0, 0.0));
     }
+
+    #[test]
+    fn rotate_about_axis() {
+        let t = Transform::rotate(&Vec3::new(0.0, 0.0, 2.0), float::consts::FRAC_PI_2);
+        let p = Point3::new(1.0, 0.0, 0.0).apply_t(&t);
+        assert!((p - Point3::new(0.0, 1.0, 0.0)).length() < 1e-6);
+        let q = Transform::from(Quaternion::from_axis_angle(&Vec3::new(1.0, 1.0, 0.0), 0.8));
+        let r = Transform::rotate(&Vec3::new(1.0, 1.0, 0.0), 0.8);
+        for (a, b) in q.mat.iter().zip(r.mat.iter()) {
+            assert!((a - b).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn decomposition() {
+        let t = Transform::translation(&Vec3::new(3.0, -2.0, 1.0))
+            * Transform::rotate(&Vec3::new(1.0, 2.0, 3.0), 0.7)
+            * Transform::scaling(2.0, 1.0, 0.5);
+        let d = t.decompose();
+        assert_eq!(d.translation, Vec3::new(3.0, -2.0, 1.0));
+        let (axis, theta) = d.rotation.to_axis_angle();
+        assert!((axis - Vec3::new(1.0, 2.0, 3.0).normalized()).length() < 1e-4);
+        assert!((theta - 0.7).abs() < 1e-4);
+        for (a, b) in d.to_transform().mat.iter().zip(t.mat.iter()) {
+            assert!((a - b).abs() < 1e-4);
+        }
+
+        let start = Transform::translation(&Vec3::new(0.0, 0.0, 0.0));
+        let end = Transform::translation(&Vec3::new(2.0, 0.0, 0.0))
+            * Transform::rotate_z(float::consts::FRAC_PI_2);
+        let animated = AnimatedTransform::new(start, 1.0, end, 2.0);
+        assert!(animated.is_animated());
+        let halfway = Point3::new(1.0, 0.0, 0.0).apply_t(&animated.interpolate(1.5));
+        let expected = Point3::new(1.0 + 0.5f64.sqrt() as Float, 0.5f64.sqrt() as Float, 0.0);
+        assert!((halfway - expected).length() < 1e-4);
+        assert_eq!(animated.interpolate(5.0).mat, animated.end.mat);
+    }
+
+    #[test]
+    fn composition_inverse() {
+        let t = Transform::translation(&Vec3::new(3.0, -2.0, 1.0))
+            * Transform::rotate_y(0.7)
+            * Transform::scaling(2.0, 1.0, 0.5);
+        let p = Point3::new(1.0, 2.0, 3.0);
+        let back = p.apply_t(&t).apply_t(&t.clone().inverse());
+        assert!((back - p).length() < 100.0 * float::EPSILON);
+    }
+
+    #[test]
+    fn lanes_match_scalar() {
+        let t = Transform::translation(&Vec3::new(3.0, -2.0, 1.0))
+            * Transform::rotate_y(0.7)
+            * Transform::scaling(2.0, 1.0, 0.5);
+        let p = Point3::new(1.0, 2.0, 3.0);
+        assert!((t.apply_lanes(&p) - p.apply_t(&t)).length() < 100.0 * float::EPSILON);
+        let projected = Transform::perspective(60.0, 1.0, 100.0);
+        assert!((projected.apply_lanes(&p) - p.apply_t(&projected)).length() < 1e-4);
+    }
 }
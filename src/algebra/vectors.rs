@@ -2,16 +2,16 @@ use crate::algebra::prelude::*;
 use image::{Pixel, Rgb, Rgba};
 use serde_derive::{Deserialize, Serialize};
 
-/// A f64 2-dimensional Vector struct
+/// A 2-dimensional Vector struct
 /// For use with Pixels and sampling
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Vec2 {
-    pub x: f64,
-    pub y: f64,
+    pub x: Float,
+    pub y: Float,
 }
 
 impl Vec2 {
-    pub const fn new(x: f64, y: f64) -> Vec2 {
+    pub const fn new(x: Float, y: Float) -> Vec2 {
         Vec2 { x, y }
     }
 
@@ -20,13 +20,13 @@ impl Vec2 {
     }
 }
 
-/// A standard f64 3-dimensional Vector struct
+/// A standard 3-dimensional Vector struct
 /// For use everywhere
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Vec3 {
-    pub x: f64,
-    pub y: f64,
-    pub z: f64,
+    pub x: Float,
+    pub y: Float,
+    pub z: Float,
 }
 
 impl From<Point3> for Vec3 {
@@ -55,9 +55,9 @@ impl std::ops::Add<Vec3> for Vec3 {
     }
 }
 
-impl std::ops::Mul<f64> for Vec3 {
+impl std::ops::Mul<Float> for Vec3 {
     type Output = Vec3;
-    fn mul(self, rhs: f64) -> Vec3 {
+    fn mul(self, rhs: Float) -> Vec3 {
         Vec3::new(self.x * rhs, self.y * rhs, self.z * rhs)
     }
 }
@@ -69,16 +69,16 @@ impl std::ops::Mul<Vec3> for Vec3 {
     }
 }
 
-impl std::ops::Mul<Vec3> for f64 {
+impl std::ops::Mul<Vec3> for Float {
     type Output = Vec3;
     fn mul(self, rhs: Vec3) -> Vec3 {
         Vec3::new(rhs.x * self, rhs.y * self, rhs.z * self)
     }
 }
 
-impl std::ops::Div<f64> for Vec3 {
+impl std::ops::Div<Float> for Vec3 {
     type Output = Vec3;
-    fn div(self, rhs: f64) -> Vec3 {
+    fn div(self, rhs: Float) -> Vec3 {
         Vec3::new(self.x / rhs, self.y / rhs, self.z / rhs)
     }
 }
@@ -102,18 +102,18 @@ impl Vec3 {
         self.x.is_nan() || self.y.is_nan() || self.z.is_nan()
     }
 
-    pub const fn new(x: f64, y: f64, z: f64) -> Self {
+    pub const fn new(x: Float, y: Float, z: Float) -> Self {
         Vec3 { x, y, z }
     }
 
     /// The square of the magniture/length of the Vector
     /// x^2 + y^2 + z^2
-    pub fn length2(&self) -> f64 {
+    pub fn length2(&self) -> Float {
         self.x * self.x + self.y * self.y + self.z * self.z
     }
 
     /// The magnitude/length of a Vector
-    pub fn length(&self) -> f64 {
+    pub fn length(&self) -> Float {
         self.length2().sqrt()
     }
 
@@ -127,12 +127,12 @@ impl Vec3 {
     }
 
     /// The square of the distance between two Vectors
-    pub fn distance2(&self, other: &Vec3) -> f64 {
+    pub fn distance2(&self, other: &Vec3) -> Float {
         (self.x - other.x).powf(2.0) + (self.y - other.y).powf(2.0) + (self.z - other.z).powf(2.0)
     }
 
     /// The distance between two vectors
-    pub fn distance(&self, other: &Vec3) -> f64 {
+    pub fn distance(&self, other: &Vec3) -> Float {
         self.distance2(other).sqrt()
     }
 
@@ -142,13 +142,13 @@ impl Vec3 {
     }
 
     /// Map a function over each of the Vector's values
-    pub fn map_all(self, f: &impl Fn(f64) -> f64) -> Self {
+    pub fn map_all(self, f: &impl Fn(Float) -> Float) -> Self {
         Vec3::new(f(self.x), f(self.y), f(self.z))
     }
 
     pub fn from_rgb(rgb: Rgb<u8>) -> Self {
         if let [r, g, b] = rgb.channels() {
-            Vec3::new(f64::from(*r), f64::from(*g), f64::from(*b))
+            Vec3::new(Float::from(*r), Float::from(*g), Float::from(*b))
         } else {
             Vec3::ORIGIN
         }
@@ -160,7 +160,7 @@ impl Vec3 {
 
     pub fn from_rgba(rgba: Rgba<u8>) -> Self {
         if let [r, g, b, _] = rgba.channels() {
-            Vec3::new(f64::from(*r), f64::from(*g), f64::from(*b))
+            Vec3::new(Float::from(*r), Float::from(*g), Float::from(*b))
         } else {
             Vec3::ORIGIN
         }
@@ -183,9 +183,9 @@ impl Vec3 {
     }
     */
 
-    pub fn hemisphere(u: f64, v: f64) -> Vec3 {
+    pub fn hemisphere(u: Float, v: Float) -> Vec3 {
         let sin_theta = (1.0 - u * u).sqrt();
-        let phi = 2.0 * std::f64::consts::PI * v;
+        let phi = 2.0 * float::consts::PI * v;
         Vec3::new(phi.cos() * sin_theta, u, phi.sin() * sin_theta)
     }
 
@@ -224,7 +224,7 @@ impl Vec3 {
     }
 
     /// Extract a dimension from a Vector by it's number 0-2
-    pub fn dim(self, dimension: u32) -> f64 {
+    pub fn dim(self, dimension: u32) -> Float {
         match dimension {
             0 => self.x,
             1 => self.y,
@@ -246,7 +246,7 @@ impl Vec3 {
     /// Rotate a dimension from a Vector by it's number 0-2
     /// This function is particularly useful since it's very versatile in conjunction with flipping
     /// for some transformations
-    pub fn rotate_around(self, axis: u32, theta: f64) -> Self {
+    pub fn rotate_around(self, axis: u32, theta: Float) -> Self {
         match axis % 3 {
             0 => Vec3::new(
                 self.x,
@@ -267,7 +267,7 @@ impl Vec3 {
         }
     }
 
-    pub fn max_component(self) -> f64 {
+    pub fn max_component(self) -> Float {
         if self.x > self.y && self.x > self.z {
             self.x
         } else if self.y > self.z {
@@ -277,7 +277,7 @@ impl Vec3 {
         }
     }
 
-    pub fn min_component(self) -> f64 {
+    pub fn min_component(self) -> Float {
         if self.x < self.y && self.x < self.z {
             self.x
         } else if self.y < self.z {
@@ -317,7 +317,8 @@ impl Vertex {
 }
 
 impl std::ops::Index<usize> for Vec3 {
-    type Output = f64;
+    type Output = Float;
+    #[inline]
     fn index(&self, i: usize) -> &Self::Output {
         match i {
             0 => &self.x,
@@ -329,6 +330,7 @@ impl std::ops::Index<usize> for Vec3 {
 }
 
 impl std::ops::IndexMut<usize> for Vec3 {
+    #[inline]
     fn index_mut(&mut self, i: usize) -> &mut Self::Output {
         match i {
             0 => &mut self.x,
@@ -413,7 +415,11 @@ mod tests {
         );
         assert!(
             (Vec3::new(1.0, 1.0, 0.0).normalized()
-                - Vec3::new(2f64.sqrt() / 2.0, 2f64.sqrt() / 2.0, 0.0))
+                - Vec3::new(
+                    float::consts::FRAC_1_SQRT_2,
+                    float::consts::FRAC_1_SQRT_2,
+                    0.0
+                ))
             .length()
                 <= 0.0001
         );
@@ -445,5 +451,4 @@ mod tests {
         assert_eq!(a.dim(1), 2.0);
         assert_eq!(a.dim(2), 1.0);
     }
-
 }
@@ -1,4 +1,12 @@
 pub mod bsdf;
+pub mod coated;
+pub mod disney;
+pub mod fresnel;
+pub mod lambertian;
+pub mod measured;
+pub mod microfacet;
+pub mod oren_nayar;
+pub mod specular;
 
 use crate::algebra::prelude::*;
 use crate::core::spectrum::RGBSpectrum;
@@ -14,8 +22,167 @@ pub enum BxDFType {
     Specular,
 }
 
-pub trait BxDF {
+/// An incident direction sampled from a BxDF
+#[derive(Debug, Clone)]
+pub struct BxDFSample {
+    pub wi: Vec3,
+    /// The value of the BxDF for the sampled pair of directions
+    pub f: RGBSpectrum,
+    pub pdf: Float,
+    /// The types of the lobe which was sampled
+    pub types: EnumSet<BxDFType>,
+}
+
+/// A single lobe of scattering at a surface.
+///
+/// All directions are in the local shading frame, where the shading normal is the Z axis, and
+/// point away from the surface. Spectra are on the same 0-255 scale as textures.
+pub trait BxDF: std::fmt::Debug + Send + Sync {
     fn types(&self) -> EnumSet<BxDFType>;
 
+    /// The value for the pair of directions. Zero for delta distributions like perfect mirrors,
+    /// which can only be sampled.
     fn evaluate(&self, wo: &Vec3, wi: &Vec3) -> RGBSpectrum;
+
+    /// Sample an incident direction for `wo`. Defaults to a cosine weighted hemisphere on the
+    /// side of `wo`.
+    fn sample_f(&self, wo: &Vec3, u: &Point2) -> Option<BxDFSample> {
+        let mut wi = cosine_sample_hemisphere(u);
+        if wo.z < 0.0 {
+            wi.z = -wi.z;
+        }
+        let pdf = self.pdf(wo, &wi);
+        if pdf == 0.0 {
+            return None;
+        }
+        Some(BxDFSample {
+            f: self.evaluate(wo, &wi),
+            wi,
+            pdf,
+            types: self.types(),
+        })
+    }
+
+    /// The density with which `sample_f` picks `wi`
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> Float {
+        if same_hemisphere(wo, wi) {
+            abs_cos_theta(wi) * float::consts::FRAC_1_PI
+        } else {
+            0.0
+        }
+    }
+
+    /// Whether all of this BxDF's types are in `types`
+    fn matches(&self, types: EnumSet<BxDFType>) -> bool {
+        types.is_superset(self.types())
+    }
+}
+
+pub fn cos_theta(w: &Vec3) -> Float {
+    w.z
+}
+
+pub fn abs_cos_theta(w: &Vec3) -> Float {
+    w.z.abs()
+}
+
+pub fn cos2_theta(w: &Vec3) -> Float {
+    w.z * w.z
+}
+
+pub fn sin2_theta(w: &Vec3) -> Float {
+    (1.0 - cos2_theta(w)).max(0.0)
+}
+
+pub fn sin_theta(w: &Vec3) -> Float {
+    sin2_theta(w).sqrt()
+}
+
+pub fn tan_theta(w: &Vec3) -> Float {
+    sin_theta(w) / cos_theta(w)
+}
+
+pub fn tan2_theta(w: &Vec3) -> Float {
+    sin2_theta(w) / cos2_theta(w)
+}
+
+pub fn cos_phi(w: &Vec3) -> Float {
+    let sin_theta = sin_theta(w);
+    if sin_theta == 0.0 {
+        1.0
+    } else {
+        (w.x / sin_theta).clamp(-1.0, 1.0)
+    }
+}
+
+pub fn sin_phi(w: &Vec3) -> Float {
+    let sin_theta = sin_theta(w);
+    if sin_theta == 0.0 {
+        0.0
+    } else {
+        (w.y / sin_theta).clamp(-1.0, 1.0)
+    }
+}
+
+pub fn cos2_phi(w: &Vec3) -> Float {
+    cos_phi(w) * cos_phi(w)
+}
+
+pub fn sin2_phi(w: &Vec3) -> Float {
+    sin_phi(w) * sin_phi(w)
+}
+
+pub fn same_hemisphere(w: &Vec3, wp: &Vec3) -> bool {
+    w.z * wp.z > 0.0
+}
+
+/// Mirror `wo` about `n`
+pub fn reflect(wo: &Vec3, n: &Vec3) -> Vec3 {
+    -*wo + *n * (2.0 * comb::dot(wo, n))
+}
+
+/// Refract `wi` through a boundary with the normal `n` on its side, where `eta` is the ratio of
+/// the index of refraction on the side of `wi` to the one on the other side. Returns nothing on
+/// total internal reflection.
+pub fn refract(wi: &Vec3, n: &Vec3, eta: Float) -> Option<Vec3> {
+    let cos_theta_i = comb::dot(n, wi);
+    let sin2_theta_i = (1.0 - cos_theta_i * cos_theta_i).max(0.0);
+    let sin2_theta_t = eta * eta * sin2_theta_i;
+    if sin2_theta_t >= 1.0 {
+        return None;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+    Some(-*wi * eta + *n * (eta * cos_theta_i - cos_theta_t))
+}
+
+/// Map a uniform square to a uniform disk, keeping areas adjacent
+pub fn concentric_sample_disk(u: &Point2) -> Point2 {
+    let offset = Point2::new(2.0 * u.x - 1.0, 2.0 * u.y - 1.0);
+    if offset.x == 0.0 && offset.y == 0.0 {
+        return Point2::new(0.0, 0.0);
+    }
+    let (r, theta) = if offset.x.abs() > offset.y.abs() {
+        (offset.x, float::consts::FRAC_PI_4 * (offset.y / offset.x))
+    } else {
+        (
+            offset.y,
+            float::consts::FRAC_PI_2 - float::consts::FRAC_PI_4 * (offset.x / offset.y),
+        )
+    };
+    Point2::new(r * theta.cos(), r * theta.sin())
+}
+
+/// Directions about the Z axis with a density proportional to their cosine
+pub fn cosine_sample_hemisphere(u: &Point2) -> Vec3 {
+    let d = concentric_sample_disk(u);
+    let z = (1.0 - d.x * d.x - d.y * d.y).max(0.0).sqrt();
+    Vec3::new(d.x, d.y, z)
+}
+
+/// Directions with the same density everywhere on the sphere
+pub fn uniform_sample_sphere(u: &Point2) -> Vec3 {
+    let z = 1.0 - 2.0 * u.x;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * float::consts::PI * u.y;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
 }
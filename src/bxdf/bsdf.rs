@@ -1,9 +1,250 @@
 use crate::algebra::prelude::*;
+use crate::bxdf::{BxDF, BxDFSample, BxDFType};
+use crate::core::spectrum::RGBSpectrum;
+use enumset::EnumSet;
 
-use crate::bxdf::BxDF;
-
-pub struct BSDF<'a> {
-    pub bxdfs: &'a [&'a dyn BxDF],
+/// All the scattering at a surface point, made up of the BxDFs a material creates.
+///
+/// Directions passed in and returned are in world space, and are converted to the local shading
+/// frame for the BxDFs.
+#[derive(Debug)]
+pub struct BSDF {
+    pub bxdfs: Vec<Box<dyn BxDF>>,
     pub geometric_normal: Normal,
     pub shading_normal: Normal,
+    /// Tangents which together with the shading normal make up the local frame
+    ss: Vec3,
+    ts: Vec3,
+}
+
+impl BSDF {
+    pub fn new(geometric_normal: Normal, shading_normal: Normal) -> Self {
+        let (ss, ts) = comb::coordinate_system(&Vec3::from(shading_normal));
+        Self {
+            bxdfs: Vec::new(),
+            geometric_normal,
+            shading_normal,
+            ss,
+            ts,
+        }
+    }
+
+    pub fn add(&mut self, bxdf: Box<dyn BxDF>) {
+        self.bxdfs.push(bxdf);
+    }
+
+    pub fn with(mut self, bxdf: Box<dyn BxDF>) -> Self {
+        self.add(bxdf);
+        self
+    }
+
+    /// Amount of BxDFs which match `types`
+    pub fn components(&self, types: EnumSet<BxDFType>) -> usize {
+        self.bxdfs.iter().filter(|b| b.matches(types)).count()
+    }
+
+    pub fn world_to_local(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            comb::dot(v, &self.ss),
+            comb::dot(v, &self.ts),
+            comb::dot(v, &self.shading_normal),
+        )
+    }
+
+    pub fn local_to_world(&self, v: &Vec3) -> Vec3 {
+        self.ss * v.x + self.ts * v.y + Vec3::from(self.shading_normal) * v.z
+    }
+
+    /// Whether the directions are on the same side of the actual surface. The shading normal
+    /// can disagree, which would let light leak through.
+    fn reflects(&self, wo_world: &Vec3, wi_world: &Vec3) -> bool {
+        comb::dot(wi_world, &self.geometric_normal) * comb::dot(wo_world, &self.geometric_normal)
+            > 0.0
+    }
+
+    fn lobe_allowed(bxdf: &dyn BxDF, reflect: bool) -> bool {
+        let types = bxdf.types();
+        (reflect && types.contains(BxDFType::Reflection))
+            || (!reflect && types.contains(BxDFType::Transmission))
+    }
+
+    pub fn evaluate(
+        &self,
+        wo_world: &Vec3,
+        wi_world: &Vec3,
+        types: EnumSet<BxDFType>,
+    ) -> RGBSpectrum {
+        let wo = self.world_to_local(wo_world);
+        let wi = self.world_to_local(wi_world);
+        if wo.z == 0.0 {
+            return RGBSpectrum::BLACK;
+        }
+        let reflect = self.reflects(wo_world, wi_world);
+        self.bxdfs
+            .iter()
+            .filter(|b| b.matches(types) && Self::lobe_allowed(b.as_ref(), reflect))
+            .fold(RGBSpectrum::BLACK, |acc, b| acc + b.evaluate(&wo, &wi))
+    }
+
+    /// Sample an incident direction by picking one of the matching BxDFs with `u.x` and
+    /// sampling it. The returned value and density account for all the matching BxDFs, unless
+    /// the picked one is specular.
+    pub fn sample_f(
+        &self,
+        wo_world: &Vec3,
+        u: &Point2,
+        types: EnumSet<BxDFType>,
+    ) -> Option<BxDFSample> {
+        let matching = self.components(types);
+        if matching == 0 {
+            return None;
+        }
+        let picked = ((u.x * matching as Float) as usize).min(matching - 1);
+        let bxdf = self.bxdfs.iter().filter(|b| b.matches(types)).nth(picked)?;
+        // Reuse the part of `u.x` which wasn't needed to pick the BxDF
+        let remapped = Point2::new(
+            (u.x * matching as Float - picked as Float).min(1.0 - float::EPSILON),
+            u.y,
+        );
+
+        let wo = self.world_to_local(wo_world);
+        if wo.z == 0.0 {
+            return None;
+        }
+        let mut sample = bxdf.sample_f(&wo, &remapped)?;
+        if sample.pdf == 0.0 {
+            return None;
+        }
+        let wi_world = self.local_to_world(&sample.wi);
+
+        if !sample.types.contains(BxDFType::Specular) && matching > 1 {
+            let reflect = self.reflects(wo_world, &wi_world);
+            let mut f = RGBSpectrum::BLACK;
+            for (i, b) in self.bxdfs.iter().filter(|b| b.matches(types)).enumerate() {
+                if i != picked {
+                    sample.pdf += b.pdf(&wo, &sample.wi);
+                }
+                if Self::lobe_allowed(b.as_ref(), reflect) {
+                    f += b.evaluate(&wo, &sample.wi);
+                }
+            }
+            sample.f = f;
+        }
+        if matching > 1 {
+            sample.pdf /= matching as Float;
+        }
+        sample.wi = wi_world;
+        Some(sample)
+    }
+
+    pub fn pdf(&self, wo_world: &Vec3, wi_world: &Vec3, types: EnumSet<BxDFType>) -> Float {
+        let matching = self.components(types);
+        if matching == 0 {
+            return 0.0;
+        }
+        let wo = self.world_to_local(wo_world);
+        let wi = self.world_to_local(wi_world);
+        if wo.z == 0.0 {
+            return 0.0;
+        }
+        let total: Float = self
+            .bxdfs
+            .iter()
+            .filter(|b| b.matches(types))
+            .map(|b| b.pdf(&wo, &wi))
+            .sum();
+        total / matching as Float
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+
+    /// The fraction of light scattered towards `wo` by a BSDF whose normal is the Z axis,
+    /// estimated by sampling from a grid. Also checks that the sampled densities agree with
+    /// `pdf`.
+    pub fn sampled_albedo(bsdf: &BSDF, wo: &Vec3) -> f64 {
+        let n = 64;
+        let mut sum = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let u = Point2::new(
+                    (i as Float + 0.5) / n as Float,
+                    (j as Float + 0.5) / n as Float,
+                );
+                if let Some(sample) = bsdf.sample_f(wo, &u, EnumSet::all()) {
+                    let pdf = bsdf.pdf(wo, &sample.wi, EnumSet::all());
+                    assert!((sample.pdf - pdf).abs() <= 1e-2 * pdf, "{:?}", sample);
+                    sum += sample.f[0] / 255.0 * float::to_f64(sample.wi.z.abs() / sample.pdf);
+                }
+            }
+        }
+        sum / f64::from(n * n)
+    }
+
+    /// The same by integrating over a grid on the sphere of directions
+    pub fn integrated_albedo(bsdf: &BSDF, wo: &Vec3) -> f64 {
+        let n = 256;
+        let d_omega = 4.0 * float::consts::PI / (2 * n * n) as Float;
+        let mut sum = 0.0;
+        for i in 0..2 * n {
+            let cos_theta = (i as Float + 0.5) / n as Float - 1.0;
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            for j in 0..n {
+                let phi = 2.0 * float::consts::PI * (j as Float + 0.5) / n as Float;
+                let wi = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+                sum += bsdf.evaluate(wo, &wi, EnumSet::all())[0] / 255.0
+                    * float::to_f64(cos_theta.abs() * d_omega);
+            }
+        }
+        sum
+    }
+
+    /// Check that sampling and evaluating agree, returning the albedo
+    pub fn check_albedo(bsdf: &BSDF, wo: &Vec3) -> f64 {
+        let sampled = sampled_albedo(bsdf, wo);
+        let integrated = integrated_albedo(bsdf, wo);
+        assert!(
+            (sampled - integrated).abs() < 0.02,
+            "{} != {}",
+            sampled,
+            integrated
+        );
+        sampled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bxdf::lambertian::LambertianReflection;
+
+    #[test]
+    fn local_frame() {
+        let n = Normal::new(0.3, -0.8, 0.2).normalized();
+        let bsdf = BSDF::new(n, n);
+        let v = Vec3::new(0.5, 1.0, -2.0);
+        assert!((bsdf.local_to_world(&bsdf.world_to_local(&v)) - v).length() < 1e-5);
+        let z = bsdf.world_to_local(&Vec3::from(n));
+        assert!((z - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-5);
+    }
+
+    #[test]
+    fn lambertian_estimate() {
+        let n = Normal::new(0.0, 1.0, 0.0);
+        let bsdf = BSDF::new(n, n).with(Box::new(LambertianReflection::new(
+            RGBSpectrum::from_rgb(255.0, 128.0, 0.0),
+        )));
+        let wo = Vec3::new(0.2, 1.0, 0.1).normalized();
+        let sample = bsdf
+            .sample_f(&wo, &Point2::new(0.3, 0.7), EnumSet::all())
+            .unwrap();
+        assert!(comb::dot(&sample.wi, &n) > 0.0);
+        // With cosine sampling the estimate is the reflectance itself
+        let cos = comb::dot(&sample.wi, &n).abs();
+        let estimate = sample.f * float::to_f64(cos / sample.pdf);
+        assert!((estimate[0] - 255.0).abs() < 1e-3);
+        assert!((estimate[1] - 128.0).abs() < 1e-3);
+    }
 }
@@ -0,0 +1,85 @@
+// Layering of a base BxDF under a clear dielectric coat.
+//
+// Light only reaches the base through the coat, so the base's contribution is scaled by the
+// fraction the Fresnel equations let through on the way in and on the way out. The light the
+// coat reflects is handled by a separate specular or glossy lobe. Light which bounces between
+// the coat and the base is ignored, so the layers together never reflect more than the base
+// alone would, though they come out slightly darker than a real coated surface.
+
+use crate::algebra::prelude::*;
+use crate::bxdf::{abs_cos_theta, fresnel, BxDF, BxDFSample, BxDFType};
+use crate::core::spectrum::RGBSpectrum;
+use enumset::EnumSet;
+
+#[derive(Debug)]
+pub struct CoatedBxDF {
+    pub base: Box<dyn BxDF>,
+    /// Index of refraction of the coat
+    pub eta: Float,
+}
+
+impl CoatedBxDF {
+    pub fn new(base: Box<dyn BxDF>, eta: Float) -> Self {
+        Self { base, eta }
+    }
+
+    /// The fraction of light which makes it through the coat to the base and back
+    fn transmittance(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        let to = 1.0 - fresnel::dielectric(abs_cos_theta(wo), 1.0, self.eta);
+        let ti = 1.0 - fresnel::dielectric(abs_cos_theta(wi), 1.0, self.eta);
+        float::to_f64(to * ti)
+    }
+}
+
+impl BxDF for CoatedBxDF {
+    fn types(&self) -> EnumSet<BxDFType> {
+        self.base.types()
+    }
+
+    fn evaluate(&self, wo: &Vec3, wi: &Vec3) -> RGBSpectrum {
+        self.base.evaluate(wo, wi) * self.transmittance(wo, wi)
+    }
+
+    fn sample_f(&self, wo: &Vec3, u: &Point2) -> Option<BxDFSample> {
+        let mut sample = self.base.sample_f(wo, u)?;
+        sample.f = sample.f * self.transmittance(wo, &sample.wi);
+        Some(sample)
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> Float {
+        self.base.pdf(wo, wi)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bxdf::bsdf::testing::check_albedo;
+    use crate::bxdf::bsdf::BSDF;
+    use crate::bxdf::fresnel::FresnelDielectric;
+    use crate::bxdf::lambertian::LambertianReflection;
+    use crate::bxdf::microfacet::{MicrofacetReflection, TrowbridgeReitz};
+
+    #[test]
+    fn coat_conserves_energy() {
+        let white = RGBSpectrum::from_rgb(255.0, 255.0, 255.0);
+        let n = Normal::new(0.0, 0.0, 1.0);
+        let bsdf = BSDF::new(n, n)
+            .with(Box::new(MicrofacetReflection::new(
+                white,
+                Box::new(TrowbridgeReitz::new(0.2, 0.2)),
+                Box::new(FresnelDielectric::new(1.0, 1.5)),
+            )))
+            .with(Box::new(CoatedBxDF::new(
+                Box::new(LambertianReflection::new(white)),
+                1.5,
+            )));
+        for wo in &[
+            Vec3::new(0.1, 0.2, 0.9).normalized(),
+            Vec3::new(0.8, -0.3, 0.2).normalized(),
+        ] {
+            let albedo = check_albedo(&bsdf, wo);
+            assert!(albedo > 0.5 && albedo <= 1.0, "{}", albedo);
+        }
+    }
+}
@@ -0,0 +1,283 @@
+// The lobes of the Disney principled BSDF, from Burley's "Physically Based Shading at Disney"
+// and its 2015 extension with transmission, as structured in pbrt.
+//
+// The specular reflection and transmission reuse the microfacet BxDFs, with a distribution and
+// Fresnel term which blend the way Disney's model does.
+
+use crate::algebra::prelude::*;
+use crate::bxdf::fresnel::{self, schlick_weight, Fresnel};
+use crate::bxdf::microfacet::{MicrofacetDistribution, TrowbridgeReitz};
+use crate::bxdf::{abs_cos_theta, reflect, same_hemisphere, BxDF, BxDFSample, BxDFType};
+use crate::core::spectrum::RGBSpectrum;
+use enumset::EnumSet;
+
+/// The half vector between two directions, if they aren't opposite
+fn half_vector(wo: &Vec3, wi: &Vec3) -> Option<Vec3> {
+    let wh = *wi + *wo;
+    if wh.x == 0.0 && wh.y == 0.0 && wh.z == 0.0 {
+        None
+    } else {
+        Some(wh.normalized())
+    }
+}
+
+/// Lambertian diffuse with a darkening towards grazing angles
+#[derive(Debug, Clone)]
+pub struct DisneyDiffuse {
+    pub r: RGBSpectrum,
+}
+
+impl DisneyDiffuse {
+    pub fn new(r: RGBSpectrum) -> Self {
+        Self { r }
+    }
+}
+
+impl BxDF for DisneyDiffuse {
+    fn types(&self) -> EnumSet<BxDFType> {
+        BxDFType::Reflection | BxDFType::Diffuse
+    }
+
+    fn evaluate(&self, wo: &Vec3, wi: &Vec3) -> RGBSpectrum {
+        let fo = schlick_weight(abs_cos_theta(wo));
+        let fi = schlick_weight(abs_cos_theta(wi));
+        self.r * float::to_f64(float::consts::FRAC_1_PI * (1.0 - fo / 2.0) * (1.0 - fi / 2.0))
+    }
+}
+
+/// Retro-reflection of rough diffuse surfaces, which brightens them towards grazing angles
+#[derive(Debug, Clone)]
+pub struct DisneyRetro {
+    pub r: RGBSpectrum,
+    pub roughness: Float,
+}
+
+impl DisneyRetro {
+    pub fn new(r: RGBSpectrum, roughness: Float) -> Self {
+        Self { r, roughness }
+    }
+}
+
+impl BxDF for DisneyRetro {
+    fn types(&self) -> EnumSet<BxDFType> {
+        BxDFType::Reflection | BxDFType::Diffuse
+    }
+
+    fn evaluate(&self, wo: &Vec3, wi: &Vec3) -> RGBSpectrum {
+        let wh = match half_vector(wo, wi) {
+            Some(wh) => wh,
+            None => return RGBSpectrum::BLACK,
+        };
+        let cos_theta_d = comb::dot(wi, &wh);
+        let fo = schlick_weight(abs_cos_theta(wo));
+        let fi = schlick_weight(abs_cos_theta(wi));
+        let rr = 2.0 * self.roughness * cos_theta_d * cos_theta_d;
+        self.r * float::to_f64(float::consts::FRAC_1_PI * rr * (fo + fi + fo * fi * (rr - 1.0)))
+    }
+}
+
+/// A soft glow at grazing angles, for cloth
+#[derive(Debug, Clone)]
+pub struct DisneySheen {
+    pub r: RGBSpectrum,
+}
+
+impl DisneySheen {
+    pub fn new(r: RGBSpectrum) -> Self {
+        Self { r }
+    }
+}
+
+impl BxDF for DisneySheen {
+    fn types(&self) -> EnumSet<BxDFType> {
+        BxDFType::Reflection | BxDFType::Diffuse
+    }
+
+    fn evaluate(&self, wo: &Vec3, wi: &Vec3) -> RGBSpectrum {
+        match half_vector(wo, wi) {
+            Some(wh) => self.r * float::to_f64(schlick_weight(comb::dot(wi, &wh))),
+            None => RGBSpectrum::BLACK,
+        }
+    }
+}
+
+/// The Generalized-Trowbridge-Reitz distribution with an exponent of 1, whose long tail suits
+/// clearcoats
+fn gtr1(cos_theta: Float, alpha: Float) -> Float {
+    let alpha2 = alpha * alpha;
+    (alpha2 - 1.0)
+        / (float::consts::PI * alpha2.ln() * (1.0 + (alpha2 - 1.0) * cos_theta * cos_theta))
+}
+
+/// Smith's masking for GGX, divided by `2 cos_theta`
+fn smith_g_ggx(cos_theta: Float, alpha: Float) -> Float {
+    let alpha2 = alpha * alpha;
+    let cos2_theta = cos_theta * cos_theta;
+    1.0 / (cos_theta + (alpha2 + cos2_theta - alpha2 * cos2_theta).sqrt())
+}
+
+/// A colorless specular layer on top of everything else, like car paint
+#[derive(Debug, Clone)]
+pub struct DisneyClearcoat {
+    pub weight: Float,
+    /// The alpha of the distribution
+    pub gloss: Float,
+}
+
+impl DisneyClearcoat {
+    pub fn new(weight: Float, gloss: Float) -> Self {
+        Self { weight, gloss }
+    }
+}
+
+impl BxDF for DisneyClearcoat {
+    fn types(&self) -> EnumSet<BxDFType> {
+        BxDFType::Reflection | BxDFType::Glossy
+    }
+
+    fn evaluate(&self, wo: &Vec3, wi: &Vec3) -> RGBSpectrum {
+        let wh = match half_vector(wo, wi) {
+            Some(wh) => wh,
+            None => return RGBSpectrum::BLACK,
+        };
+        // The coat has a fixed index of refraction of 1.5 and roughness of 0.25
+        let d = gtr1(abs_cos_theta(&wh), self.gloss);
+        let f = fresnel::schlick(0.04, comb::dot(wo, &wh));
+        let g = smith_g_ggx(abs_cos_theta(wo), 0.25) * smith_g_ggx(abs_cos_theta(wi), 0.25);
+        let value = float::to_f64(self.weight * g * f * d / 4.0) * 255.0;
+        RGBSpectrum::from_rgb(value, value, value)
+    }
+
+    fn sample_f(&self, wo: &Vec3, u: &Point2) -> Option<BxDFSample> {
+        if wo.z == 0.0 {
+            return None;
+        }
+        let alpha2 = self.gloss * self.gloss;
+        let cos_theta = ((1.0 - alpha2.powf(1.0 - u.x)) / (1.0 - alpha2))
+            .max(0.0)
+            .sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * float::consts::PI * u.y;
+        let wh = comb::spherical_direction(sin_theta, cos_theta, phi);
+        let wh = if same_hemisphere(wo, &wh) { wh } else { -wh };
+        let wi = reflect(wo, &wh);
+        let pdf = self.pdf(wo, &wi);
+        if pdf == 0.0 {
+            return None;
+        }
+        Some(BxDFSample {
+            f: self.evaluate(wo, &wi),
+            wi,
+            pdf,
+            types: self.types(),
+        })
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> Float {
+        if !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+        match half_vector(wo, wi) {
+            Some(wh) => {
+                let d = gtr1(abs_cos_theta(&wh), self.gloss);
+                d * abs_cos_theta(&wh) / (4.0 * comb::dot(wo, &wh))
+            }
+            None => 0.0,
+        }
+    }
+}
+
+/// Trowbridge-Reitz, but with the masking for both directions taken as independent, which is
+/// what Disney's model was fit with
+#[derive(Debug, Clone)]
+pub struct DisneyMicrofacetDistribution {
+    pub inner: TrowbridgeReitz,
+}
+
+impl DisneyMicrofacetDistribution {
+    pub fn new(alpha_x: Float, alpha_y: Float) -> Self {
+        Self {
+            inner: TrowbridgeReitz::new(alpha_x, alpha_y),
+        }
+    }
+}
+
+impl MicrofacetDistribution for DisneyMicrofacetDistribution {
+    fn d(&self, wh: &Vec3) -> Float {
+        self.inner.d(wh)
+    }
+
+    fn lambda(&self, w: &Vec3) -> Float {
+        self.inner.lambda(w)
+    }
+
+    fn g(&self, wo: &Vec3, wi: &Vec3) -> Float {
+        self.g1(wo) * self.g1(wi)
+    }
+
+    fn sample_wh(&self, wo: &Vec3, u: &Point2) -> Vec3 {
+        self.inner.sample_wh(wo, u)
+    }
+}
+
+/// Blends between the Fresnel reflectance of a dielectric and Schlick's approximation for a
+/// metal with the reflectance `r0` head on
+#[derive(Debug, Clone)]
+pub struct DisneyFresnel {
+    pub r0: RGBSpectrum,
+    pub metallic: Float,
+    pub eta: Float,
+}
+
+impl DisneyFresnel {
+    pub fn new(r0: RGBSpectrum, metallic: Float, eta: Float) -> Self {
+        Self { r0, metallic, eta }
+    }
+}
+
+impl Fresnel for DisneyFresnel {
+    fn evaluate(&self, cos_theta_i: Float) -> RGBSpectrum {
+        let dielectric = float::to_f64(fresnel::dielectric(cos_theta_i, 1.0, self.eta)) * 255.0;
+        let weight = float::to_f64(schlick_weight(cos_theta_i));
+        let metallic = float::to_f64(self.metallic);
+        self.r0
+            .iter()
+            .map(|r0| {
+                let schlick = r0 + (255.0 - r0) * weight;
+                dielectric * (1.0 - metallic) + schlick * metallic
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bxdf::bsdf::testing::check_albedo;
+    use crate::bxdf::bsdf::BSDF;
+    use crate::bxdf::microfacet::MicrofacetReflection;
+
+    #[test]
+    fn lobes_sample_what_they_evaluate() {
+        let color = RGBSpectrum::from_rgb(200.0, 100.0, 50.0);
+        let n = Normal::new(0.0, 0.0, 1.0);
+        let bsdf = BSDF::new(n, n)
+            .with(Box::new(DisneyDiffuse::new(color)))
+            .with(Box::new(DisneyRetro::new(color, 0.6)))
+            .with(Box::new(DisneySheen::new(color * 0.5)))
+            .with(Box::new(MicrofacetReflection::new(
+                RGBSpectrum::from_rgb(255.0, 255.0, 255.0),
+                Box::new(DisneyMicrofacetDistribution::new(0.3, 0.2)),
+                Box::new(DisneyFresnel::new(color * 0.04, 0.3, 1.5)),
+            )))
+            .with(Box::new(DisneyClearcoat::new(0.8, 0.1)));
+        for wo in &[
+            Vec3::new(0.1, 0.2, 0.9).normalized(),
+            Vec3::new(0.8, -0.3, 0.2).normalized(),
+        ] {
+            // Retro-reflection and sheen can add up to a little over 1 at grazing angles
+            let albedo = check_albedo(&bsdf, wo);
+            assert!(albedo > 0.2 && albedo < 1.1, "{}", albedo);
+        }
+    }
+}
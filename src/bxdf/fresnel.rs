@@ -0,0 +1,168 @@
+// The Fresnel equations, which give the fraction of light reflected at the boundary between
+// two media. The rest is transmitted through the boundary.
+
+use crate::algebra::prelude::*;
+use crate::core::spectrum::RGBSpectrum;
+
+/// Fresnel reflectance as used by the microfacet BxDFs. The result is on the same 0-255 scale
+/// as other spectra, where 255 means all light is reflected, so it can be combined with
+/// `RGBSpectrum::mul_with`.
+pub trait Fresnel: std::fmt::Debug + Send + Sync {
+    fn evaluate(&self, cos_theta_i: Float) -> RGBSpectrum;
+}
+
+/// The boundary between two dielectrics, see `dielectric`
+#[derive(Debug, Clone)]
+pub struct FresnelDielectric {
+    pub eta_i: Float,
+    pub eta_t: Float,
+}
+
+impl FresnelDielectric {
+    pub fn new(eta_i: Float, eta_t: Float) -> Self {
+        Self { eta_i, eta_t }
+    }
+}
+
+impl Fresnel for FresnelDielectric {
+    fn evaluate(&self, cos_theta_i: Float) -> RGBSpectrum {
+        let f = float::to_f64(dielectric(cos_theta_i, self.eta_i, self.eta_t)) * 255.0;
+        RGBSpectrum::from_rgb(f, f, f)
+    }
+}
+
+/// The boundary between a dielectric and a conductor with a complex index of refraction
+/// `eta + i k` per color channel, relative to the dielectric
+#[derive(Debug, Clone)]
+pub struct FresnelConductor {
+    pub eta: [Float; 3],
+    pub k: [Float; 3],
+}
+
+impl FresnelConductor {
+    pub const GOLD: Self = Self::new([0.18299, 0.42108, 1.37340], [3.42420, 2.34590, 1.77040]);
+    pub const COPPER: Self = Self::new([0.27105, 0.67693, 1.31640], [3.60920, 2.62480, 2.29210]);
+    pub const ALUMINIUM: Self = Self::new([1.34560, 0.96521, 0.61722], [7.47460, 6.39950, 5.30310]);
+
+    pub const fn new(eta: [Float; 3], k: [Float; 3]) -> Self {
+        Self { eta, k }
+    }
+}
+
+impl Fresnel for FresnelConductor {
+    fn evaluate(&self, cos_theta_i: Float) -> RGBSpectrum {
+        let cos_theta_i = cos_theta_i.abs();
+        self.eta
+            .iter()
+            .zip(self.k.iter())
+            .map(|(eta, k)| float::to_f64(conductor(cos_theta_i, *eta, *k)) * 255.0)
+            .collect()
+    }
+}
+
+/// Reflects everything, for materials which are tinted by their color alone
+#[derive(Debug, Clone)]
+pub struct FresnelNoOp;
+
+impl Fresnel for FresnelNoOp {
+    fn evaluate(&self, _cos_theta_i: Float) -> RGBSpectrum {
+        RGBSpectrum::from_rgb(255.0, 255.0, 255.0)
+    }
+}
+
+/// Reflectance for unpolarized light at the boundary between two dielectrics, with indices of
+/// refraction `eta_i` on the side of the normal and `eta_t` on the other. A negative
+/// `cos_theta_i` means the light arrives from the `eta_t` side.
+pub fn dielectric(cos_theta_i: Float, eta_i: Float, eta_t: Float) -> Float {
+    let cos_theta_i = cos_theta_i.clamp(-1.0, 1.0);
+    let (cos_theta_i, eta_i, eta_t) = if cos_theta_i > 0.0 {
+        (cos_theta_i, eta_i, eta_t)
+    } else {
+        (-cos_theta_i, eta_t, eta_i)
+    };
+
+    // Snell's law
+    let sin_theta_i = (1.0 - cos_theta_i * cos_theta_i).max(0.0).sqrt();
+    let sin_theta_t = eta_i / eta_t * sin_theta_i;
+    if sin_theta_t >= 1.0 {
+        // Total internal reflection
+        return 1.0;
+    }
+    let cos_theta_t = (1.0 - sin_theta_t * sin_theta_t).max(0.0).sqrt();
+
+    let parallel =
+        (eta_t * cos_theta_i - eta_i * cos_theta_t) / (eta_t * cos_theta_i + eta_i * cos_theta_t);
+    let perpendicular =
+        (eta_i * cos_theta_i - eta_t * cos_theta_t) / (eta_i * cos_theta_i + eta_t * cos_theta_t);
+    (parallel * parallel + perpendicular * perpendicular) / 2.0
+}
+
+/// Reflectance for unpolarized light at the boundary between a dielectric and a conductor, for
+/// the conductor's index of refraction `eta + i k` relative to the dielectric
+pub fn conductor(cos_theta_i: Float, eta: Float, k: Float) -> Float {
+    let cos_theta_i = cos_theta_i.clamp(-1.0, 1.0);
+    let cos2_theta_i = cos_theta_i * cos_theta_i;
+    let sin2_theta_i = 1.0 - cos2_theta_i;
+    let eta2 = eta * eta;
+    let k2 = k * k;
+
+    let t0 = eta2 - k2 - sin2_theta_i;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2_theta_i;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_theta_i * a;
+    let perpendicular = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2_theta_i * a2_plus_b2 + sin2_theta_i * sin2_theta_i;
+    let t4 = t2 * sin2_theta_i;
+    let parallel = perpendicular * (t3 - t4) / (t3 + t4);
+
+    (parallel + perpendicular) / 2.0
+}
+
+/// `(1 - cos)^5`, the angular falloff of Schlick's approximation
+pub fn schlick_weight(cos_theta: Float) -> Float {
+    let m = (1.0 - cos_theta).clamp(0.0, 1.0);
+    (m * m) * (m * m) * m
+}
+
+/// Schlick's approximation of the Fresnel reflectance, from the reflectance `r0` head on
+pub fn schlick(r0: Float, cos_theta: Float) -> Float {
+    r0 + (1.0 - r0) * schlick_weight(cos_theta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dielectric_reflectance() {
+        // ((1 - 1.5) / (1 + 1.5))^2 head on, from either side
+        assert!((dielectric(1.0, 1.0, 1.5) - 0.04).abs() < 1e-5);
+        assert!((dielectric(-1.0, 1.5, 1.0) - 0.04).abs() < 1e-5);
+        // Everything is reflected at grazing angles
+        assert!((dielectric(0.0, 1.0, 1.5) - 1.0).abs() < 1e-5);
+        // Past the critical angle of about 41.8 degrees leaving glass
+        assert_eq!(
+            dielectric(-(50.0 as Float).to_radians().cos(), 1.0, 1.5),
+            1.0
+        );
+        assert!(dielectric(-(30.0 as Float).to_radians().cos(), 1.0, 1.5) < 1.0);
+    }
+
+    #[test]
+    fn conductor_reflectance() {
+        // Without absorption it's the same as a dielectric
+        for &cos in &[1.0, 0.7, 0.2] {
+            assert!((conductor(cos, 1.5, 0.0) - dielectric(cos, 1.0, 1.5)).abs() < 1e-4);
+        }
+        // ((n - 1)^2 + k^2) / ((n + 1)^2 + k^2) head on
+        let gold = FresnelConductor::GOLD;
+        let expected = ((gold.eta[0] - 1.0).powi(2) + gold.k[0].powi(2))
+            / ((gold.eta[0] + 1.0).powi(2) + gold.k[0].powi(2));
+        assert!((conductor(1.0, gold.eta[0], gold.k[0]) - expected).abs() < 1e-4);
+        // Gold is yellow
+        let f = gold.evaluate(1.0);
+        assert!(f[0] > f[1] && f[1] > f[2]);
+    }
+}
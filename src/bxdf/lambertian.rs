@@ -0,0 +1,26 @@
+use crate::algebra::prelude::*;
+use crate::bxdf::{BxDF, BxDFType};
+use crate::core::spectrum::RGBSpectrum;
+use enumset::EnumSet;
+
+/// Perfectly diffuse reflection, scattering equally in all directions
+#[derive(Debug, Clone)]
+pub struct LambertianReflection {
+    pub r: RGBSpectrum,
+}
+
+impl LambertianReflection {
+    pub fn new(r: RGBSpectrum) -> Self {
+        Self { r }
+    }
+}
+
+impl BxDF for LambertianReflection {
+    fn types(&self) -> EnumSet<BxDFType> {
+        BxDFType::Reflection | BxDFType::Diffuse
+    }
+
+    fn evaluate(&self, _wo: &Vec3, _wi: &Vec3) -> RGBSpectrum {
+        self.r * std::f64::consts::FRAC_1_PI
+    }
+}
@@ -0,0 +1,312 @@
+// Measured BRDFs in the binary format of the MERL BRDF database.
+//
+// A file holds an isotropic BRDF tabulated over the half and difference angles of Rusinkiewicz's
+// parameterization. The elevation of the half vector is spaced by its square root, which puts
+// more of the samples near the specular peak, and the difference in azimuth only covers half a
+// circle because of reciprocity. Lookups interpolate linearly between the samples.
+//
+// For sampling, the BRDF times the cosine is tabulated for a set of outgoing elevations, as a
+// distribution over the incoming elevation and the difference in azimuth.
+
+use crate::algebra::prelude::*;
+use crate::bxdf::{same_hemisphere, BxDF, BxDFSample, BxDFType};
+use crate::core::spectrum::RGBSpectrum;
+use crate::sampler::Distribution2D;
+use enumset::EnumSet;
+
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+/// The stored values are scaled per channel
+const SCALE: [f64; 3] = [1.0 / 1500.0, 1.15 / 1500.0, 1.66 / 1500.0];
+
+/// Resolution of the sampling tables, in outgoing elevations and per table in incoming
+/// elevations and azimuths
+const SAMPLING_THETA_O: usize = 16;
+const SAMPLING_THETA_I: usize = 32;
+const SAMPLING_PHI: usize = 64;
+
+#[derive(Debug)]
+pub enum MerlError {
+    Io(io::Error),
+    /// The header doesn't describe a table
+    InvalidHeader,
+}
+
+/// A measured isotropic BRDF
+#[derive(Debug)]
+pub struct MerlBRDF {
+    /// Samples of the half vector elevation, the difference elevation and the difference azimuth
+    dims: [usize; 3],
+    /// RGB on the same 0-255 scale as other spectra, with the difference azimuth changing
+    /// fastest
+    samples: Vec<[f32; 3]>,
+    /// For sampling, one distribution for every range of outgoing elevations
+    distributions: Vec<Distribution2D>,
+}
+
+impl MerlBRDF {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, MerlError> {
+        Self::read(BufReader::new(File::open(path).map_err(MerlError::Io)?))
+    }
+
+    pub fn read<R: Read>(mut reader: R) -> Result<Self, MerlError> {
+        let mut dims = [0; 3];
+        for dim in dims.iter_mut() {
+            let mut bytes = [0; 4];
+            reader.read_exact(&mut bytes).map_err(MerlError::Io)?;
+            let value = i32::from_le_bytes(bytes);
+            if value <= 0 || value > 4096 {
+                return Err(MerlError::InvalidHeader);
+            }
+            *dim = value as usize;
+        }
+
+        // The channels are stored one after the other
+        let n = dims[0] * dims[1] * dims[2];
+        let mut samples = vec![[0.0; 3]; n];
+        let mut bytes = vec![0; n * 8];
+        for (channel, scale) in SCALE.iter().enumerate() {
+            reader.read_exact(&mut bytes).map_err(MerlError::Io)?;
+            for (sample, value) in samples.iter_mut().zip(bytes.chunks_exact(8)) {
+                let mut value_bytes = [0; 8];
+                value_bytes.copy_from_slice(value);
+                // Missing measurements are stored as negative values
+                let value = f64::from_le_bytes(value_bytes) * scale * 255.0;
+                sample[channel] = value.max(0.0) as f32;
+            }
+        }
+
+        let mut brdf = Self {
+            dims,
+            samples,
+            distributions: Vec::new(),
+        };
+        brdf.distributions = (0..SAMPLING_THETA_O)
+            .map(|i| brdf.tabulate((i as Float + 0.5) / SAMPLING_THETA_O as Float))
+            .collect();
+        Ok(brdf)
+    }
+
+    /// The sampling distribution for outgoing elevations at `t` of the way to the horizon
+    fn tabulate(&self, t: Float) -> Distribution2D {
+        let theta_o = t * float::consts::FRAC_PI_2;
+        let wo = Vec3::new(theta_o.sin(), 0.0, theta_o.cos());
+        let mut func = Vec::with_capacity(SAMPLING_THETA_I * SAMPLING_PHI);
+        for j in 0..SAMPLING_PHI {
+            let phi = 2.0 * float::consts::PI * (j as Float + 0.5) / SAMPLING_PHI as Float;
+            for i in 0..SAMPLING_THETA_I {
+                let theta_i =
+                    float::consts::FRAC_PI_2 * (i as Float + 0.5) / SAMPLING_THETA_I as Float;
+                let wi = comb::spherical_direction(theta_i.sin(), theta_i.cos(), phi);
+                // The sine is the Jacobian from solid angle to the elevation and azimuth
+                let value =
+                    self.evaluate(&wo, &wi).luminance() as Float * theta_i.cos() * theta_i.sin();
+                func.push(value);
+            }
+        }
+        // Keep every direction possible to sample, in case the table misses part of a lobe
+        let floor = 0.01 * func.iter().sum::<Float>() / func.len() as Float;
+        func.iter_mut().for_each(|v| *v += floor.max(1e-6));
+        Distribution2D::new(&func, SAMPLING_THETA_I, SAMPLING_PHI)
+    }
+
+    fn sample(&self, h: usize, d: usize, p: usize) -> &[f32; 3] {
+        &self.samples[(h * self.dims[1] + d) * self.dims[2] + p]
+    }
+
+    /// Interpolate at continuous sample positions, clamping the elevations and wrapping the
+    /// azimuth
+    fn lookup(&self, h: Float, d: Float, p: Float) -> RGBSpectrum {
+        let clamped = |x: Float, n: usize| {
+            let x = x.clamp(0.0, (n - 1) as Float);
+            let i = x.floor() as usize;
+            (i, (i + 1).min(n - 1), x - i as Float)
+        };
+        let (h0, h1, th) = clamped(h, self.dims[0]);
+        let (d0, d1, td) = clamped(d, self.dims[1]);
+        let n = self.dims[2];
+        let p = p.rem_euclid(n as Float);
+        let p0 = (p.floor() as usize).min(n - 1);
+        let p1 = (p0 + 1) % n;
+        let tp = p - p0 as Float;
+
+        let mut rgb = [0.0; 3];
+        for &(h, wh) in &[(h0, 1.0 - th), (h1, th)] {
+            for &(d, wd) in &[(d0, 1.0 - td), (d1, td)] {
+                for &(p, wp) in &[(p0, 1.0 - tp), (p1, tp)] {
+                    let weight = float::to_f64(wh * wd * wp);
+                    for (c, value) in rgb.iter_mut().zip(self.sample(h, d, p).iter()) {
+                        *c += weight * f64::from(*value);
+                    }
+                }
+            }
+        }
+        RGBSpectrum::new(rgb)
+    }
+
+    /// The value for directions in the local shading frame, which are above the surface
+    pub fn evaluate(&self, wo: &Vec3, wi: &Vec3) -> RGBSpectrum {
+        let (theta_h, theta_d, phi_d) = half_diff(wo, wi);
+        self.lookup(
+            (theta_h / float::consts::FRAC_PI_2).max(0.0).sqrt() * self.dims[0] as Float,
+            theta_d / float::consts::FRAC_PI_2 * self.dims[1] as Float,
+            phi_d / float::consts::PI * self.dims[2] as Float,
+        )
+    }
+
+    fn distribution(&self, wo: &Vec3) -> &Distribution2D {
+        let theta_o = wo.z.clamp(-1.0, 1.0).acos();
+        let i = (theta_o / float::consts::FRAC_PI_2 * SAMPLING_THETA_O as Float) as usize;
+        &self.distributions[i.min(SAMPLING_THETA_O - 1)]
+    }
+}
+
+/// Rusinkiewicz's half vector elevation and the elevation and azimuth of `wi` relative to it
+fn half_diff(wo: &Vec3, wi: &Vec3) -> (Float, Float, Float) {
+    let wh = (*wo + *wi).normalized();
+    let theta_h = wh.z.clamp(-1.0, 1.0).acos();
+    let phi_h = wh.y.atan2(wh.x);
+
+    // Rotate `wi` so the half vector ends up on the Z axis
+    let (sin, cos) = (-phi_h).sin_cos();
+    let d = Vec3::new(wi.x * cos - wi.y * sin, wi.x * sin + wi.y * cos, wi.z);
+    let (sin, cos) = (-theta_h).sin_cos();
+    let d = Vec3::new(d.x * cos + d.z * sin, d.y, d.z * cos - d.x * sin);
+
+    (theta_h, d.z.clamp(-1.0, 1.0).acos(), d.y.atan2(d.x))
+}
+
+/// Mirror a direction below the surface above it, so the measurements apply to both sides
+fn upper(w: &Vec3) -> Vec3 {
+    Vec3::new(w.x, w.y, w.z.abs())
+}
+
+/// A BxDF backed by a measured BRDF
+#[derive(Debug, Clone)]
+pub struct MeasuredBxDF {
+    pub brdf: Arc<MerlBRDF>,
+}
+
+impl MeasuredBxDF {
+    pub fn new(brdf: Arc<MerlBRDF>) -> Self {
+        Self { brdf }
+    }
+}
+
+impl BxDF for MeasuredBxDF {
+    fn types(&self) -> EnumSet<BxDFType> {
+        BxDFType::Reflection | BxDFType::Glossy
+    }
+
+    fn evaluate(&self, wo: &Vec3, wi: &Vec3) -> RGBSpectrum {
+        if !same_hemisphere(wo, wi) {
+            return RGBSpectrum::BLACK;
+        }
+        self.brdf.evaluate(&upper(wo), &upper(wi))
+    }
+
+    fn sample_f(&self, wo: &Vec3, u: &Point2) -> Option<BxDFSample> {
+        if wo.z == 0.0 {
+            return None;
+        }
+        let (p, _) = self.brdf.distribution(&upper(wo)).sample_continuous(u);
+        let theta_i = p.x * float::consts::FRAC_PI_2;
+        let phi = wo.y.atan2(wo.x) + p.y * 2.0 * float::consts::PI;
+        let mut wi = comb::spherical_direction(theta_i.sin(), theta_i.cos(), phi);
+        if wo.z < 0.0 {
+            wi.z = -wi.z;
+        }
+        let pdf = self.pdf(wo, &wi);
+        if pdf == 0.0 {
+            return None;
+        }
+        Some(BxDFSample {
+            f: self.evaluate(wo, &wi),
+            wi,
+            pdf,
+            types: self.types(),
+        })
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> Float {
+        if !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+        let (wo, wi) = (upper(wo), upper(wi));
+        let theta_i = wi.z.clamp(-1.0, 1.0).acos();
+        let sin_theta_i = theta_i.sin();
+        if sin_theta_i == 0.0 {
+            return 0.0;
+        }
+        let phi = (wi.y.atan2(wi.x) - wo.y.atan2(wo.x)).rem_euclid(2.0 * float::consts::PI);
+        let p = Point2::new(
+            theta_i / float::consts::FRAC_PI_2,
+            phi / (2.0 * float::consts::PI),
+        );
+        // From the unit square to elevation and azimuth, and from there to solid angle
+        self.brdf.distribution(&wo).pdf(&p)
+            / (float::consts::FRAC_PI_2 * 2.0 * float::consts::PI * sin_theta_i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bxdf::bsdf::testing::check_albedo;
+    use crate::bxdf::bsdf::BSDF;
+
+    /// A glossy BRDF in terms of the half and difference angles
+    fn analytic(theta_h: Float, theta_d: Float) -> f64 {
+        float::to_f64((0.2 + 2.0 * theta_h.cos().powi(8) * (1.0 - 0.3 * theta_d.sin())) / 3.0)
+    }
+
+    /// The analytic BRDF written in the MERL format
+    fn merl_file(dims: [usize; 3]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for dim in &dims {
+            bytes.extend_from_slice(&(*dim as i32).to_le_bytes());
+        }
+        for scale in &SCALE {
+            for h in 0..dims[0] {
+                let t = h as Float / dims[0] as Float;
+                let theta_h = t * t * float::consts::FRAC_PI_2;
+                for d in 0..dims[1] {
+                    let theta_d = d as Float / dims[1] as Float * float::consts::FRAC_PI_2;
+                    for _ in 0..dims[2] {
+                        let value = analytic(theta_h, theta_d) / scale;
+                        bytes.extend_from_slice(&value.to_le_bytes());
+                    }
+                }
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn merl() {
+        let brdf = Arc::new(MerlBRDF::read(&merl_file([90, 45, 90])[..]).unwrap());
+        let wo = Vec3::new(0.3, 0.4, 0.8).normalized();
+        for wi in &[
+            Vec3::new(-0.3, -0.4, 0.8).normalized(),
+            Vec3::new(-0.5, 0.1, 0.6).normalized(),
+            Vec3::new(0.7, -0.2, 0.3).normalized(),
+        ] {
+            let (theta_h, theta_d, _) = half_diff(&wo, wi);
+            let expected = analytic(theta_h, theta_d) * 255.0;
+            let value = brdf.evaluate(&wo, wi);
+            assert!((value[0] - expected).abs() < 0.02 * expected, "{:?}", value);
+            assert!((value[2] - expected).abs() < 0.02 * expected, "{:?}", value);
+        }
+
+        let n = Normal::new(0.0, 0.0, 1.0);
+        let bsdf = BSDF::new(n, n).with(Box::new(MeasuredBxDF::new(brdf)));
+        check_albedo(&bsdf, &wo);
+        check_albedo(&bsdf, &Vec3::new(0.9, 0.0, 0.2).normalized());
+
+        assert!(MerlBRDF::read(&[0u8; 12][..]).is_err());
+        assert!(MerlBRDF::read(&merl_file([4, 4, 4])[..100]).is_err());
+    }
+}
@@ -0,0 +1,557 @@
+// Microfacet models, which treat a rough surface as a collection of tiny perfectly smooth
+// facets whose normals follow a distribution.
+//
+// Both distributions sample only the normals which are visible from the outgoing direction,
+// which wastes far fewer samples at grazing angles than sampling the whole distribution. For
+// Trowbridge-Reitz this is the method from Heitz's "Sampling the GGX Distribution of Visible
+// Normals", for Beckmann the slope sampling from pbrt.
+
+use crate::algebra::prelude::*;
+use crate::bxdf::fresnel::{self, Fresnel};
+use crate::bxdf::{
+    abs_cos_theta, cos2_phi, cos2_theta, cos_phi, cos_theta, reflect, refract, same_hemisphere,
+    sin2_phi, sin_phi, tan2_theta, tan_theta, BxDF, BxDFSample, BxDFType,
+};
+use crate::core::spectrum::RGBSpectrum;
+use crate::core::transport::TransportMode;
+use enumset::EnumSet;
+
+/// A distribution of microfacet normals about the Z axis, where `alpha_x` and `alpha_y` are
+/// the roughness along each tangent
+pub trait MicrofacetDistribution: std::fmt::Debug + Send + Sync {
+    /// The density of microfacets with the normal `wh`
+    fn d(&self, wh: &Vec3) -> Float;
+
+    /// Smith's auxiliary function, the area of microfacets hidden from `w` per visible area
+    fn lambda(&self, w: &Vec3) -> Float;
+
+    /// The fraction of microfacets visible from `w`
+    fn g1(&self, w: &Vec3) -> Float {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// The fraction of microfacets visible from both directions
+    fn g(&self, wo: &Vec3, wi: &Vec3) -> Float {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Sample a microfacet normal visible from `wo`, on the same side as `wo`
+    fn sample_wh(&self, wo: &Vec3, u: &Point2) -> Vec3;
+
+    /// The density with which `sample_wh` picks `wh`
+    fn pdf(&self, wo: &Vec3, wh: &Vec3) -> Float {
+        let cos_o = abs_cos_theta(wo);
+        if cos_o == 0.0 {
+            return 0.0;
+        }
+        self.d(wh) * self.g1(wo) * comb::dot(wo, wh).abs() / cos_o
+    }
+}
+
+/// Map a roughness between 0 and 1 to the `alpha` of the distributions, which makes roughness
+/// perceptually linear
+pub fn roughness_to_alpha(roughness: Float) -> Float {
+    (roughness * roughness).max(1e-3)
+}
+
+/// Which distribution a material uses for its microfacets
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MicrofacetModel {
+    /// Also known as GGX. Has a longer tail than Beckmann, which gives glossy highlights a glow.
+    TrowbridgeReitz,
+    Beckmann,
+}
+
+impl MicrofacetModel {
+    pub fn distribution(self, alpha_x: Float, alpha_y: Float) -> Box<dyn MicrofacetDistribution> {
+        match self {
+            MicrofacetModel::TrowbridgeReitz => Box::new(TrowbridgeReitz::new(alpha_x, alpha_y)),
+            MicrofacetModel::Beckmann => Box::new(Beckmann::new(alpha_x, alpha_y)),
+        }
+    }
+}
+
+/// Combined slope variance over the directions' azimuth, for anisotropic distributions
+fn projected_alpha(w: &Vec3, alpha_x: Float, alpha_y: Float) -> Float {
+    (cos2_phi(w) * alpha_x * alpha_x + sin2_phi(w) * alpha_y * alpha_y).sqrt()
+}
+
+#[derive(Debug, Clone)]
+pub struct TrowbridgeReitz {
+    pub alpha_x: Float,
+    pub alpha_y: Float,
+}
+
+impl TrowbridgeReitz {
+    pub fn new(alpha_x: Float, alpha_y: Float) -> Self {
+        Self { alpha_x, alpha_y }
+    }
+}
+
+impl MicrofacetDistribution for TrowbridgeReitz {
+    fn d(&self, wh: &Vec3) -> Float {
+        let tan2_theta = tan2_theta(wh);
+        if tan2_theta.is_infinite() {
+            return 0.0;
+        }
+        let cos4_theta = cos2_theta(wh) * cos2_theta(wh);
+        let e = (cos2_phi(wh) / (self.alpha_x * self.alpha_x)
+            + sin2_phi(wh) / (self.alpha_y * self.alpha_y))
+            * tan2_theta;
+        1.0 / (float::consts::PI * self.alpha_x * self.alpha_y * cos4_theta * (1.0 + e) * (1.0 + e))
+    }
+
+    fn lambda(&self, w: &Vec3) -> Float {
+        let abs_tan_theta = tan_theta(w).abs();
+        if abs_tan_theta.is_infinite() {
+            return 0.0;
+        }
+        let alpha = projected_alpha(w, self.alpha_x, self.alpha_y);
+        let alpha2_tan2_theta = (alpha * abs_tan_theta) * (alpha * abs_tan_theta);
+        (-1.0 + (1.0 + alpha2_tan2_theta).sqrt()) / 2.0
+    }
+
+    fn sample_wh(&self, wo: &Vec3, u: &Point2) -> Vec3 {
+        let flip = wo.z < 0.0;
+        let wo = if flip { -*wo } else { *wo };
+        // Stretch to the configuration where the distribution is a hemisphere, and sample the
+        // projection of that hemisphere seen from `v`
+        let v = Vec3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z).normalized();
+        let len2 = v.x * v.x + v.y * v.y;
+        let t1 = if len2 > 0.0 {
+            Vec3::new(-v.y, v.x, 0.0) / len2.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = comb::cross(&v, &t1);
+        let r = u.x.sqrt();
+        let phi = 2.0 * float::consts::PI * u.y;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + v.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+        let n = t1 * p1 + t2 * p2 + v * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+        // And back again
+        let wh = Vec3::new(self.alpha_x * n.x, self.alpha_y * n.y, n.z.max(1e-6)).normalized();
+        if flip {
+            -wh
+        } else {
+            wh
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Beckmann {
+    pub alpha_x: Float,
+    pub alpha_y: Float,
+}
+
+impl Beckmann {
+    pub fn new(alpha_x: Float, alpha_y: Float) -> Self {
+        Self { alpha_x, alpha_y }
+    }
+}
+
+impl MicrofacetDistribution for Beckmann {
+    fn d(&self, wh: &Vec3) -> Float {
+        let tan2_theta = tan2_theta(wh);
+        if tan2_theta.is_infinite() {
+            return 0.0;
+        }
+        let cos4_theta = cos2_theta(wh) * cos2_theta(wh);
+        (-tan2_theta
+            * (cos2_phi(wh) / (self.alpha_x * self.alpha_x)
+                + sin2_phi(wh) / (self.alpha_y * self.alpha_y)))
+            .exp()
+            / (float::consts::PI * self.alpha_x * self.alpha_y * cos4_theta)
+    }
+
+    fn lambda(&self, w: &Vec3) -> Float {
+        let abs_tan_theta = tan_theta(w).abs();
+        if abs_tan_theta.is_infinite() {
+            return 0.0;
+        }
+        let alpha = projected_alpha(w, self.alpha_x, self.alpha_y);
+        // Rational approximation of the exact `(erf(a) - 1) / 2 + exp(-a^2) / (2 a sqrt(pi))`
+        let a = 1.0 / (alpha * abs_tan_theta);
+        if a >= 1.6 {
+            return 0.0;
+        }
+        (1.0 - 1.259 * a + 0.396 * a * a) / (3.535 * a + 2.181 * a * a)
+    }
+
+    fn sample_wh(&self, wo: &Vec3, u: &Point2) -> Vec3 {
+        let flip = wo.z < 0.0;
+        let wo = if flip { -*wo } else { *wo };
+        let v = Vec3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z).normalized();
+        let (slope_x, slope_y) = beckmann_sample_slopes(cos_theta(&v), u);
+        // Rotate to the azimuth of `v` and unstretch
+        let (cos_phi, sin_phi) = (cos_phi(&v), sin_phi(&v));
+        let slope_x_rotated = cos_phi * slope_x - sin_phi * slope_y;
+        let slope_y_rotated = sin_phi * slope_x + cos_phi * slope_y;
+        let wh = Vec3::new(
+            -self.alpha_x * slope_x_rotated,
+            -self.alpha_y * slope_y_rotated,
+            1.0,
+        )
+        .normalized();
+        if flip {
+            -wh
+        } else {
+            wh
+        }
+    }
+}
+
+/// Sample the slopes of visible microfacets of the unit Beckmann distribution seen from an
+/// angle with `cos_theta` in the XZ plane
+fn beckmann_sample_slopes(cos_theta: Float, u: &Point2) -> (Float, Float) {
+    if cos_theta > 0.9999 {
+        // Head on every microfacet is visible
+        let r = (-(1.0 - u.x).ln()).sqrt();
+        let phi = 2.0 * float::consts::PI * u.y;
+        return (r * phi.cos(), r * phi.sin());
+    }
+
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let tan_theta = sin_theta / cos_theta;
+    let cot_theta = 1.0 / tan_theta;
+    let inv_sqrt_pi = 1.0 / float::consts::PI.sqrt();
+
+    // Invert the CDF of the X slope with Newton-Raphson, bisecting if it leaves the bracket
+    let mut a = -1.0;
+    let mut c = erf(cot_theta);
+    let sample_x = u.x.max(1e-6);
+    let theta = cos_theta.acos();
+    let fit = 1.0 + theta * (-0.876 + theta * (0.4265 - 0.0594 * theta));
+    let mut b = c - (1.0 + c) * (1.0 - sample_x).powf(fit);
+    let normalization = 1.0 / (1.0 + c + inv_sqrt_pi * tan_theta * (-cot_theta * cot_theta).exp());
+    for _ in 0..9 {
+        if !(b >= a && b <= c) {
+            b = 0.5 * (a + c);
+        }
+        let inv_erf = erf_inv(b);
+        let value = normalization
+            * (1.0 + b + inv_sqrt_pi * tan_theta * (-inv_erf * inv_erf).exp())
+            - sample_x;
+        let derivative = normalization * (1.0 - inv_erf * tan_theta);
+        if value.abs() < 1e-5 {
+            break;
+        }
+        if value > 0.0 {
+            c = b;
+        } else {
+            a = b;
+        }
+        b -= value / derivative;
+    }
+
+    (erf_inv(b), erf_inv(2.0 * u.y.max(1e-6) - 1.0))
+}
+
+/// The error function, to about 1e-7
+fn erf(x: Float) -> Float {
+    let t = 1.0 / (1.0 + 0.327_591_1 * x.abs());
+    let y = 1.0
+        - (((((1.061_405_429 * t - 1.453_152_027) * t) + 1.421_413_741) * t - 0.284_496_736) * t
+            + 0.254_829_592)
+            * t
+            * (-x * x).exp();
+    y.copysign(x)
+}
+
+/// Inverse of the error function, from Giles' "Approximating the erfinv function"
+fn erf_inv(x: Float) -> Float {
+    let x = x.clamp(-0.99999, 0.99999);
+    let w = -((1.0 - x) * (1.0 + x)).ln();
+    let p = if w < 5.0 {
+        let w = w - 2.5;
+        [
+            3.432_739_39e-7,
+            -3.523_387_7e-6,
+            -4.391_506_54e-6,
+            2.185_808_7e-4,
+            -1.253_725_03e-3,
+            -4.177_681_64e-3,
+            2.466_407_27e-1,
+            1.501_409_41,
+        ]
+        .iter()
+        .fold(2.810_226_36e-8, |p, c| c + p * w)
+    } else {
+        let w = w.sqrt() - 3.0;
+        [
+            1.009_505_58e-4,
+            1.349_343_22e-3,
+            -3.673_428_44e-3,
+            5.739_507_73e-3,
+            -7.622_461_3e-3,
+            9.438_870_47e-3,
+            1.001_674_06,
+            2.832_976_82,
+        ]
+        .iter()
+        .fold(-2.002_142_57e-4, |p, c| c + p * w)
+    };
+    p * x
+}
+
+/// Glossy reflection off microfacets which are perfect mirrors, like metals or the coating of
+/// a plastic
+#[derive(Debug)]
+pub struct MicrofacetReflection {
+    pub r: RGBSpectrum,
+    pub distribution: Box<dyn MicrofacetDistribution>,
+    pub fresnel: Box<dyn Fresnel>,
+}
+
+impl MicrofacetReflection {
+    pub fn new(
+        r: RGBSpectrum,
+        distribution: Box<dyn MicrofacetDistribution>,
+        fresnel: Box<dyn Fresnel>,
+    ) -> Self {
+        Self {
+            r,
+            distribution,
+            fresnel,
+        }
+    }
+}
+
+impl BxDF for MicrofacetReflection {
+    fn types(&self) -> EnumSet<BxDFType> {
+        BxDFType::Reflection | BxDFType::Glossy
+    }
+
+    fn evaluate(&self, wo: &Vec3, wi: &Vec3) -> RGBSpectrum {
+        let cos_o = abs_cos_theta(wo);
+        let cos_i = abs_cos_theta(wi);
+        if cos_o == 0.0 || cos_i == 0.0 || !same_hemisphere(wo, wi) {
+            return RGBSpectrum::BLACK;
+        }
+        let wh = *wi + *wo;
+        if wh.x == 0.0 && wh.y == 0.0 && wh.z == 0.0 {
+            return RGBSpectrum::BLACK;
+        }
+        let wh = wh.normalized();
+        // The Fresnel term is relative to the outside of the surface
+        let wh_out = if wh.z < 0.0 { -wh } else { wh };
+        let f = self.fresnel.evaluate(comb::dot(wi, &wh_out));
+        let d = self.distribution.d(&wh);
+        let g = self.distribution.g(wo, wi);
+        self.r.mul_with(f) * float::to_f64(d * g / (4.0 * cos_i * cos_o))
+    }
+
+    fn sample_f(&self, wo: &Vec3, u: &Point2) -> Option<BxDFSample> {
+        if wo.z == 0.0 {
+            return None;
+        }
+        let wh = self.distribution.sample_wh(wo, u);
+        let cos_oh = comb::dot(wo, &wh);
+        if cos_oh <= 0.0 {
+            return None;
+        }
+        let wi = reflect(wo, &wh);
+        if !same_hemisphere(wo, &wi) {
+            return None;
+        }
+        Some(BxDFSample {
+            f: self.evaluate(wo, &wi),
+            pdf: self.distribution.pdf(wo, &wh) / (4.0 * cos_oh),
+            wi,
+            types: self.types(),
+        })
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> Float {
+        if !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+        let wh = (*wo + *wi).normalized();
+        self.distribution.pdf(wo, &wh) / (4.0 * comb::dot(wo, &wh))
+    }
+}
+
+/// Glossy transmission through a rough boundary between two dielectrics, like frosted glass
+#[derive(Debug)]
+pub struct MicrofacetTransmission {
+    pub t: RGBSpectrum,
+    pub distribution: Box<dyn MicrofacetDistribution>,
+    /// Index of refraction on the side the normal points to
+    pub eta_a: Float,
+    /// Index of refraction on the other side
+    pub eta_b: Float,
+    pub mode: TransportMode,
+}
+
+impl MicrofacetTransmission {
+    pub fn new(
+        t: RGBSpectrum,
+        distribution: Box<dyn MicrofacetDistribution>,
+        eta_a: Float,
+        eta_b: Float,
+        mode: TransportMode,
+    ) -> Self {
+        Self {
+            t,
+            distribution,
+            eta_a,
+            eta_b,
+            mode,
+        }
+    }
+
+    /// Ratio of the index of refraction on the side of `wi` to the one on the side of `wo`
+    fn eta(&self, wo: &Vec3) -> Float {
+        if cos_theta(wo) > 0.0 {
+            self.eta_b / self.eta_a
+        } else {
+            self.eta_a / self.eta_b
+        }
+    }
+}
+
+impl BxDF for MicrofacetTransmission {
+    fn types(&self) -> EnumSet<BxDFType> {
+        BxDFType::Transmission | BxDFType::Glossy
+    }
+
+    fn evaluate(&self, wo: &Vec3, wi: &Vec3) -> RGBSpectrum {
+        if same_hemisphere(wo, wi) {
+            return RGBSpectrum::BLACK;
+        }
+        let cos_o = cos_theta(wo);
+        let cos_i = cos_theta(wi);
+        if cos_o == 0.0 || cos_i == 0.0 {
+            return RGBSpectrum::BLACK;
+        }
+
+        // The generalized half vector of refraction
+        let eta = self.eta(wo);
+        let wh = (*wo + *wi * eta).normalized();
+        let wh = if wh.z < 0.0 { -wh } else { wh };
+        let cos_oh = comb::dot(wo, &wh);
+        let cos_ih = comb::dot(wi, &wh);
+        // Both have to be on opposite sides of the microfacet
+        if cos_oh * cos_ih > 0.0 {
+            return RGBSpectrum::BLACK;
+        }
+
+        let f = fresnel::dielectric(cos_oh, self.eta_a, self.eta_b);
+        let sqrt_denom = cos_oh + eta * cos_ih;
+        let factor = if self.mode == TransportMode::Radiance {
+            1.0 / eta
+        } else {
+            1.0
+        };
+        let value = (1.0 - f)
+            * (self.distribution.d(&wh)
+                * self.distribution.g(wo, wi)
+                * eta
+                * eta
+                * cos_ih.abs()
+                * cos_oh.abs()
+                * factor
+                * factor
+                / (cos_i * cos_o * sqrt_denom * sqrt_denom))
+                .abs();
+        self.t * float::to_f64(value)
+    }
+
+    fn sample_f(&self, wo: &Vec3, u: &Point2) -> Option<BxDFSample> {
+        if wo.z == 0.0 {
+            return None;
+        }
+        let wh = self.distribution.sample_wh(wo, u);
+        if comb::dot(wo, &wh) <= 0.0 {
+            return None;
+        }
+        let wi = refract(wo, &wh, 1.0 / self.eta(wo))?;
+        let pdf = self.pdf(wo, &wi);
+        if pdf == 0.0 {
+            return None;
+        }
+        Some(BxDFSample {
+            f: self.evaluate(wo, &wi),
+            wi,
+            pdf,
+            types: self.types(),
+        })
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> Float {
+        if same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+        let eta = self.eta(wo);
+        let wh = (*wo + *wi * eta).normalized();
+        let cos_oh = comb::dot(wo, &wh);
+        let cos_ih = comb::dot(wi, &wh);
+        if cos_oh * cos_ih > 0.0 {
+            return 0.0;
+        }
+        // Change of variables from the half vector to the refracted direction
+        let sqrt_denom = cos_oh + eta * cos_ih;
+        let dwh_dwi = (eta * eta * cos_ih / (sqrt_denom * sqrt_denom)).abs();
+        self.distribution.pdf(wo, &wh) * dwh_dwi
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bxdf::bsdf::testing::check_albedo;
+    use crate::bxdf::bsdf::BSDF;
+    use crate::bxdf::fresnel::{FresnelDielectric, FresnelNoOp};
+
+    const WHITE: RGBSpectrum = RGBSpectrum::from_rgb(255.0, 255.0, 255.0);
+
+    fn normal_bsdf() -> BSDF {
+        BSDF::new(Normal::new(0.0, 0.0, 1.0), Normal::new(0.0, 0.0, 1.0))
+    }
+
+    #[test]
+    fn reflection() {
+        let wo = Vec3::new(0.3, -0.2, 0.8).normalized();
+        for &model in &[MicrofacetModel::TrowbridgeReitz, MicrofacetModel::Beckmann] {
+            for &(alpha_x, alpha_y) in &[(0.1, 0.1), (0.4, 0.4), (0.1, 0.5)] {
+                let bsdf = normal_bsdf().with(Box::new(MicrofacetReflection::new(
+                    WHITE,
+                    model.distribution(alpha_x, alpha_y),
+                    Box::new(FresnelNoOp),
+                )));
+                let albedo = check_albedo(&bsdf, &wo);
+                // Only light which is shadowed by other microfacets is lost
+                assert!(albedo > 0.5 && albedo < 1.0 + 1e-2, "{}", albedo);
+            }
+        }
+    }
+
+    #[test]
+    fn rough_dielectric() {
+        for &model in &[MicrofacetModel::TrowbridgeReitz, MicrofacetModel::Beckmann] {
+            let bsdf = normal_bsdf()
+                .with(Box::new(MicrofacetReflection::new(
+                    WHITE,
+                    model.distribution(0.3, 0.3),
+                    Box::new(FresnelDielectric::new(1.0, 1.5)),
+                )))
+                .with(Box::new(MicrofacetTransmission::new(
+                    WHITE,
+                    model.distribution(0.3, 0.3),
+                    1.0,
+                    1.5,
+                    TransportMode::Importance,
+                )));
+            // Entering, and leaving with some total internal reflection
+            for wo in &[
+                Vec3::new(0.4, 0.1, 0.9).normalized(),
+                Vec3::new(0.4, 0.1, -0.9).normalized(),
+            ] {
+                let albedo = check_albedo(&bsdf, wo);
+                assert!(albedo > 0.5 && albedo < 1.0 + 1e-2, "{}", albedo);
+            }
+        }
+    }
+}
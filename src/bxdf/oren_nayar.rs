@@ -0,0 +1,76 @@
+// Oren-Nayar reflection, which models rough diffuse surfaces as V-shaped Lambertian
+// microfacets. Unlike Lambertian surfaces they get brighter towards the light, which is why
+// the full moon looks flat instead of like a ball.
+
+use crate::algebra::prelude::*;
+use crate::bxdf::{abs_cos_theta, cos_phi, sin_phi, sin_theta, BxDF, BxDFType};
+use crate::core::spectrum::RGBSpectrum;
+use enumset::EnumSet;
+
+#[derive(Debug, Clone)]
+pub struct OrenNayar {
+    pub r: RGBSpectrum,
+    a: Float,
+    b: Float,
+}
+
+impl OrenNayar {
+    /// `sigma` is the standard deviation of the microfacet angles in degrees, where 0 is
+    /// Lambertian
+    pub fn new(r: RGBSpectrum, sigma: Float) -> Self {
+        let sigma2 = comb::to_radians(sigma) * comb::to_radians(sigma);
+        Self {
+            r,
+            a: 1.0 - sigma2 / (2.0 * (sigma2 + 0.33)),
+            b: 0.45 * sigma2 / (sigma2 + 0.09),
+        }
+    }
+}
+
+impl BxDF for OrenNayar {
+    fn types(&self) -> EnumSet<BxDFType> {
+        BxDFType::Reflection | BxDFType::Diffuse
+    }
+
+    fn evaluate(&self, wo: &Vec3, wi: &Vec3) -> RGBSpectrum {
+        let sin_theta_i = sin_theta(wi);
+        let sin_theta_o = sin_theta(wo);
+        // The cosine of the difference in azimuth
+        let max_cos = if sin_theta_i > 1e-4 && sin_theta_o > 1e-4 {
+            (cos_phi(wi) * cos_phi(wo) + sin_phi(wi) * sin_phi(wo)).max(0.0)
+        } else {
+            0.0
+        };
+        let (sin_alpha, tan_beta) = if abs_cos_theta(wi) > abs_cos_theta(wo) {
+            (sin_theta_o, sin_theta_i / abs_cos_theta(wi))
+        } else {
+            (sin_theta_i, sin_theta_o / abs_cos_theta(wo))
+        };
+        self.r
+            * float::to_f64(
+                float::consts::FRAC_1_PI * (self.a + self.b * max_cos * sin_alpha * tan_beta),
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bxdf::bsdf::testing::check_albedo;
+    use crate::bxdf::bsdf::BSDF;
+    use crate::bxdf::lambertian::LambertianReflection;
+
+    #[test]
+    fn oren_nayar() {
+        let white = RGBSpectrum::from_rgb(255.0, 255.0, 255.0);
+        let wo = Vec3::new(0.6, 0.2, 0.5).normalized();
+        let wi = Vec3::new(-0.3, 0.4, 0.7).normalized();
+        let smooth = OrenNayar::new(white, 0.0).evaluate(&wo, &wi);
+        assert!((smooth[0] - LambertianReflection::new(white).evaluate(&wo, &wi)[0]).abs() < 1e-3);
+
+        let n = Normal::new(0.0, 0.0, 1.0);
+        let rough = BSDF::new(n, n).with(Box::new(OrenNayar::new(white, 30.0)));
+        let albedo = check_albedo(&rough, &wo);
+        assert!(albedo > 0.8 && albedo < 1.0, "{}", albedo);
+    }
+}
@@ -0,0 +1,126 @@
+use crate::algebra::prelude::*;
+use crate::bxdf::fresnel::{self, Fresnel};
+use crate::bxdf::{abs_cos_theta, cos_theta, refract, BxDF, BxDFSample, BxDFType};
+use crate::core::spectrum::RGBSpectrum;
+use crate::core::transport::TransportMode;
+use enumset::EnumSet;
+
+/// A perfect mirror, which reflects light in exactly one direction
+#[derive(Debug)]
+pub struct SpecularReflection {
+    pub r: RGBSpectrum,
+    pub fresnel: Box<dyn Fresnel>,
+}
+
+impl SpecularReflection {
+    pub fn new(r: RGBSpectrum, fresnel: Box<dyn Fresnel>) -> Self {
+        Self { r, fresnel }
+    }
+}
+
+impl BxDF for SpecularReflection {
+    fn types(&self) -> EnumSet<BxDFType> {
+        BxDFType::Reflection | BxDFType::Specular
+    }
+
+    fn evaluate(&self, _wo: &Vec3, _wi: &Vec3) -> RGBSpectrum {
+        RGBSpectrum::BLACK
+    }
+
+    fn sample_f(&self, wo: &Vec3, _u: &Point2) -> Option<BxDFSample> {
+        let wi = Vec3::new(-wo.x, -wo.y, wo.z);
+        let cos = abs_cos_theta(&wi);
+        if cos == 0.0 {
+            return None;
+        }
+        // Divided by the cosine, which the integrator multiplies back in
+        Some(BxDFSample {
+            f: self.r.mul_with(self.fresnel.evaluate(cos_theta(&wi))) / float::to_f64(cos),
+            wi,
+            pdf: 1.0,
+            types: self.types(),
+        })
+    }
+
+    fn pdf(&self, _wo: &Vec3, _wi: &Vec3) -> Float {
+        0.0
+    }
+}
+
+/// A smooth boundary between two dielectrics, like glass or water. Light is reflected and
+/// transmitted in the proportions given by the Fresnel equations, and sampling picks one of the
+/// two by the same proportions.
+#[derive(Debug, Clone)]
+pub struct FresnelSpecular {
+    pub r: RGBSpectrum,
+    pub t: RGBSpectrum,
+    /// Index of refraction on the side the normal points to
+    pub eta_a: Float,
+    /// Index of refraction on the other side
+    pub eta_b: Float,
+    pub mode: TransportMode,
+}
+
+impl FresnelSpecular {
+    pub fn new(
+        r: RGBSpectrum,
+        t: RGBSpectrum,
+        eta_a: Float,
+        eta_b: Float,
+        mode: TransportMode,
+    ) -> Self {
+        Self {
+            r,
+            t,
+            eta_a,
+            eta_b,
+            mode,
+        }
+    }
+}
+
+impl BxDF for FresnelSpecular {
+    fn types(&self) -> EnumSet<BxDFType> {
+        BxDFType::Reflection | BxDFType::Transmission | BxDFType::Specular
+    }
+
+    fn evaluate(&self, _wo: &Vec3, _wi: &Vec3) -> RGBSpectrum {
+        RGBSpectrum::BLACK
+    }
+
+    fn sample_f(&self, wo: &Vec3, u: &Point2) -> Option<BxDFSample> {
+        let f = fresnel::dielectric(cos_theta(wo), self.eta_a, self.eta_b);
+        if u.x < f {
+            let wi = Vec3::new(-wo.x, -wo.y, wo.z);
+            return Some(BxDFSample {
+                f: self.r * float::to_f64(f / abs_cos_theta(&wi)),
+                wi,
+                pdf: f,
+                types: BxDFType::Reflection | BxDFType::Specular,
+            });
+        }
+
+        let entering = cos_theta(wo) > 0.0;
+        let (eta_i, eta_t, n) = if entering {
+            (self.eta_a, self.eta_b, Vec3::new(0.0, 0.0, 1.0))
+        } else {
+            (self.eta_b, self.eta_a, Vec3::new(0.0, 0.0, -1.0))
+        };
+        let wi = refract(wo, &n, eta_i / eta_t)?;
+        let mut ft = self.t * float::to_f64((1.0 - f) / abs_cos_theta(&wi));
+        // Radiance gets compressed into a smaller solid angle when entering a denser medium
+        if self.mode == TransportMode::Radiance {
+            ft = ft * float::to_f64((eta_i * eta_i) / (eta_t * eta_t));
+        }
+        Some(BxDFSample {
+            wi,
+            f: ft,
+            pdf: 1.0 - f,
+            types: BxDFType::Transmission | BxDFType::Specular,
+        })
+    }
+
+    fn pdf(&self, _wo: &Vec3, _wi: &Vec3) -> Float {
+        0.0
+    }
+}
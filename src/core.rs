@@ -8,6 +8,7 @@ pub mod primitive;
 pub mod renderer;
 pub mod scene;
 pub mod spectrum;
+pub mod subsurface;
 pub mod surface_interaction;
 pub mod texture;
 pub mod transport;
@@ -1,7 +1,11 @@
-use crate::acceleration::bvh::{BVHAccel, BVHConstructionAlgorithm, BVHLinearTree};
+use crate::acceleration::accelerator::{Accelerator, AcceleratorStats, AcceleratorType};
+use crate::acceleration::bvh::BVHConstructionAlgorithm;
+use crate::acceleration::bvh_stats::TraversalCounters;
+use crate::acceleration::instancing::{Instance, TopLevelBVH};
+use crate::acceleration::multi_hit;
 use crate::algebra::prelude::*;
 use crate::bxdf::bsdf::BSDF;
-use crate::core::interaction::Interaction;
+use crate::core::interaction::{HitFilter, Interaction};
 use crate::core::material::Material;
 use crate::core::primitive::Primitive;
 use crate::light::area_light::AreaLight;
@@ -10,29 +14,155 @@ use std::sync::Arc;
 
 #[derive(Debug)]
 pub struct Aggregate {
-    pub tree: BVHLinearTree,
+    /// The structure over the loose primitives
+    pub accelerator: Box<dyn Accelerator + Sync + Send>,
+    pub instances: TopLevelBVH,
 }
 
 impl Aggregate {
     pub fn from_primitives(primitives: Vec<Arc<dyn Primitive + Sync + Send>>) -> Self {
-        let mut accel = BVHAccel::new(BVHConstructionAlgorithm::SAH, primitives);
-        let (total, node) = accel.construct().expect("Could not construct BVHTree");
-        let flat_bvh = accel.flatten(Box::new(node), total);
+        Self::new(primitives, Vec::new())
+    }
+
+    /// Construct an Aggregate from loose primitives and instances of bottom level BVHs
+    pub fn new(
+        primitives: Vec<Arc<dyn Primitive + Sync + Send>>,
+        instances: Vec<Instance>,
+    ) -> Self {
+        Self::with_accelerator(AcceleratorType::default(), primitives, instances)
+    }
+
+    /// Like [new](#method.new), but with a choice of acceleration structure for the loose
+    /// primitives
+    pub fn with_accelerator(
+        accelerator_type: AcceleratorType,
+        primitives: Vec<Arc<dyn Primitive + Sync + Send>>,
+        instances: Vec<Instance>,
+    ) -> Self {
+        Self {
+            accelerator: accelerator_type.build(primitives),
+            instances: TopLevelBVH::new(BVHConstructionAlgorithm::SAH, instances),
+        }
+    }
+
+    /// Rebuild only the top level BVH, after instances have been moved
+    pub fn rebuild_instances(&mut self) {
+        self.instances.rebuild();
+    }
 
-        Self { tree: flat_bvh }
+    /// Statistics of the structure over the loose primitives
+    pub fn stats(&self) -> AcceleratorStats {
+        self.accelerator.stats()
     }
 }
 
 impl Aggregate {
     pub fn bounds(&self) -> BoundingBox {
-        self.tree.bounds.clone()
+        self.accelerator.bounds().merge(&self.instances.bounds)
     }
 
     pub fn intersect(&self, ray: &Ray) -> Option<Interaction> {
-        self.tree.intersect(ray)
+        nearest(
+            self.accelerator.intersect(ray),
+            self.instances.intersect(ray),
+        )
+    }
+
+    /// The nearest hit accepted by `filter`
+    pub fn intersect_filtered(&self, ray: &Ray, filter: &HitFilter) -> Option<Interaction<'_>> {
+        nearest(
+            self.accelerator.intersect_filtered(ray, filter),
+            self.instances.intersect_filtered(ray, filter),
+        )
+    }
+
+    /// Any hit accepted by `filter`, not necessarily the nearest
+    pub fn intersect_any(&self, ray: &Ray, filter: &HitFilter) -> Option<Interaction<'_>> {
+        self.accelerator
+            .intersect_any(ray, filter)
+            .or_else(|| self.instances.intersect_any(ray, filter))
+    }
+
+    /// Every hit in `[ray.min_t, ray.max_t]` sorted by distance, or only the `max_hits` nearest
+    pub fn intersect_all(&self, ray: &Ray, max_hits: Option<usize>) -> Vec<Interaction<'_>> {
+        let mut hits = self.accelerator.intersect_all(ray, max_hits);
+        if !self.instances.instances.is_empty() {
+            hits.extend(multi_hit::walk_hits(
+                ray,
+                ray.max_t,
+                max_hits.unwrap_or(usize::MAX),
+                |ray| self.instances.intersect(ray),
+            ));
+            hits.sort_by(|a, b| {
+                a.geom
+                    .t
+                    .partial_cmp(&b.geom.t)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+            if let Some(max_hits) = max_hits {
+                hits.truncate(max_hits);
+            }
+        }
+        hits
+    }
+
+    /// Intersect, counting the work done in the structure over the loose primitives as well as
+    /// in the instances
+    pub fn intersect_counted(&self, ray: &Ray) -> (Option<Interaction<'_>>, TraversalCounters) {
+        let (hit, mut counters) = self.accelerator.intersect_counted(ray);
+        let (instance_hit, instance_counters) = self.instances.intersect_counted(ray);
+        counters += instance_counters;
+        (nearest(hit, instance_hit), counters)
     }
 
     pub fn does_intersect(&self, ray: &Ray) -> bool {
-        self.tree.does_intersect(ray)
+        self.accelerator.does_intersect(ray) || self.instances.does_intersect(ray)
+    }
+
+    pub fn intersect_packet(&self, rays: &[Ray]) -> Vec<Option<Interaction<'_>>> {
+        self.with_instances(rays, self.accelerator.intersect_packet(rays))
+    }
+
+    pub fn does_intersect_packet(&self, rays: &[Ray]) -> Vec<bool> {
+        self.accelerator
+            .does_intersect_packet(rays)
+            .into_iter()
+            .zip(rays)
+            .map(|(hit, ray)| hit || self.instances.does_intersect(ray))
+            .collect()
+    }
+
+    /// Intersect any amount of rays, sorted into coherent packets internally
+    pub fn intersect_stream(&self, rays: &[Ray]) -> Vec<Option<Interaction<'_>>> {
+        self.with_instances(rays, self.accelerator.intersect_stream(rays))
+    }
+
+    pub fn does_intersect_stream(&self, rays: &[Ray]) -> Vec<bool> {
+        self.accelerator
+            .does_intersect_stream(rays)
+            .into_iter()
+            .zip(rays)
+            .map(|(hit, ray)| hit || self.instances.does_intersect(ray))
+            .collect()
+    }
+
+    fn with_instances<'a>(
+        &'a self,
+        rays: &[Ray],
+        primitive_hits: Vec<Option<Interaction<'a>>>,
+    ) -> Vec<Option<Interaction<'a>>> {
+        primitive_hits
+            .into_iter()
+            .zip(rays)
+            .map(|(hit, ray)| nearest(hit, self.instances.intersect(ray)))
+            .collect()
+    }
+}
+
+fn nearest<'a>(a: Option<Interaction<'a>>, b: Option<Interaction<'a>>) -> Option<Interaction<'a>> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.nearest(b)),
+        (a, None) => a,
+        (None, b) => b,
     }
 }
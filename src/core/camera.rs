@@ -2,7 +2,7 @@ use crate::algebra::prelude::*;
 
 pub struct CameraSample {
     pub film_pos: Point2,
-    pub time: f64,
+    pub time: Float,
 }
 
 pub trait Camera: Send + Sync {
@@ -12,15 +12,15 @@ pub trait Camera: Send + Sync {
 pub struct PerspectiveCamera {
     pub camera_to_world: Transform,
     pub raster_to_screen: Transform,
-    pub shutter_time: (f64, f64),
-    pub fov: f64,
+    pub shutter_time: (Float, Float),
+    pub fov: Float,
     pub scaling: Vec3,
     pub screen_dim: Vec2,
     pub proj_dir_inv: Transform,
 }
 
 impl PerspectiveCamera {
-    pub fn new(camera_to_world: Transform, fov: f64, screen_dimensions: Vec2) -> Self {
+    pub fn new(camera_to_world: Transform, fov: Float, screen_dimensions: Vec2) -> Self {
         let shutter_time = (0.0, 1.0);
         let aspect_ratio = screen_dimensions.x / screen_dimensions.y;
         let screen = if aspect_ratio > 1.0 {
@@ -28,19 +28,15 @@ impl PerspectiveCamera {
         } else {
             (-1.0, 1.0, -1.0 / aspect_ratio, 1.0 / aspect_ratio)
         };
-        let screen_to_raster = Transform::scaling(screen_dimensions.x, screen_dimensions.y, 1.0)
-            * Transform::scaling(
-                1.0 / (screen.1 - screen.0),
-                1.0 / (screen.2 - screen.3),
-                1.0,
-            )
-            * Transform::translation(&Vec3::new(-screen.0, -screen.3, 0.0));
-
-        let raster_to_screen = screen_to_raster.inverse();
+        // Maps raster positions relative to the centre of the screen onto the screen window
+        let raster_to_screen =
+            Transform::scaling(1.0 / screen_dimensions.x, 1.0 / screen_dimensions.y, 1.0)
+                * Transform::scaling(screen.1 - screen.0, screen.2 - screen.3, 1.0)
+                * Transform::translation(&Vec3::new(screen.0, screen.3, 0.0));
         let far = 1.0;
         let near = 1000.0;
         let proj_dir_inv = Transform::perspective(fov, far, near).inverse();
-        let tan_fov = f64::tan(comb::to_radians(fov) / 2.0);
+        let tan_fov = Float::tan(comb::to_radians(fov) / 2.0);
         let scaling = Vec3::new(tan_fov, tan_fov, 1.0);
         Self {
             proj_dir_inv,
@@ -68,3 +64,29 @@ impl Camera for PerspectiveCamera {
         Ray::new(Point3::ORIGIN, d).apply_t(&self.camera_to_world)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rays_do_not_depend_on_transform_composition() {
+        // Directions generated before `Transform::compose` computed the inverse in the right
+        // order, the raster to screen transform used to be the inverse of a composition
+        let camera = PerspectiveCamera::new(Transform::identity(), 90.0, Vec2::new(200.0, 100.0));
+        let expected = [
+            ((100.0, 50.0), Vec3::new(-0.039960, -0.019980, 0.999001)),
+            ((0.0, 0.0), Vec3::new(-0.824486, 0.396077, 0.404160)),
+            ((200.0, 100.0), Vec3::new(0.808153, -0.420569, 0.412323)),
+            ((100.0, 0.0), Vec3::new(-0.028557, 0.699643, 0.713922)),
+        ];
+        for &((x, y), direction) in &expected {
+            let ray = camera.generate_ray(&CameraSample {
+                film_pos: Point2::new(x, y),
+                time: 0.0,
+            });
+            assert_eq!(ray.origin, Point3::ORIGIN);
+            assert!((ray.direction - direction).length() < 1e-5);
+        }
+    }
+}
@@ -1,12 +1,16 @@
 use crate::algebra::prelude::*;
 use crate::bxdf::bsdf::BSDF;
-use crate::core::material::BRDF;
 use crate::core::primitive::Primitive;
 use crate::core::spectrum::RGBSpectrum;
+use crate::core::transport::TransportMode;
 use crate::geometry::geometry_information::GeometryInformation;
 
 use std::sync::Arc;
 
+/// Decides whether a candidate hit counts for a query, for example to ignore the surface a ray
+/// starts on. Rejected hits are skipped and traversal carries on looking behind them.
+pub type HitFilter<'f> = dyn Fn(&Interaction) -> bool + 'f;
+
 #[derive(Debug, Clone)]
 pub struct Interaction<'a> {
     pub geom: GeometryInformation,
@@ -22,8 +26,23 @@ impl<'a> Interaction<'a> {
         }
     }
 
-    pub fn compute_scattering_functions(&self, ray: &Ray) -> BRDF {
-        self.primitive.compute_scattering_functions(self)
+    pub fn compute_scattering_functions(&self, ray: &Ray, mode: TransportMode) -> BSDF {
+        self.primitive.compute_scattering_functions(self, mode)
+    }
+
+    /// A ray leaving the hit point in `direction`, nudged off the surface so it doesn't hit it
+    /// again straight away
+    pub fn spawn_ray(&self, direction: &Vec3) -> Ray {
+        let p = self.geom.origin;
+        let scale = p.x.abs().max(p.y.abs()).max(p.z.abs());
+        let offset = (256.0 * float::EPSILON * scale).max(1e-4);
+        let n = Vec3::from(self.geom.normal);
+        let n = if comb::dot(&n, direction) < 0.0 {
+            -n
+        } else {
+            n
+        };
+        Ray::new(p + n * offset, *direction)
     }
 
     pub fn light_emission(&self) -> RGBSpectrum {
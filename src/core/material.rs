@@ -1,38 +1,82 @@
 use crate::algebra::prelude::*;
 use crate::bxdf::bsdf::BSDF;
+use crate::bxdf::coated::CoatedBxDF;
+use crate::bxdf::disney::{
+    DisneyClearcoat, DisneyDiffuse, DisneyFresnel, DisneyMicrofacetDistribution, DisneyRetro,
+    DisneySheen,
+};
+use crate::bxdf::fresnel::{Fresnel, FresnelConductor, FresnelDielectric, FresnelNoOp};
+use crate::bxdf::lambertian::LambertianReflection;
+use crate::bxdf::measured::{MeasuredBxDF, MerlBRDF};
+use crate::bxdf::microfacet::{
+    roughness_to_alpha, MicrofacetModel, MicrofacetReflection, MicrofacetTransmission,
+};
+use crate::bxdf::oren_nayar::OrenNayar;
+use crate::bxdf::specular::{FresnelSpecular, SpecularReflection};
 use crate::core::interaction::Interaction;
 use crate::core::spectrum::RGBSpectrum;
-use crate::core::texture::Texture;
+use crate::core::subsurface::RandomWalk;
+use crate::core::texture::{ConstantTexture, Texture};
 use crate::core::transport::TransportMode;
+use crate::geometry::geometry_information::GeometryInformation;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::sync::Arc;
 
-pub enum BRDF {
-    Matte,
-    Reflective,
-}
-
 pub trait Material: std::fmt::Debug + Send + Sync {
-    fn compute_scattering_functions(&self, interaction: &Interaction) -> BRDF;
+    /// The scattering at the interaction, for light flowing in the direction of `mode`
+    fn compute_scattering_functions(&self, interaction: &Interaction, mode: TransportMode) -> BSDF;
     fn albedo(&self, uv: &Point2) -> RGBSpectrum;
+
+    /// The medium light refracted into the object scatters through, if it doesn't travel
+    /// straight
+    fn subsurface(&self, _interaction: &Interaction) -> Option<RandomWalk> {
+        None
+    }
 }
 
+/// A diffuse surface, which is Lambertian unless it's rough
 #[derive(Debug)]
 pub struct Matte<'a> {
     pub kd: Arc<dyn Texture<RGBSpectrum> + 'a>,
+    /// Standard deviation of the microfacet angles in degrees, for Oren-Nayar reflection
+    pub sigma: Arc<dyn Texture<Float> + 'a>,
 }
+
+impl<'a> Matte<'a> {
+    /// A perfectly smooth matte surface
+    pub fn lambertian(kd: Arc<dyn Texture<RGBSpectrum> + 'a>) -> Self {
+        Self {
+            kd,
+            sigma: Arc::new(ConstantTexture::new(0.0)),
+        }
+    }
+}
+
 impl<'a> Material for Matte<'a> {
     fn albedo(&self, uv: &Point2) -> RGBSpectrum {
         self.kd.sample(uv)
     }
 
-    fn compute_scattering_functions(&self, interaction: &Interaction) -> BRDF {
-        BRDF::Matte
+    fn compute_scattering_functions(&self, interaction: &Interaction, _: TransportMode) -> BSDF {
+        let geom = &interaction.geom;
+        let r = self.kd.sample(&geom.uv);
+        let sigma = self.sigma.sample(&geom.uv).clamp(0.0, 90.0);
+        let bsdf = BSDF::new(geom.normal, geom.normal);
+        if sigma == 0.0 {
+            bsdf.with(Box::new(LambertianReflection::new(r)))
+        } else {
+            bsdf.with(Box::new(OrenNayar::new(r, sigma)))
+        }
     }
 }
 
+/// A colored reflection which is blurred by its roughness, without any Fresnel falloff
 #[derive(Debug)]
 pub struct Glossy<'a> {
     pub kd: Arc<dyn Texture<RGBSpectrum> + 'a>,
+    /// Between 0 for a perfect mirror and 1
+    pub roughness: Arc<dyn Texture<Float> + 'a>,
 }
 
 impl<'a> Material for Glossy<'a> {
@@ -40,7 +84,582 @@ impl<'a> Material for Glossy<'a> {
         self.kd.sample(uv)
     }
 
-    fn compute_scattering_functions(&self, interaction: &Interaction) -> BRDF {
-        BRDF::Reflective
+    fn compute_scattering_functions(&self, interaction: &Interaction, _: TransportMode) -> BSDF {
+        let geom = &interaction.geom;
+        let r = self.kd.sample(&geom.uv);
+        let roughness = self.roughness.sample(&geom.uv);
+        let bsdf = BSDF::new(geom.normal, geom.normal);
+        if roughness == 0.0 {
+            bsdf.with(Box::new(SpecularReflection::new(r, Box::new(FresnelNoOp))))
+        } else {
+            let alpha = roughness_to_alpha(roughness);
+            bsdf.with(Box::new(MicrofacetReflection::new(
+                r,
+                MicrofacetModel::TrowbridgeReitz.distribution(alpha, alpha),
+                Box::new(FresnelNoOp),
+            )))
+        }
+    }
+}
+
+/// A conductor like gold or copper, which reflects all light it doesn't absorb. The color
+/// comes from the complex index of refraction, see the presets on `FresnelConductor`.
+#[derive(Debug)]
+pub struct Metal<'a> {
+    pub fresnel: FresnelConductor,
+    /// Roughness along the U and V tangents, which differ for brushed metals
+    pub u_roughness: Arc<dyn Texture<Float> + 'a>,
+    pub v_roughness: Arc<dyn Texture<Float> + 'a>,
+    pub model: MicrofacetModel,
+}
+
+impl<'a> Material for Metal<'a> {
+    fn albedo(&self, _uv: &Point2) -> RGBSpectrum {
+        self.fresnel.evaluate(1.0)
+    }
+
+    fn compute_scattering_functions(&self, interaction: &Interaction, _: TransportMode) -> BSDF {
+        let geom = &interaction.geom;
+        let white = RGBSpectrum::from_rgb(255.0, 255.0, 255.0);
+        let u_roughness = self.u_roughness.sample(&geom.uv);
+        let v_roughness = self.v_roughness.sample(&geom.uv);
+        let bsdf = BSDF::new(geom.normal, geom.normal);
+        if u_roughness == 0.0 && v_roughness == 0.0 {
+            bsdf.with(Box::new(SpecularReflection::new(
+                white,
+                Box::new(self.fresnel.clone()),
+            )))
+        } else {
+            bsdf.with(Box::new(MicrofacetReflection::new(
+                white,
+                self.model.distribution(
+                    roughness_to_alpha(u_roughness),
+                    roughness_to_alpha(v_roughness),
+                ),
+                Box::new(self.fresnel.clone()),
+            )))
+        }
+    }
+}
+
+/// A dielectric like glass or water, which both reflects and refracts. Rough glass scatters
+/// both, like frosted glass.
+#[derive(Debug)]
+pub struct Glass<'a> {
+    pub kr: Arc<dyn Texture<RGBSpectrum> + 'a>,
+    pub kt: Arc<dyn Texture<RGBSpectrum> + 'a>,
+    /// Index of refraction of the inside, where the outside is taken to be air
+    pub eta: Float,
+    pub u_roughness: Arc<dyn Texture<Float> + 'a>,
+    pub v_roughness: Arc<dyn Texture<Float> + 'a>,
+    pub model: MicrofacetModel,
+}
+
+impl<'a> Material for Glass<'a> {
+    fn albedo(&self, uv: &Point2) -> RGBSpectrum {
+        self.kt.sample(uv)
+    }
+
+    fn compute_scattering_functions(&self, interaction: &Interaction, mode: TransportMode) -> BSDF {
+        let geom = &interaction.geom;
+        let r = self.kr.sample(&geom.uv);
+        let t = self.kt.sample(&geom.uv);
+        let u_roughness = self.u_roughness.sample(&geom.uv);
+        let v_roughness = self.v_roughness.sample(&geom.uv);
+        let bsdf = BSDF::new(geom.normal, geom.normal);
+        if u_roughness == 0.0 && v_roughness == 0.0 {
+            return bsdf.with(Box::new(FresnelSpecular::new(r, t, 1.0, self.eta, mode)));
+        }
+
+        let alpha_x = roughness_to_alpha(u_roughness);
+        let alpha_y = roughness_to_alpha(v_roughness);
+        bsdf.with(Box::new(MicrofacetReflection::new(
+            r,
+            self.model.distribution(alpha_x, alpha_y),
+            Box::new(FresnelDielectric::new(1.0, self.eta)),
+        )))
+        .with(Box::new(MicrofacetTransmission::new(
+            t,
+            self.model.distribution(alpha_x, alpha_y),
+            1.0,
+            self.eta,
+            mode,
+        )))
+    }
+}
+
+/// Disney's principled material, whose parameters are the ones artists and tools like glTF
+/// use. All of them except the base color are between 0 and 1.
+#[derive(Debug)]
+pub struct Principled<'a> {
+    pub base_color: Arc<dyn Texture<RGBSpectrum> + 'a>,
+    /// Blends from a dielectric to a metal whose specular color is the base color
+    pub metallic: Arc<dyn Texture<Float> + 'a>,
+    pub roughness: Arc<dyn Texture<Float> + 'a>,
+    /// Reflectance head on of dielectrics, where 0.5 means 4% like an index of refraction of 1.5
+    pub specular: Arc<dyn Texture<Float> + 'a>,
+    /// Tints the dielectric specular towards the base color
+    pub specular_tint: Arc<dyn Texture<Float> + 'a>,
+    /// Stretches the highlight along the U tangent
+    pub anisotropic: Arc<dyn Texture<Float> + 'a>,
+    pub sheen: Arc<dyn Texture<Float> + 'a>,
+    pub sheen_tint: Arc<dyn Texture<Float> + 'a>,
+    pub clearcoat: Arc<dyn Texture<Float> + 'a>,
+    /// From a satin to a glossy clearcoat
+    pub clearcoat_gloss: Arc<dyn Texture<Float> + 'a>,
+    /// Blends from opaque to refracting like glass
+    pub transmission: Arc<dyn Texture<Float> + 'a>,
+}
+
+impl<'a> Principled<'a> {
+    /// A rough dielectric with the given color, and the other parameters at Disney's defaults
+    pub fn new(base_color: Arc<dyn Texture<RGBSpectrum> + 'a>) -> Self {
+        let constant =
+            |v: Float| -> Arc<dyn Texture<Float> + 'a> { Arc::new(ConstantTexture::new(v)) };
+        Self {
+            base_color,
+            metallic: constant(0.0),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            specular_tint: constant(0.0),
+            anisotropic: constant(0.0),
+            sheen: constant(0.0),
+            sheen_tint: constant(0.5),
+            clearcoat: constant(0.0),
+            clearcoat_gloss: constant(1.0),
+            transmission: constant(0.0),
+        }
+    }
+}
+
+/// `a` for `t` of zero and `b` for one
+fn mix(t: Float, a: RGBSpectrum, b: RGBSpectrum) -> RGBSpectrum {
+    let t = float::to_f64(t);
+    a * (1.0 - t) + b * t
+}
+
+impl<'a> Material for Principled<'a> {
+    fn albedo(&self, uv: &Point2) -> RGBSpectrum {
+        self.base_color.sample(uv)
+    }
+
+    fn compute_scattering_functions(&self, interaction: &Interaction, mode: TransportMode) -> BSDF {
+        let geom = &interaction.geom;
+        let uv = &geom.uv;
+        let white = RGBSpectrum::from_rgb(255.0, 255.0, 255.0);
+        let color = self.base_color.sample(uv);
+        let metallic = self.metallic.sample(uv);
+        let roughness = self.roughness.sample(uv);
+        let sheen = self.sheen.sample(uv);
+        let clearcoat = self.clearcoat.sample(uv);
+        let transmission = self.transmission.sample(uv);
+        let mut bsdf = BSDF::new(geom.normal, geom.normal);
+
+        // The hue and saturation of the base color, without its luminance
+        let luminance = color.luminance() / 255.0;
+        let tint = if luminance > 0.0 {
+            color / luminance
+        } else {
+            white
+        };
+
+        let diffuse_weight = float::to_f64((1.0 - metallic) * (1.0 - transmission));
+        if diffuse_weight > 0.0 {
+            let diffuse = color * diffuse_weight;
+            bsdf.add(Box::new(DisneyDiffuse::new(diffuse)));
+            bsdf.add(Box::new(DisneyRetro::new(diffuse, roughness)));
+            if sheen > 0.0 {
+                let sheen_color = mix(self.sheen_tint.sample(uv), white, tint);
+                bsdf.add(Box::new(DisneySheen::new(
+                    sheen_color * (diffuse_weight * float::to_f64(sheen)),
+                )));
+            }
+        }
+
+        let aspect = (1.0 - self.anisotropic.sample(uv) * 0.9).sqrt();
+        let alpha_x = (roughness * roughness / aspect).max(1e-3);
+        let alpha_y = (roughness * roughness * aspect).max(1e-3);
+        // `specular` maps 0.08 to the reflectance head on. Without any reflectance the index of
+        // refraction would be 1, where the half vector of refraction is undefined.
+        let r0 = (0.08 * self.specular.sample(uv)).clamp(1e-4, 0.999);
+        let eta = (1.0 + r0.sqrt()) / (1.0 - r0.sqrt());
+        let specular_color = mix(self.specular_tint.sample(uv), white, tint) * float::to_f64(r0);
+        bsdf.add(Box::new(MicrofacetReflection::new(
+            white,
+            Box::new(DisneyMicrofacetDistribution::new(alpha_x, alpha_y)),
+            Box::new(DisneyFresnel::new(
+                mix(metallic, specular_color, color),
+                metallic,
+                eta,
+            )),
+        )));
+
+        if clearcoat > 0.0 {
+            let gloss = 0.1 + (0.001 - 0.1) * self.clearcoat_gloss.sample(uv);
+            bsdf.add(Box::new(DisneyClearcoat::new(clearcoat, gloss)));
+        }
+
+        if transmission > 0.0 {
+            let t = (color / 255.0).sqrt() * (255.0 * float::to_f64(transmission));
+            bsdf.add(Box::new(MicrofacetTransmission::new(
+                t,
+                Box::new(DisneyMicrofacetDistribution::new(alpha_x, alpha_y)),
+                1.0,
+                eta,
+                mode,
+            )));
+        }
+
+        bsdf
+    }
+}
+
+/// Blends two materials by `amount`, like patches of rust on metal. Every hit uses one of the
+/// two, picked at random with `amount`, so each keeps its own shading normals and subsurface
+/// scattering.
+#[derive(Debug)]
+pub struct MixMaterial<'a> {
+    pub m1: Arc<dyn Material + 'a>,
+    pub m2: Arc<dyn Material + 'a>,
+    /// The weight of `m2`, between 0 and 1
+    pub amount: Arc<dyn Texture<Float> + 'a>,
+}
+
+impl<'a> Material for MixMaterial<'a> {
+    fn albedo(&self, uv: &Point2) -> RGBSpectrum {
+        mix(
+            self.amount.sample(uv),
+            self.m1.albedo(uv),
+            self.m2.albedo(uv),
+        )
+    }
+
+    fn compute_scattering_functions(&self, interaction: &Interaction, mode: TransportMode) -> BSDF {
+        self.picked(interaction)
+            .compute_scattering_functions(interaction, mode)
+    }
+
+    fn subsurface(&self, interaction: &Interaction) -> Option<RandomWalk> {
+        self.picked(interaction).subsurface(interaction)
+    }
+}
+
+impl<'a> MixMaterial<'a> {
+    /// The material used at `interaction`. The choice is made from a hash of the hit, so the
+    /// scattering functions and the subsurface walk of the same hit agree.
+    fn picked(&self, interaction: &Interaction) -> &Arc<dyn Material + 'a> {
+        let amount = self.amount.sample(&interaction.geom.uv);
+        if hash_hit(&interaction.geom) < amount {
+            &self.m2
+        } else {
+            &self.m1
+        }
+    }
+}
+
+/// A number in [0, 1) which only depends on where and how far away the hit was
+fn hash_hit(geom: &GeometryInformation) -> Float {
+    let mut hasher = DefaultHasher::new();
+    for i in 0..3 {
+        geom.origin[i].to_bits().hash(&mut hasher);
+    }
+    geom.t.to_bits().hash(&mut hasher);
+    (hasher.finish() >> 11) as Float / (1u64 << 53) as Float
+}
+
+/// Another material under a clear dielectric coat, like varnished wood or car paint
+#[derive(Debug)]
+pub struct CoatedMaterial<'a> {
+    pub base: Arc<dyn Material + 'a>,
+    /// Index of refraction of the coat
+    pub eta: Float,
+    /// Roughness of the coat, where 0 is perfectly smooth
+    pub roughness: Arc<dyn Texture<Float> + 'a>,
+}
+
+impl<'a> Material for CoatedMaterial<'a> {
+    fn albedo(&self, uv: &Point2) -> RGBSpectrum {
+        self.base.albedo(uv)
+    }
+
+    fn compute_scattering_functions(&self, interaction: &Interaction, mode: TransportMode) -> BSDF {
+        let white = RGBSpectrum::from_rgb(255.0, 255.0, 255.0);
+        let base = self.base.compute_scattering_functions(interaction, mode);
+        let mut bsdf = BSDF::new(base.geometric_normal, base.shading_normal);
+
+        let roughness = self.roughness.sample(&interaction.geom.uv);
+        let fresnel = Box::new(FresnelDielectric::new(1.0, self.eta));
+        if roughness == 0.0 {
+            bsdf.add(Box::new(SpecularReflection::new(white, fresnel)));
+        } else {
+            let alpha = roughness_to_alpha(roughness);
+            bsdf.add(Box::new(MicrofacetReflection::new(
+                white,
+                MicrofacetModel::TrowbridgeReitz.distribution(alpha, alpha),
+                fresnel,
+            )));
+        }
+        for bxdf in base.bxdfs {
+            bsdf.add(Box::new(CoatedBxDF::new(bxdf, self.eta)));
+        }
+        bsdf
+    }
+}
+
+/// A measured BRDF, for comparing against real materials
+#[derive(Debug)]
+pub struct Measured {
+    pub brdf: Arc<MerlBRDF>,
+}
+
+impl Material for Measured {
+    fn albedo(&self, _uv: &Point2) -> RGBSpectrum {
+        // What a Lambertian surface with the same value head on would reflect
+        let n = Vec3::new(0.0, 0.0, 1.0);
+        self.brdf.evaluate(&n, &n) * std::f64::consts::PI
+    }
+
+    fn compute_scattering_functions(&self, interaction: &Interaction, _: TransportMode) -> BSDF {
+        let geom = &interaction.geom;
+        BSDF::new(geom.normal, geom.normal)
+            .with(Box::new(MeasuredBxDF::new(Arc::clone(&self.brdf))))
+    }
+}
+
+/// A translucent object like skin, wax or marble, where light scatters around inside before
+/// coming out somewhere else. The object has to be closed.
+#[derive(Debug)]
+pub struct Subsurface<'a> {
+    /// Roughly the color of the object, once light has scattered through it
+    pub albedo: Arc<dyn Texture<RGBSpectrum> + 'a>,
+    /// How far light gets between scattering events for each channel, in scene units
+    pub mean_free_path: [Float; 3],
+    /// Index of refraction of the inside, where the outside is taken to be air
+    pub eta: Float,
+}
+
+impl<'a> Material for Subsurface<'a> {
+    fn albedo(&self, uv: &Point2) -> RGBSpectrum {
+        self.albedo.sample(uv)
+    }
+
+    fn compute_scattering_functions(&self, interaction: &Interaction, mode: TransportMode) -> BSDF {
+        let geom = &interaction.geom;
+        let white = RGBSpectrum::from_rgb(255.0, 255.0, 255.0);
+        BSDF::new(geom.normal, geom.normal).with(Box::new(FresnelSpecular::new(
+            white, white, 1.0, self.eta, mode,
+        )))
+    }
+
+    fn subsurface(&self, interaction: &Interaction) -> Option<RandomWalk> {
+        Some(RandomWalk::new(
+            self.albedo.sample(&interaction.geom.uv),
+            self.mean_free_path,
+            self.eta,
+        ))
+    }
+}
+
+/// A step in texture space for taking the slope of bump maps
+const BUMP_DELTA: Float = 0.0005;
+
+/// Bends the shading normal to add detail that isn't in the geometry
+#[derive(Debug)]
+pub enum NormalPerturbation<'a> {
+    /// A height field over the texture coordinates, in scene units along the normal
+    Bump(Arc<dyn Texture<Float> + 'a>),
+    /// Normals in the tangent space of the texture coordinates, with each axis mapped from
+    /// [-1, 1] to [0, 255] and Z pointing away from the surface
+    NormalMap(Arc<dyn Texture<RGBSpectrum> + 'a>),
+}
+
+impl<'a> NormalPerturbation<'a> {
+    /// The shading normal, on the same side of the surface as the geometric one
+    pub fn shading_normal(&self, geom: &GeometryInformation) -> Normal {
+        let n = Vec3::from(geom.normal);
+        let shading = match self {
+            NormalPerturbation::Bump(height) => {
+                let uv = geom.uv;
+                let h = height.sample(&uv);
+                let du = height.sample(&Point2::new(uv.x + BUMP_DELTA, uv.y)) - h;
+                let dv = height.sample(&Point2::new(uv.x, uv.y + BUMP_DELTA)) - h;
+                // Displacing the surface tilts its tangents by the slopes of the height field
+                let dpdu = geom.dpdu + n * (du / BUMP_DELTA);
+                let dpdv = geom.dpdv + n * (dv / BUMP_DELTA);
+                comb::cross(&dpdu, &dpdv)
+            }
+            NormalPerturbation::NormalMap(map) => {
+                let rgb = map.sample(&geom.uv);
+                let axis = |c: f64| (c / 255.0 * 2.0 - 1.0) as Float;
+                let tangent = (geom.dpdu - n * comb::dot(&geom.dpdu, &n)).normalized();
+                let bitangent = comb::cross(&n, &tangent);
+                let bitangent = if comb::dot(&bitangent, &geom.dpdv) < 0.0 {
+                    -bitangent
+                } else {
+                    bitangent
+                };
+                tangent * axis(rgb[0]) + bitangent * axis(rgb[1]) + n * axis(rgb[2])
+            }
+        };
+        if shading.length2() == 0.0 || shading.has_nans() {
+            return geom.normal;
+        }
+        let shading = shading.normalized();
+        if comb::dot(&shading, &n) < 0.0 {
+            Normal::from(-shading)
+        } else {
+            Normal::from(shading)
+        }
+    }
+}
+
+/// Another material with a bump or normal map. Only the shading changes; rays still leave from
+/// the actual surface.
+#[derive(Debug)]
+pub struct Bumped<'a> {
+    pub material: Arc<dyn Material + 'a>,
+    pub perturbation: NormalPerturbation<'a>,
+}
+
+impl<'a> Material for Bumped<'a> {
+    fn albedo(&self, uv: &Point2) -> RGBSpectrum {
+        self.material.albedo(uv)
+    }
+
+    fn compute_scattering_functions(&self, interaction: &Interaction, mode: TransportMode) -> BSDF {
+        let mut shaded = interaction.clone();
+        shaded.geom.normal = self.perturbation.shading_normal(&interaction.geom);
+        let mut bsdf = self.material.compute_scattering_functions(&shaded, mode);
+        bsdf.geometric_normal = interaction.geom.normal;
+        bsdf
+    }
+
+    fn subsurface(&self, interaction: &Interaction) -> Option<RandomWalk> {
+        self.material.subsurface(interaction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acceleration::bvh::testing;
+    use crate::bxdf::bsdf::testing::sampled_albedo;
+    use crate::geometry::sphere::Sphere;
+    use enumset::EnumSet;
+
+    /// A ramp along U
+    #[derive(Debug)]
+    struct Ramp(Float);
+
+    impl Texture<Float> for Ramp {
+        fn sample(&self, uv: &Point2) -> Float {
+            self.0 * uv.x
+        }
+    }
+
+    #[test]
+    fn perturbed_normals() {
+        let geom = GeometryInformation {
+            t: 1.0,
+            normal: Normal::new(0.0, 1.0, 0.0),
+            origin: Point3::new(0.0, 0.0, 0.0),
+            uv: Point2::new(0.3, 0.6),
+            dpdu: Vec3::new(1.0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, 0.0, 2.0),
+        };
+        let close = |a: Normal, b: Vec3| (Vec3::from(a) - b.normalized()).length() < 1e-3;
+
+        // Rising towards +X tilts the normal towards -X
+        let bump = NormalPerturbation::Bump(Arc::new(Ramp(0.5)));
+        assert!(close(bump.shading_normal(&geom), Vec3::new(-0.5, 1.0, 0.0)));
+
+        let flat = NormalPerturbation::NormalMap(Arc::new(ConstantTexture::new(
+            RGBSpectrum::from_rgb(127.5, 127.5, 255.0),
+        )));
+        assert!(close(flat.shading_normal(&geom), Vec3::new(0.0, 1.0, 0.0)));
+        let tilted = NormalPerturbation::NormalMap(Arc::new(ConstantTexture::new(
+            RGBSpectrum::from_rgb(127.5, 255.0, 255.0),
+        )));
+        assert!(close(
+            tilted.shading_normal(&geom),
+            Vec3::new(0.0, 1.0, 1.0)
+        ));
+    }
+
+    /// A hit on a flat surface facing +Z
+    fn interaction() -> Interaction<'static> {
+        let sphere = Sphere::new(Point3::new(0.0, 0.0, -1.0), 1.0);
+        Interaction {
+            geom: GeometryInformation {
+                t: 1.0,
+                normal: Normal::new(0.0, 0.0, 1.0),
+                origin: Point3::new(0.0, 0.0, 0.0),
+                uv: Point2::new(0.5, 0.5),
+                dpdu: Vec3::new(1.0, 0.0, 0.0),
+                dpdv: Vec3::new(0.0, 1.0, 0.0),
+            },
+            primitive: testing::primitive(Arc::new(sphere)),
+        }
+    }
+
+    #[test]
+    fn principled_without_specular() {
+        let mut material = Principled::new(Arc::new(ConstantTexture::new(RGBSpectrum::from_rgb(
+            255.0, 255.0, 255.0,
+        ))));
+        material.specular = Arc::new(ConstantTexture::new(0.0));
+        material.transmission = Arc::new(ConstantTexture::new(1.0));
+        let bsdf = material.compute_scattering_functions(&interaction(), TransportMode::Radiance);
+
+        let wo = Vec3::new(0.3, 0.1, 1.0).normalized();
+        let straight = bsdf.pdf(&wo, &-wo, EnumSet::all());
+        assert!(straight.is_finite() && straight > 0.0);
+        assert!(bsdf.evaluate(&wo, &-wo, EnumSet::all())[0].is_finite());
+        // Almost everything passes through
+        let albedo = sampled_albedo(&bsdf, &wo);
+        assert!(albedo > 0.5 && albedo < 1.1, "{}", albedo);
+    }
+
+    #[test]
+    fn mixes_pick_one_material() {
+        let white = || {
+            Arc::new(ConstantTexture::new(RGBSpectrum::from_rgb(
+                255.0, 255.0, 255.0,
+            )))
+        };
+        let translucent = Subsurface {
+            albedo: white(),
+            mean_free_path: [0.1; 3],
+            eta: 1.3,
+        };
+        let bumped = Bumped {
+            material: Arc::new(translucent),
+            perturbation: NormalPerturbation::Bump(Arc::new(Ramp(0.5))),
+        };
+        let mix = MixMaterial {
+            m1: Arc::new(Matte::lambertian(white())),
+            m2: Arc::new(bumped),
+            amount: Arc::new(ConstantTexture::new(0.25)),
+        };
+        let flat = Normal::new(0.0, 0.0, 1.0);
+
+        let mut picked_m2 = 0;
+        for i in 0..1000 {
+            let mut hit = interaction();
+            hit.geom.origin = Point3::new(i as Float * 0.01, 0.0, 0.0);
+            let bsdf = mix.compute_scattering_functions(&hit, TransportMode::Radiance);
+            let subsurface = mix.subsurface(&hit);
+            assert_eq!(bsdf.bxdfs.len(), 1);
+            if bsdf.shading_normal == flat {
+                assert!(subsurface.is_none());
+            } else {
+                // The bumped material's own frame, and its walk
+                let expected = NormalPerturbation::Bump(Arc::new(Ramp(0.5)));
+                assert_eq!(bsdf.shading_normal, expected.shading_normal(&hit.geom));
+                assert!(subsurface.is_some());
+                picked_m2 += 1;
+            }
+        }
+        assert!(picked_m2 > 200 && picked_m2 < 300, "{}", picked_m2);
     }
 }
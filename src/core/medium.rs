@@ -15,7 +15,7 @@ pub struct HomogeneousMedium {
     pub sigma_a: RGBSpectrum,
     pub sigma_s: RGBSpectrum,
     pub sigma_t: RGBSpectrum,
-    pub g: f64,
+    pub g: Float,
 }
 
 impl Default for HomogeneousMedium {
@@ -1,24 +1,35 @@
+use crate::acceleration::multi_hit::skip_distance;
 use crate::algebra::prelude::*;
 use crate::bxdf::bsdf::BSDF;
 use crate::core::interaction::Interaction;
-use crate::core::material::{Material, BRDF};
+use crate::core::material::Material;
 use crate::core::medium::MediumInterface;
 use crate::core::spectrum::RGBSpectrum;
+use crate::core::texture::Texture;
+use crate::core::transport::TransportMode;
 use crate::geometry::geometry_information::GeometryInformation;
 use crate::geometry::shape::Shape;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::sync::Arc;
 
 pub trait Primitive: std::fmt::Debug {
     fn bounds(&self) -> BoundingBox;
+    fn clipped_bounds(&self, clip: &BoundingBox) -> BoundingBox {
+        self.bounds().intersection(clip)
+    }
     fn intersect(&self, ray: &Ray) -> Option<GeometryInformation>;
     fn does_intersect(&self, ray: &Ray) -> bool {
         self.intersect(ray).is_some()
     }
+    /// Feed the numbers which define the geometry to `state`, see `Shape::hash_geometry`
+    fn hash_geometry(&self, state: &mut dyn Hasher);
 
     fn mat<'a>(&'a self) -> Arc<dyn Material + 'a>;
     fn light_emission(&self) -> RGBSpectrum;
 
-    fn compute_scattering_functions(&self, interaction: &Interaction) -> BRDF;
+    fn compute_scattering_functions(&self, interaction: &Interaction, mode: TransportMode) -> BSDF;
 }
 
 #[derive(Debug)]
@@ -34,18 +45,27 @@ impl<'a> Primitive for GeometricPrimitive<'a> {
         self.shape.bounds()
     }
 
+    fn clipped_bounds(&self, clip: &BoundingBox) -> BoundingBox {
+        self.shape.clipped_bounds(clip)
+    }
+
     fn intersect(&self, ray: &Ray) -> Option<GeometryInformation> {
         self.shape.intersect(ray)
     }
 
-    fn compute_scattering_functions(&self, interaction: &Interaction) -> BRDF {
-        self.material.compute_scattering_functions(interaction)
+    fn compute_scattering_functions(&self, interaction: &Interaction, mode: TransportMode) -> BSDF {
+        self.material
+            .compute_scattering_functions(interaction, mode)
     }
 
     fn does_intersect(&self, ray: &Ray) -> bool {
         self.shape.does_intersect(ray)
     }
 
+    fn hash_geometry(&self, state: &mut dyn Hasher) {
+        self.shape.hash_geometry(state)
+    }
+
     fn mat<'b>(&'b self) -> Arc<dyn Material + 'b> {
         Arc::clone(&self.material)
     }
@@ -54,3 +74,225 @@ impl<'a> Primitive for GeometricPrimitive<'a> {
         self.emission
     }
 }
+
+/// Decides whether a candidate hit on a primitive counts
+pub type PrimitiveFilter<'a> = dyn Fn(&Ray, &GeometryInformation) -> bool + Sync + Send + 'a;
+
+/// How many hits on one primitive are tried for a ray before giving up on it
+const MAX_REJECTED_HITS: usize = 8;
+
+/// How alpha decides whether a hit counts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlphaTest {
+    /// Hits where alpha is below the threshold are ignored
+    Threshold(Float),
+    /// Hits are ignored with a probability of one minus alpha, which makes the surface partly
+    /// transparent on average. The choice is made from a hash of the hit point and the ray
+    /// direction, so tracing the same ray again gives the same answer.
+    Stochastic,
+}
+
+/// A number in [0, 1) which only depends on where the ray hit and where it was going
+fn hash_hit(ray: &Ray, geom: &GeometryInformation) -> Float {
+    let mut hasher = DefaultHasher::new();
+    for i in 0..3 {
+        geom.origin[i].to_bits().hash(&mut hasher);
+        ray.direction[i].to_bits().hash(&mut hasher);
+    }
+    (hasher.finish() >> 11) as Float / (1u64 << 53) as Float
+}
+
+/// A primitive whose hits have to pass a filter, for example to cut alpha-masked holes in a leaf
+/// or to make geometry one-sided. The filter runs while the acceleration structure is being
+/// traversed, and a rejected hit is skipped as if that part of the primitive wasn't there.
+pub struct FilteredPrimitive<'a> {
+    pub primitive: Arc<dyn Primitive + Sync + Send + 'a>,
+    pub filter: Arc<PrimitiveFilter<'a>>,
+}
+
+impl<'a> FilteredPrimitive<'a> {
+    pub fn new<F>(primitive: Arc<dyn Primitive + Sync + Send + 'a>, filter: F) -> Self
+    where
+        F: Fn(&Ray, &GeometryInformation) -> bool + Sync + Send + 'a,
+    {
+        Self {
+            primitive,
+            filter: Arc::new(filter),
+        }
+    }
+
+    /// Only hits on the side the normal points to count
+    pub fn one_sided(primitive: Arc<dyn Primitive + Sync + Send + 'a>) -> Self {
+        Self::new(primitive, |ray, geom| {
+            comb::dot(&ray.direction, &geom.normal) < 0.0
+        })
+    }
+
+    /// Cut holes where `alpha` is low, like around the leaves painted on a quad. This applies to
+    /// every ray, so shadows get the same holes.
+    pub fn alpha_masked(
+        primitive: Arc<dyn Primitive + Sync + Send + 'a>,
+        alpha: Arc<dyn Texture<Float> + 'a>,
+        test: AlphaTest,
+    ) -> Self {
+        Self::new(primitive, move |ray, geom| {
+            let a = alpha.sample(&geom.uv);
+            match test {
+                AlphaTest::Threshold(threshold) => a >= threshold,
+                AlphaTest::Stochastic => a >= 1.0 || (a > 0.0 && hash_hit(ray, geom) < a),
+            }
+        })
+    }
+}
+
+impl<'a> fmt::Debug for FilteredPrimitive<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FilteredPrimitive")
+            .field("primitive", &self.primitive)
+            .finish()
+    }
+}
+
+impl<'a> Primitive for FilteredPrimitive<'a> {
+    fn bounds(&self) -> BoundingBox {
+        self.primitive.bounds()
+    }
+
+    fn clipped_bounds(&self, clip: &BoundingBox) -> BoundingBox {
+        self.primitive.clipped_bounds(clip)
+    }
+
+    fn intersect(&self, ray: &Ray) -> Option<GeometryInformation> {
+        // A rejected hit can hide another one on the same primitive, like the inside of a sphere
+        // seen through a hole
+        let mut min_t = ray.min_t;
+        for _ in 0..MAX_REJECTED_HITS {
+            let restarted = Ray { min_t, ..*ray };
+            let geom = self.primitive.intersect(&restarted)?;
+            if (self.filter)(ray, &geom) {
+                return Some(geom);
+            }
+            min_t = geom.t + skip_distance(geom.t);
+        }
+        None
+    }
+
+    fn hash_geometry(&self, state: &mut dyn Hasher) {
+        self.primitive.hash_geometry(state)
+    }
+
+    fn compute_scattering_functions(&self, interaction: &Interaction, mode: TransportMode) -> BSDF {
+        self.primitive
+            .compute_scattering_functions(interaction, mode)
+    }
+
+    fn mat<'b>(&'b self) -> Arc<dyn Material + 'b> {
+        self.primitive.mat()
+    }
+
+    fn light_emission(&self) -> RGBSpectrum {
+        self.primitive.light_emission()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acceleration::bvh::testing;
+    use crate::core::aggregate::Aggregate;
+    use crate::core::material::Matte;
+    use crate::core::medium::HomogeneousMedium;
+    use crate::core::texture::ConstantTexture;
+    use crate::geometry::sphere::Sphere;
+
+    /// Opaque on one half of the texture and transparent on the other
+    #[derive(Debug)]
+    struct HalfOpaque;
+
+    impl Texture<Float> for HalfOpaque {
+        fn sample(&self, uv: &Point2) -> Float {
+            if uv.x < 0.5 {
+                1.0
+            } else {
+                0.0
+            }
+        }
+    }
+
+    fn masked(alpha: Arc<dyn Texture<Float>>, test: AlphaTest) -> Aggregate {
+        let sphere = Arc::new(GeometricPrimitive {
+            shape: Arc::new(Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0)),
+            material: Arc::new(Matte::lambertian(Arc::new(ConstantTexture::new(
+                RGBSpectrum::BLACK,
+            )))),
+            emission: RGBSpectrum::BLACK,
+            medium_interface: MediumInterface {
+                inside: Box::new(HomogeneousMedium::default()),
+                outside: Box::new(HomogeneousMedium::default()),
+            },
+        });
+        Aggregate::from_primitives(vec![Arc::new(FilteredPrimitive::alpha_masked(
+            sphere, alpha, test,
+        ))])
+    }
+
+    #[test]
+    fn one_sided() {
+        let sphere = testing::primitive(Arc::new(Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0)));
+        let front = Aggregate::from_primitives(vec![Arc::new(FilteredPrimitive::one_sided(
+            Arc::clone(&sphere),
+        ))]);
+        // Only the inside faces the ray, so the front is skipped on the way in
+        let back = Aggregate::from_primitives(vec![Arc::new(FilteredPrimitive::new(
+            sphere,
+            |ray, geom| comb::dot(&ray.direction, &geom.normal) > 0.0,
+        ))]);
+
+        let outside = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let inside = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!((front.intersect(&outside).unwrap().geom.t - 4.0).abs() < 1e-4);
+        assert!(front.intersect(&inside).is_none());
+        assert!(!front.does_intersect(&inside));
+        assert!((back.intersect(&outside).unwrap().geom.t - 6.0).abs() < 1e-4);
+        assert!((back.intersect(&inside).unwrap().geom.t - 1.0).abs() < 1e-4);
+
+        // The filters of the queries come on top of the primitive's own
+        let nothing = |_: &Interaction| false;
+        let everything = |_: &Interaction| true;
+        assert!(back.intersect_filtered(&outside, &nothing).is_none());
+        assert!(back.intersect_any(&outside, &nothing).is_none());
+        let any = back.intersect_any(&outside, &everything).unwrap();
+        assert!((any.geom.t - 6.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn alpha_masks() {
+        let ray = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+
+        // The front of the sphere is cut away, so the ray goes on to the back
+        let half = masked(Arc::new(HalfOpaque), AlphaTest::Threshold(0.5));
+        assert!((half.intersect(&ray).unwrap().geom.t - 6.0).abs() < 1e-4);
+        assert!(half.does_intersect(&ray));
+
+        let clear = masked(
+            Arc::new(ConstantTexture::new(0.2)),
+            AlphaTest::Threshold(0.5),
+        );
+        assert!(clear.intersect(&ray).is_none());
+        assert!(!clear.does_intersect(&ray));
+
+        // Either side is kept half of the time
+        let stochastic = masked(Arc::new(ConstantTexture::new(0.5)), AlphaTest::Stochastic);
+        let n = 2000;
+        let mut hits = 0;
+        for i in 0..n {
+            let x = (i as Float / n as Float - 0.5) * 0.1;
+            let ray = Ray::new(Point3::new(x, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+            let hit = stochastic.intersect(&ray).is_some();
+            assert_eq!(hit, stochastic.does_intersect(&ray));
+            hits += hit as usize;
+        }
+        let fraction = hits as Float / n as Float;
+        assert!((fraction - 0.75).abs() < 0.05, "{}", fraction);
+    }
+}
@@ -1,10 +1,13 @@
+use crate::acceleration::bvh::TRAVERSAL_COST;
 use crate::algebra::prelude::*;
-use crate::core::camera::Camera;
-use crate::core::material::{Material, BRDF};
+use crate::bxdf::BxDFType;
+use crate::core::camera::{Camera, CameraSample};
 use crate::core::scene::Scene;
 use crate::core::spectrum::RGBSpectrum;
+use crate::core::transport::TransportMode;
 use crate::denoise::Denoiser;
 use crate::sampler::{RandomSamplerConstructor, Sampler};
+use enumset::EnumSet;
 
 use scoped_threadpool::Pool;
 
@@ -40,16 +43,6 @@ pub struct BasicRenderer<'a> {
     pub camera: Arc<dyn Camera + 'a>,
 }
 
-// TODO: move this
-fn create_coordinate_system(n: &Vec3) -> (Vec3, Vec3) {
-    let nt = if n.x.abs() > n.y.abs() {
-        Vec3::new(n.z, 0.0, -n.x) / (n.x * n.x + n.z * n.z).sqrt()
-    } else {
-        Vec3::new(0.0, -n.z, n.y) / (n.y * n.y + n.z * n.z).sqrt()
-    };
-    (nt, comb::cross(&nt, n))
-}
-
 impl<'a> BasicRenderer<'a> {
     pub fn new(sampler_const: RandomSamplerConstructor, camera: Arc<dyn Camera + 'a>) -> Self {
         Self {
@@ -73,33 +66,30 @@ impl<'a> BasicRenderer<'a> {
                 let mut col = RGBSpectrum::BLACK;
                 col += isect.light_emission();
 
-                match isect.compute_scattering_functions(ray) {
-                    BRDF::Matte => {
-                        let (nt, nb) = create_coordinate_system(&Vec3::from(isect.geom.normal));
-                        let uv = samp.get_2d();
-                        let hemi = Vec3::hemisphere(uv.x, uv.y);
-                        let sample_world = Vec3::new(
-                            hemi.x * nb.x + hemi.y * isect.geom.normal.x + hemi.z * nt.x,
-                            hemi.x * nb.y + hemi.y * isect.geom.normal.y + hemi.z * nt.y,
-                            hemi.x * nb.z + hemi.y * isect.geom.normal.z + hemi.z * nt.z,
-                        );
-                        let ray = Ray::new(isect.geom.origin, sample_world);
-                        col += self
-                            .li(&ray, scene, depth - 1, samp)
-                            .mul_with(isect.primitive.mat().albedo(&Point2::new(0.0, 0.0)));
-                    }
-                    BRDF::Reflective => {
-                        let refl = ray.direction.reflect(&Vec3::from(isect.geom.normal));
-                        let specularity = 0.1;
-                        let random_p = Vec3::new(samp.get_1d(), samp.get_1d(), samp.get_1d())
-                            - Vec3::new(0.5, 0.5, 0.5);
-                        let dir = refl + (random_p * specularity);
-
-                        let ray = Ray::new(isect.geom.origin, dir.normalized());
-
-                        col += self
-                            .li(&ray, scene, depth - 1, samp)
-                            .mul_with(isect.primitive.mat().albedo(&Point2::new(0.0, 0.0)));
+                let bsdf = isect.compute_scattering_functions(ray, TransportMode::Radiance);
+                let wo = -ray.direction;
+                if let Some(sample) = bsdf.sample_f(&wo, &samp.get_2d(), EnumSet::all()) {
+                    if sample.pdf > 0.0 && !sample.f.is_black() {
+                        let cos = comb::dot(&sample.wi, &bsdf.shading_normal).abs();
+                        let weight = sample.f * float::to_f64(cos / sample.pdf);
+                        let entering = sample.types.contains(BxDFType::Transmission)
+                            && comb::dot(&sample.wi, &isect.geom.normal) < 0.0;
+                        match isect.primitive.mat().subsurface(&isect) {
+                            Some(walk) if entering => {
+                                // Light comes out again somewhere else on the object
+                                if let Some(exit) = walk.walk(scene, &isect, &sample.wi, samp) {
+                                    let ray = exit.interaction.spawn_ray(&exit.direction);
+                                    col += self
+                                        .li(&ray, scene, depth - 1, samp)
+                                        .mul_with(exit.weight)
+                                        .mul_with(weight);
+                                }
+                            }
+                            _ => {
+                                let ray = isect.spawn_ray(&sample.wi);
+                                col += self.li(&ray, scene, depth - 1, samp).mul_with(weight);
+                            }
+                        }
                     }
                 }
 
@@ -128,7 +118,7 @@ impl<'a> Renderer for BasicRenderer<'a> {
                         'sample_loop: loop {
                             let mut camera_sample = samp.get_camera_sample();
                             camera_sample.film_pos =
-                                Point2::new(f64::from(x), f64::from(y)) + camera_sample.film_pos;
+                                Point2::new(x as Float, y as Float) + camera_sample.film_pos;
                             let ray = self.camera.generate_ray(&camera_sample);
                             let l = self.li(&ray, scene, 5, &mut samp);
                             contribution.add_contribution(l, samp.spp());
@@ -149,3 +139,84 @@ impl<'a> Renderer for BasicRenderer<'a> {
         });
     }
 }
+
+/// What the heatmap shows for every pixel's primary ray
+#[derive(Clone, Copy, Debug)]
+pub enum HeatmapMetric {
+    NodesVisited,
+    PrimitivesTested,
+    /// Nodes and primitives weighted by their cost, as the SAH would
+    Cost,
+}
+
+pub struct HeatmapConfiguration {
+    pub metric: HeatmapMetric,
+    /// The count which is shown as full red; anything above it is white
+    pub max: f64,
+}
+
+/// Renders the work it takes to trace each pixel's primary ray through the acceleration
+/// structure, from blue (cheap) to red (expensive). Useful for finding out why a scene is slow.
+pub struct HeatmapRenderer<'a> {
+    pub camera: Arc<dyn Camera + 'a>,
+}
+
+impl<'a> HeatmapRenderer<'a> {
+    pub fn new(camera: Arc<dyn Camera + 'a>) -> Self {
+        Self { camera }
+    }
+
+    fn heat(t: f64) -> Rgba<u8> {
+        if t > 1.0 {
+            return Rgba([255, 255, 255, 255]);
+        }
+        // Blue -> cyan -> green -> yellow -> red
+        let (r, g, b) = if t < 0.25 {
+            (0.0, t * 4.0, 1.0)
+        } else if t < 0.5 {
+            (0.0, 1.0, 1.0 - (t - 0.25) * 4.0)
+        } else if t < 0.75 {
+            ((t - 0.5) * 4.0, 1.0, 0.0)
+        } else {
+            (1.0, 1.0 - (t - 0.75) * 4.0, 0.0)
+        };
+        Rgba([(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8, 255])
+    }
+}
+
+impl<'a> Renderer for HeatmapRenderer<'a> {
+    type ConfigurationType = HeatmapConfiguration;
+
+    fn render_scene(
+        &self,
+        scene: &Scene,
+        configuration: HeatmapConfiguration,
+        out: &mut RenderOutput,
+    ) {
+        let configuration = &configuration;
+        let mut pool = Pool::new(12);
+        pool.scoped(|scoped| {
+            for (_, row) in out.buf.enumerate_rows_mut() {
+                scoped.execute(move || {
+                    for (x, y, pix) in row {
+                        let camera_sample = CameraSample {
+                            film_pos: Point2::new(x as Float + 0.5, y as Float + 0.5),
+                            time: 0.0,
+                        };
+                        let ray = self.camera.generate_ray(&camera_sample);
+                        let (_, counters) = scene.intersect_counted(&ray);
+                        let count = match configuration.metric {
+                            HeatmapMetric::NodesVisited => counters.nodes_visited as f64,
+                            HeatmapMetric::PrimitivesTested => counters.primitives_tested as f64,
+                            HeatmapMetric::Cost => {
+                                counters.nodes_visited as f64 * TRAVERSAL_COST
+                                    + counters.primitives_tested as f64
+                            }
+                        };
+                        *pix = Self::heat(count / configuration.max);
+                    }
+                });
+            }
+        });
+    }
+}
@@ -1,6 +1,7 @@
+use crate::acceleration::bvh_stats::TraversalCounters;
 use crate::algebra::prelude::*;
 use crate::core::aggregate::Aggregate;
-use crate::core::interaction::Interaction;
+use crate::core::interaction::{HitFilter, Interaction};
 use crate::core::primitive::Primitive;
 use crate::light::Light;
 use std::sync::Arc;
@@ -27,7 +28,47 @@ impl<'a> Scene<'a> {
         self.aggregate.intersect(ray)
     }
 
+    /// The nearest hit accepted by `filter`, for example to skip the surface a ray leaves from
+    pub fn intersect_filtered(&self, ray: &Ray, filter: &HitFilter) -> Option<Interaction<'_>> {
+        self.aggregate.intersect_filtered(ray, filter)
+    }
+
+    /// Any hit accepted by `filter`, stopping at the first one
+    pub fn intersect_any(&self, ray: &Ray, filter: &HitFilter) -> Option<Interaction<'_>> {
+        self.aggregate.intersect_any(ray, filter)
+    }
+
+    /// Every hit along the ray in `[ray.min_t, ray.max_t]`, nearest first, or only the
+    /// `max_hits` nearest
+    pub fn intersect_all(&self, ray: &Ray, max_hits: Option<usize>) -> Vec<Interaction<'_>> {
+        self.aggregate.intersect_all(ray, max_hits)
+    }
+
+    /// Intersect, counting the work done; used by the heatmap renderer
+    pub fn intersect_counted(&self, ray: &Ray) -> (Option<Interaction<'_>>, TraversalCounters) {
+        self.aggregate.intersect_counted(ray)
+    }
+
     pub fn does_intersect(&self, ray: &Ray) -> bool {
         self.aggregate.does_intersect(ray)
     }
+
+    /// Intersect a packet of at most 64 coherent rays, like the camera rays of a small tile
+    pub fn intersect_packet(&self, rays: &[Ray]) -> Vec<Option<Interaction<'_>>> {
+        self.aggregate.intersect_packet(rays)
+    }
+
+    /// Test a packet of at most 64 coherent rays for occlusion, like shadow rays
+    pub fn does_intersect_packet(&self, rays: &[Ray]) -> Vec<bool> {
+        self.aggregate.does_intersect_packet(rays)
+    }
+
+    /// Intersect a batch of rays of any size, which are sorted into packets internally
+    pub fn intersect_stream(&self, rays: &[Ray]) -> Vec<Option<Interaction<'_>>> {
+        self.aggregate.intersect_stream(rays)
+    }
+
+    pub fn does_intersect_stream(&self, rays: &[Ray]) -> Vec<bool> {
+        self.aggregate.does_intersect_stream(rays)
+    }
 }
@@ -30,6 +30,15 @@ impl RGBSpectrum {
         self.iter().map(|s| s.sqrt()).collect()
     }
 
+    /// The Y of CIE XYZ, on the same scale as the channels
+    pub fn luminance(&self) -> f64 {
+        0.212_671 * self[0] + 0.715_160 * self[1] + 0.072_169 * self[2]
+    }
+
+    pub fn is_black(&self) -> bool {
+        self.iter().all(|s| *s == 0.0)
+    }
+
     pub fn has_nans(&self) -> bool {
         self.iter().any(|s| s.is_nan())
     }
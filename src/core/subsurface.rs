@@ -0,0 +1,294 @@
+// Subsurface scattering by a random walk through the inside of an object, after Chiang et al.'s
+// "Practical and Controllable Subsurface Scattering for Production Path Tracing".
+//
+// Light refracted into the object is traced through a homogeneous medium with isotropic
+// scattering until it reaches the boundary again, using the scene's intersection queries to
+// find it. This needs the object to be closed; a walk which escapes through a hole is lost.
+
+use crate::algebra::prelude::*;
+use crate::bxdf::{fresnel, reflect, refract, uniform_sample_sphere};
+use crate::core::interaction::Interaction;
+use crate::core::scene::Scene;
+use crate::core::spectrum::RGBSpectrum;
+use crate::sampler::Sampler;
+use std::sync::Arc;
+
+/// Walks are cut off after this many scattering events
+const MAX_BOUNCES: usize = 256;
+
+/// Russian roulette only starts after this many scattering events
+const MIN_BOUNCES: usize = 8;
+
+/// The single scattering albedo which gives roughly the multiple scattering albedo `a` of a
+/// semi-infinite slab
+fn single_scattering_albedo(a: f64) -> f64 {
+    let a = a.clamp(0.0, 1.0);
+    let s = 4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt();
+    1.0 - s * s
+}
+
+/// Where a walk leaves the object again
+#[derive(Debug, Clone)]
+pub struct SubsurfaceExit<'a> {
+    pub interaction: Interaction<'a>,
+    /// The direction light leaves in, outside the object
+    pub direction: Vec3,
+    /// The throughput of the walk, on the same 0-255 scale as other spectra
+    pub weight: RGBSpectrum,
+}
+
+/// The medium inside an object and its smooth boundary
+#[derive(Debug, Clone)]
+pub struct RandomWalk {
+    /// The multiple scattering albedo, which is roughly the color the object ends up
+    pub albedo: RGBSpectrum,
+    /// The average distance between scattering events for each channel, in scene units
+    pub mean_free_path: [Float; 3],
+    /// Index of refraction of the inside, where the outside is taken to be air
+    pub eta: Float,
+}
+
+impl RandomWalk {
+    pub fn new(albedo: RGBSpectrum, mean_free_path: [Float; 3], eta: Float) -> Self {
+        Self {
+            albedo,
+            mean_free_path,
+            eta,
+        }
+    }
+
+    /// Follow light that refracted into the object at `entry` in `direction` until it comes
+    /// out again. Returns nothing if it gets absorbed.
+    pub fn walk<'s>(
+        &self,
+        scene: &'s Scene,
+        entry: &Interaction,
+        direction: &Vec3,
+        sampler: &mut dyn Sampler,
+    ) -> Option<SubsurfaceExit<'s>> {
+        let mut sigma_t = [0.0; 3];
+        let mut albedo = [0.0; 3];
+        for c in 0..3 {
+            sigma_t[c] = 1.0 / float::to_f64(self.mean_free_path[c]).max(1e-8);
+            albedo[c] = single_scattering_albedo(self.albedo[c] / 255.0);
+        }
+
+        // Meshes are one primitive per triangle, so the object is whatever shares its material
+        let material = Arc::as_ptr(&entry.primitive.mat()) as *const ();
+        let mut throughput = [1.0; 3];
+        let mut ray = entry.spawn_ray(direction);
+        let mut bounces = 0;
+        while bounces < MAX_BOUNCES {
+            // Distances are sampled for one channel, and weighted by the average density of all
+            // of them so the other channels stay unbiased
+            let channel = ((sampler.get_1d() * 3.0) as usize).min(2);
+            let u = float::to_f64(sampler.get_1d());
+            let t = -(1.0 - u).ln() / sigma_t[channel];
+
+            // Other objects inside this one don't stop the walk, it only ends at its own boundary
+            let hit = scene.intersect_filtered(&ray, &|hit: &Interaction| {
+                Arc::as_ptr(&hit.primitive.mat()) as *const () == material
+            })?;
+            let distance = float::to_f64(hit.geom.t);
+            if distance <= t {
+                // Made it to the boundary, with the probability that nothing was in the way
+                let mut pdf = 0.0;
+                let mut tr = [0.0; 3];
+                for c in 0..3 {
+                    tr[c] = (-sigma_t[c] * distance).exp();
+                    pdf += tr[c] / 3.0;
+                }
+                for c in 0..3 {
+                    throughput[c] *= tr[c] / pdf;
+                }
+
+                // Either reflect back inside or refract out
+                let wo = -ray.direction;
+                let n = Vec3::from(hit.geom.normal);
+                let n = if comb::dot(&n, &wo) < 0.0 { -n } else { n };
+                let f = fresnel::dielectric(comb::dot(&wo, &n), self.eta, 1.0);
+                let out = if sampler.get_1d() < f {
+                    None
+                } else {
+                    refract(&wo, &n, self.eta)
+                };
+                match out {
+                    Some(direction) => {
+                        // The radiance spreads out again as it leaves the denser medium
+                        let scale = float::to_f64(self.eta * self.eta) * 255.0;
+                        return Some(SubsurfaceExit {
+                            weight: RGBSpectrum::from_rgb(
+                                throughput[0] * scale,
+                                throughput[1] * scale,
+                                throughput[2] * scale,
+                            ),
+                            direction,
+                            interaction: hit,
+                        });
+                    }
+                    None => {
+                        ray = hit.spawn_ray(&reflect(&wo, &n));
+                        continue;
+                    }
+                }
+            }
+
+            // Scatter inside, in a direction independent of the one the light came from
+            let mut pdf = 0.0;
+            let mut tr = [0.0; 3];
+            for c in 0..3 {
+                tr[c] = (-sigma_t[c] * t).exp();
+                pdf += sigma_t[c] * tr[c] / 3.0;
+            }
+            for c in 0..3 {
+                throughput[c] *= albedo[c] * sigma_t[c] * tr[c] / pdf;
+            }
+            bounces += 1;
+
+            if bounces > MIN_BOUNCES {
+                let q = throughput.iter().cloned().fold(0.0, f64::max);
+                if q < 1.0 {
+                    if float::to_f64(sampler.get_1d()) >= q {
+                        return None;
+                    }
+                    throughput.iter_mut().for_each(|v| *v /= q);
+                }
+            }
+
+            let origin = ray.origin + ray.direction * t as Float;
+            ray = Ray::new(origin, uniform_sample_sphere(&sampler.get_2d()));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acceleration::bvh::testing;
+    use crate::core::aggregate::Aggregate;
+    use crate::core::material::{Material, Matte};
+    use crate::core::medium::{HomogeneousMedium, MediumInterface};
+    use crate::core::primitive::{GeometricPrimitive, Primitive};
+    use crate::core::texture::ConstantTexture;
+    use crate::geometry::sphere::Sphere;
+    use crate::geometry::triangle::Triangle;
+    use crate::sampler::RandomSampler;
+
+    #[test]
+    fn walks_come_out_of_a_sphere() {
+        let sphere: Arc<dyn Primitive + Sync + Send> = Arc::new(GeometricPrimitive {
+            shape: Arc::new(Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0)),
+            material: Arc::new(Matte::lambertian(Arc::new(ConstantTexture::new(
+                RGBSpectrum::BLACK,
+            )))),
+            emission: RGBSpectrum::BLACK,
+            medium_interface: MediumInterface {
+                inside: Box::new(HomogeneousMedium::default()),
+                outside: Box::new(HomogeneousMedium::default()),
+            },
+        });
+        let scene = Scene::new(Arc::new(Aggregate::from_primitives(vec![sphere])), vec![]);
+        let ray = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let entry = scene.intersect(&ray).unwrap();
+
+        // Without a boundary between the media, a dense object is close to a half-space lit head
+        // on, which reflects a little less than the albedo the fit aims for
+        let walk = RandomWalk::new(
+            RGBSpectrum::from_rgb(0.8 * 255.0, 0.5 * 255.0, 0.2 * 255.0),
+            [0.01, 0.01, 0.01],
+            1.0,
+        );
+        let mut sampler = RandomSampler::new(1);
+        let n = 4000;
+        let mut total = RGBSpectrum::BLACK;
+        for _ in 0..n {
+            if let Some(exit) = walk.walk(&scene, &entry, &ray.direction, &mut sampler) {
+                let p = exit.interaction.geom.origin;
+                let normal = Vec3::new(p.x, p.y, p.z);
+                assert!((normal.length() - 1.0).abs() < 1e-3);
+                assert!(comb::dot(&normal, &exit.direction) > 0.0);
+                total += exit.weight / n as f64;
+            }
+        }
+        for (c, expected) in [0.76, 0.44, 0.16].iter().enumerate() {
+            assert!(
+                (total[c] / 255.0 - expected).abs() < 0.03,
+                "{:?}",
+                total / 255.0
+            );
+        }
+    }
+
+    #[test]
+    fn walks_only_leave_through_their_own_object() {
+        let outer = testing::primitive(Arc::new(Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0)));
+        let inner = testing::primitive(Arc::new(Sphere::new(Point3::new(0.0, 0.0, 0.0), 0.9)));
+        let aggregate = Aggregate::from_primitives(vec![Arc::clone(&outer), inner]);
+        let scene = Scene::new(Arc::new(aggregate), vec![]);
+        let ray = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let entry = scene.intersect(&ray).unwrap();
+        assert!(Arc::ptr_eq(&entry.primitive, &outer));
+
+        let walk = RandomWalk::new(RGBSpectrum::from_rgb(255.0, 255.0, 255.0), [0.5; 3], 1.0);
+        let mut sampler = RandomSampler::new(2);
+        let mut exits = 0;
+        for _ in 0..500 {
+            if let Some(exit) = walk.walk(&scene, &entry, &ray.direction, &mut sampler) {
+                assert!(Arc::ptr_eq(&exit.interaction.primitive, &outer));
+                let p = exit.interaction.geom.origin;
+                assert!((Vec3::new(p.x, p.y, p.z).length() - 1.0).abs() < 1e-3);
+                exits += 1;
+            }
+        }
+        assert!(exits > 100);
+    }
+
+    #[test]
+    fn walks_come_out_of_a_closed_mesh() {
+        let material: Arc<dyn Material> = Arc::new(Matte::lambertian(Arc::new(
+            ConstantTexture::new(RGBSpectrum::BLACK),
+        )));
+        let corners = [
+            Vec3::new(1.0, 1.0, 1.0),
+            Vec3::new(1.0, -1.0, -1.0),
+            Vec3::new(-1.0, 1.0, -1.0),
+            Vec3::new(-1.0, -1.0, 1.0),
+        ];
+        let vertex =
+            |i: usize| Vertex::new(corners[i], Vec3::new(0.0, 0.0, 0.0), Vec2::new(0.0, 0.0));
+        let faces = [(0, 1, 2), (0, 1, 3), (0, 2, 3), (1, 2, 3)];
+        let triangles: Vec<Arc<dyn Primitive + Sync + Send>> = faces
+            .iter()
+            .map(|&(a, b, c)| {
+                Arc::new(GeometricPrimitive {
+                    shape: Arc::new(Triangle::new(vertex(a), vertex(b), vertex(c))),
+                    material: Arc::clone(&material),
+                    emission: RGBSpectrum::BLACK,
+                    medium_interface: MediumInterface {
+                        inside: Box::new(HomogeneousMedium::default()),
+                        outside: Box::new(HomogeneousMedium::default()),
+                    },
+                }) as Arc<dyn Primitive + Sync + Send>
+            })
+            .collect();
+        let scene = Scene::new(Arc::new(Aggregate::from_primitives(triangles)), vec![]);
+        let ray = Ray::new(Point3::new(0.1, 0.2, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let entry = scene.intersect(&ray).unwrap();
+
+        // Each face is its own primitive, but the walk has to be able to leave through any of
+        // them
+        let walk = RandomWalk::new(RGBSpectrum::from_rgb(255.0, 255.0, 255.0), [0.2; 3], 1.0);
+        let mut sampler = RandomSampler::new(3);
+        let mut exits = 0;
+        for _ in 0..500 {
+            if walk
+                .walk(&scene, &entry, &ray.direction, &mut sampler)
+                .is_some()
+            {
+                exits += 1;
+            }
+        }
+        assert!(exits > 400, "{}", exits);
+    }
+}
@@ -2,3 +2,4 @@ pub mod geometry_information;
 pub mod plane;
 pub mod shape;
 pub mod sphere;
+pub mod triangle;
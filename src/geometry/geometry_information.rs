@@ -3,7 +3,7 @@ use crate::algebra::prelude::*;
 #[derive(Debug, Clone)]
 pub struct GeometryInformation {
     /// The 'distance' the ray hit at. This is derived from `p = rO + t * rD`
-    pub t: f64,
+    pub t: Float,
 
     /// The normal from the shape at the intersection
     pub normal: Normal,
@@ -13,4 +13,8 @@ pub struct GeometryInformation {
 
     /// The texture position in UV-space that the ray intersects
     pub uv: Point2,
+
+    /// How the position changes along the texture coordinates, which spans the tangent plane
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
 }
@@ -1,5 +1,9 @@
 use crate::algebra::prelude::*;
-use crate::geometry::{geometry_information::GeometryInformation, shape::Shape};
+use crate::geometry::{
+    geometry_information::GeometryInformation,
+    shape::{hash_floats, Shape},
+};
+use std::hash::Hasher;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Plane {
@@ -17,7 +21,7 @@ impl Shape for Plane {
     fn intersect(&self, ray: &Ray) -> Option<GeometryInformation> {
         let t = comb::dot(&(self.origin - ray.origin), &self.normal)
             / comb::dot(&self.normal, &ray.direction);
-        if t < 0.001 {
+        if !t.is_finite() || t <= ray.min_t || t > ray.max_t {
             None
         } else {
             let p = ray.origin + (ray.direction * t);
@@ -27,6 +31,8 @@ impl Shape for Plane {
                 t,
                 normal: self.normal,
                 uv,
+                dpdu: Vec3::new(1.0, 0.0, 0.0),
+                dpdv: Vec3::new(0.0, 0.0, 1.0),
             })
         }
     }
@@ -39,4 +45,9 @@ impl Shape for Plane {
             max: Point3::from(self.origin + far - Vec3::from(self.normal) * far),
         }
     }
+
+    fn hash_geometry(&self, state: &mut dyn Hasher) {
+        let (o, n) = (self.origin, self.normal);
+        hash_floats(state, &[o.x, o.y, o.z, n.x, n.y, n.z]);
+    }
 }
@@ -1,5 +1,6 @@
 use crate::algebra::prelude::*;
 use crate::geometry::geometry_information::GeometryInformation;
+use std::hash::Hasher;
 
 pub trait Shape: std::fmt::Debug + Send + Sync {
     fn intersect(&self, ray: &Ray) -> Option<GeometryInformation>;
@@ -8,4 +9,26 @@ pub trait Shape: std::fmt::Debug + Send + Sync {
     }
 
     fn bounds(&self) -> BoundingBox;
+
+    /// Get the bounds of the part of the shape that lies within `clip`. This is used for spatial
+    /// splits in BVH construction, where tighter bounds make for better trees. Clipping the
+    /// shape's bounds is always correct, so that's the default.
+    fn clipped_bounds(&self, clip: &BoundingBox) -> BoundingBox {
+        self.bounds().intersection(clip)
+    }
+
+    /// Feed the numbers which define the shape to `state`, so that edited geometry can be told
+    /// apart, for example by the BVH cache. Shapes which are not fully described by their bounds
+    /// have to override this.
+    fn hash_geometry(&self, state: &mut dyn Hasher) {
+        let BoundingBox { min, max } = self.bounds();
+        hash_floats(state, &[min.x, min.y, min.z, max.x, max.y, max.z]);
+    }
+}
+
+/// Write the exact bits of `values` to `state`
+pub fn hash_floats(state: &mut dyn Hasher, values: &[Float]) {
+    for &x in values {
+        state.write(&float::to_f64(x).to_bits().to_le_bytes());
+    }
 }
@@ -1,14 +1,18 @@
 use crate::algebra::prelude::*;
-use crate::geometry::{geometry_information::GeometryInformation, shape::Shape};
+use crate::geometry::{
+    geometry_information::GeometryInformation,
+    shape::{hash_floats, Shape},
+};
+use std::hash::Hasher;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Sphere {
     pub origin: Point3,
-    pub radius: f64,
+    pub radius: Float,
 }
 
 impl Sphere {
-    pub fn new(origin: Point3, radius: f64) -> Self {
+    pub fn new(origin: Point3, radius: Float) -> Self {
         Self { origin, radius }
     }
 }
@@ -21,43 +25,99 @@ impl Shape for Sphere {
         }
     }
 
+    fn hash_geometry(&self, state: &mut dyn Hasher) {
+        let Point3 { x, y, z } = self.origin;
+        hash_floats(state, &[x, y, z, self.radius]);
+    }
+
     fn intersect(&self, ray: &Ray) -> Option<GeometryInformation> {
         let local_ray = self.origin - ray.origin;
         let tca = comb::dot(&local_ray, &ray.direction);
-        if tca < 0.0 {
-            return None;
-        }
         let d2 = local_ray.length2() - tca * tca;
         if d2 > self.radius * self.radius {
             return None;
         }
         let thc = (self.radius * self.radius - d2).sqrt();
+        // From outside the near hit is the one in front of the ray, from inside only the far
+        // one is
         let t0 = tca - thc;
         let t1 = tca + thc;
-        if t0 < 0.0 && t1 < 0.0 {
-            return None;
-        }
-        let t = if t0 < 0.0 {
-            t1
-        } else if t1 < 0.0 {
-            t0
-        } else {
-            t0.min(t1)
-        };
-        if t <= 0.0 {
+        let t = if t0 > ray.min_t { t0 } else { t1 };
+        if t <= ray.min_t || t > ray.max_t {
             return None;
         }
         let p = ray.origin + (ray.direction * t);
         let normal = Normal::from((p - self.origin).normalized());
         let uv = Point2::new(
-            0.5 + normal.z.atan2(normal.x) / std::f64::consts::PI / 2.0,
-            0.5 - normal.y.asin() / std::f64::consts::PI,
+            0.5 + normal.z.atan2(normal.x) / float::consts::PI / 2.0,
+            0.5 - normal.y.asin() / float::consts::PI,
         );
+        // From the derivatives of the longitude and latitude the UVs are made of
+        let rho = (normal.x * normal.x + normal.z * normal.z).sqrt();
+        let (dpdu, dpdv) = if rho > 0.0 {
+            let dpdu =
+                Vec3::new(-normal.z, 0.0, normal.x) * (2.0 * float::consts::PI * self.radius);
+            let dpdv = Vec3::new(normal.y * normal.x / rho, -rho, normal.y * normal.z / rho)
+                * (float::consts::PI * self.radius);
+            (dpdu, dpdv)
+        } else {
+            // The poles, where the longitude is undefined
+            comb::coordinate_system(&Vec3::from(normal))
+        };
         Some(GeometryInformation {
             t,
             origin: p,
             normal,
             uv,
+            dpdu,
+            dpdv,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hits_from_inside() {
+        let sphere = Sphere::new(Point3::new(0.0, 0.0, 5.0), 2.0);
+        let outside = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!((sphere.intersect(&outside).unwrap().t - 3.0).abs() < 1e-5);
+        let inside = Ray::new(Point3::new(0.0, 0.0, 4.0), Vec3::new(0.0, 0.0, 1.0));
+        let geom = sphere.intersect(&inside).unwrap();
+        assert!((geom.t - 3.0).abs() < 1e-5);
+        // The normal keeps pointing out of the sphere
+        assert!(geom.normal.z > 0.0);
+        let behind = Ray::new(Point3::new(0.0, 0.0, 8.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!(sphere.intersect(&behind).is_none());
+    }
+
+    #[test]
+    fn hits_within_the_ray_range() {
+        let sphere = Sphere::new(Point3::new(0.0, 0.0, 5.0), 2.0);
+        let mut ray = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        // Past the near side, the far one is next
+        ray.min_t = 4.0;
+        assert!((sphere.intersect(&ray).unwrap().t - 7.0).abs() < 1e-5);
+        ray.min_t = 0.0;
+        ray.max_t = 2.0;
+        assert!(sphere.intersect(&ray).is_none());
+    }
+
+    #[test]
+    fn derivatives_follow_the_uvs() {
+        let sphere = Sphere::new(Point3::new(1.0, 2.0, 3.0), 2.0);
+        let ray = Ray::new(Point3::new(5.0, 4.0, 2.0), Vec3::new(-1.0, -0.5, 0.3));
+        let geom = sphere.intersect(&ray).unwrap();
+        let uv_at = |p: Point3| {
+            let ray = Ray::new(sphere.origin, p - sphere.origin);
+            sphere.intersect(&ray).unwrap().uv
+        };
+        let eps = 1e-3;
+        let u = uv_at(geom.origin + geom.dpdu * eps);
+        assert!((u.x - geom.uv.x - eps).abs() < 1e-4 && (u.y - geom.uv.y).abs() < 1e-4);
+        let v = uv_at(geom.origin + geom.dpdv * eps);
+        assert!((v.x - geom.uv.x).abs() < 1e-4 && (v.y - geom.uv.y - eps).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,192 @@
+use crate::algebra::prelude::*;
+use crate::geometry::{
+    geometry_information::GeometryInformation,
+    shape::{hash_floats, Shape},
+};
+use std::hash::Hasher;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Triangle {
+    pub a: Vertex,
+    pub b: Vertex,
+    pub c: Vertex,
+}
+
+impl Triangle {
+    pub fn new(a: Vertex, b: Vertex, c: Vertex) -> Self {
+        Self { a, b, c }
+    }
+}
+
+impl Shape for Triangle {
+    fn bounds(&self) -> BoundingBox {
+        BoundingBox {
+            min: Point3::from(self.a.origin.min(&self.b.origin).min(&self.c.origin)),
+            max: Point3::from(self.a.origin.max(&self.b.origin).max(&self.c.origin)),
+        }
+    }
+
+    /// Only the positions, the normals and uvs don't change where the triangle is hit
+    fn hash_geometry(&self, state: &mut dyn Hasher) {
+        for vertex in &[self.a, self.b, self.c] {
+            let Vec3 { x, y, z } = vertex.origin;
+            hash_floats(state, &[x, y, z]);
+        }
+    }
+
+    /// Clip the triangle against each of the planes of `clip` (Sutherland-Hodgman), and bound
+    /// whatever is left of it. Long diagonal triangles have much tighter bounds this way.
+    fn clipped_bounds(&self, clip: &BoundingBox) -> BoundingBox {
+        let mut polygon = vec![
+            Point3::from(self.a.origin),
+            Point3::from(self.b.origin),
+            Point3::from(self.c.origin),
+        ];
+        for axis in 0..3 {
+            for &(plane, keep_above) in &[(clip.min[axis], true), (clip.max[axis], false)] {
+                let inside = |p: &Point3| {
+                    if keep_above {
+                        p[axis] >= plane
+                    } else {
+                        p[axis] <= plane
+                    }
+                };
+                let mut clipped = Vec::with_capacity(polygon.len() + 1);
+                for (i, current) in polygon.iter().enumerate() {
+                    let next = &polygon[(i + 1) % polygon.len()];
+                    if inside(current) {
+                        clipped.push(*current);
+                    }
+                    if inside(current) != inside(next) {
+                        let t = (plane - current[axis]) / (next[axis] - current[axis]);
+                        let mut p = *current + (*next - *current) * t;
+                        // Snap to the plane to avoid rounding errors
+                        p[axis] = plane;
+                        clipped.push(p);
+                    }
+                }
+                polygon = clipped;
+                if polygon.is_empty() {
+                    return BoundingBox::EMPTY;
+                }
+            }
+        }
+        polygon
+            .iter()
+            .fold(BoundingBox::EMPTY, |acc: BoundingBox, p| {
+                acc.merge_with_point(p)
+            })
+    }
+
+    /// Möller–Trumbore intersection
+    fn intersect(&self, ray: &Ray) -> Option<GeometryInformation> {
+        let ab = self.b.origin - self.a.origin;
+        let ac = self.c.origin - self.a.origin;
+
+        let pvec = comb::cross(&ray.direction, &ac);
+        let det = comb::dot(&ab, &pvec);
+        if det.abs() < float::EPSILON {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let tvec = Vec3::from(ray.origin) - self.a.origin;
+        let u = comb::dot(&tvec, &pvec) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let qvec = comb::cross(&tvec, &ab);
+        let v = comb::dot(&ray.direction, &qvec) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = comb::dot(&ac, &qvec) * inv_det;
+        if t <= ray.min_t || t > ray.max_t {
+            return None;
+        }
+        let w = 1.0 - u - v;
+        let normal = self.a.normal.normalized() * w
+            + self.b.normal.normalized() * u
+            + self.c.normal.normalized() * v;
+        // Meshes without vertex normals get the normal of the face
+        let normal = if normal.has_nans() || normal.length2() == 0.0 {
+            comb::cross(&ab, &ac).normalized()
+        } else {
+            normal.normalized()
+        };
+
+        // Solve for the derivatives from the edges and the differences in UVs along them
+        let duv02 = Vec2::new(self.a.uv.x - self.c.uv.x, self.a.uv.y - self.c.uv.y);
+        let duv12 = Vec2::new(self.b.uv.x - self.c.uv.x, self.b.uv.y - self.c.uv.y);
+        let dp02 = self.a.origin - self.c.origin;
+        let dp12 = self.b.origin - self.c.origin;
+        let det = duv02.x * duv12.y - duv02.y * duv12.x;
+        let (dpdu, dpdv) = if det.abs() < 1e-8 {
+            // Degenerate UVs, any tangents will do
+            comb::coordinate_system(&normal)
+        } else {
+            let inv_det = 1.0 / det;
+            (
+                (dp02 * duv12.y - dp12 * duv02.y) * inv_det,
+                (dp12 * duv02.x - dp02 * duv12.x) * inv_det,
+            )
+        };
+        Some(GeometryInformation {
+            t,
+            origin: ray.origin + ray.direction * t,
+            normal: Normal::from(normal),
+            uv: Point2::new(
+                self.a.uv.x * w + self.b.uv.x * u + self.c.uv.x * v,
+                self.a.uv.y * w + self.b.uv.y * u + self.c.uv.y * v,
+            ),
+            dpdu,
+            dpdv,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clipped_bounds() {
+        let vertex = |x, y, z| Vertex::new(Vec3::new(x, y, z), Vec3::ORIGIN, Vec2::new(0.0, 0.0));
+        // A long, thin diagonal triangle
+        let tri = Triangle::new(
+            vertex(0.0, 0.0, 0.0),
+            vertex(10.0, 10.0, 0.0),
+            vertex(10.0, 10.0, 1.0),
+        );
+        let clip = BoundingBox {
+            min: Point3::new(0.0, 0.0, 0.0),
+            max: Point3::new(5.0, 10.0, 1.0),
+        };
+        let bounds = tri.clipped_bounds(&clip);
+        assert!((bounds.max.x - 5.0).abs() < 1e-9);
+        assert!((bounds.max.y - 5.0).abs() < 1e-9);
+        assert!((bounds.max.z - 0.5).abs() < 1e-9);
+
+        let outside = BoundingBox {
+            min: Point3::new(20.0, 20.0, 20.0),
+            max: Point3::new(30.0, 30.0, 30.0),
+        };
+        assert!(tri.clipped_bounds(&outside).is_empty());
+    }
+
+    #[test]
+    fn hits_within_the_ray_range() {
+        // Without vertex normals
+        let vertex = |x, y| Vertex::new(Vec3::new(x, y, 0.0), Vec3::ORIGIN, Vec2::new(0.0, 0.0));
+        let tri = Triangle::new(vertex(-1.0, -1.0), vertex(1.0, -1.0), vertex(0.0, 1.0));
+        let mut ray = Ray::new(Point3::new(0.0, 0.0, 1e-4), Vec3::new(0.0, 0.0, -1.0));
+        let geom = tri.intersect(&ray).unwrap();
+        assert!((geom.t - 1e-4).abs() < 1e-6);
+        assert!((geom.normal.z.abs() - 1.0).abs() < 1e-6);
+
+        ray.min_t = 1e-3;
+        assert!(tri.intersect(&ray).is_none());
+        ray.min_t = 0.0;
+        ray.max_t = 5e-5;
+        assert!(tri.intersect(&ray).is_none());
+    }
+}
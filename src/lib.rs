@@ -17,6 +17,7 @@ extern crate oidn;
 
 #[macro_use]
 extern crate serde_derive;
+extern crate bincode;
 extern crate ron;
 extern crate serde;
 
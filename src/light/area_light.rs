@@ -8,5 +8,5 @@ pub struct AreaLight;
 pub struct InfiniteAreaLight {
     pub lmap: Box<dyn Texture<RGBSpectrum>>,
     pub world_centre: Point3,
-    pub world_radius: f64,
+    pub world_radius: Float,
 }
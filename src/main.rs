@@ -53,6 +53,7 @@ pub fn main() -> std::result::Result<(), String> {
                 kd: Arc::new(ConstantTexture::new(RGBSpectrum::from_rgb(
                     255.0, 255.0, 255.0,
                 ))),
+                roughness: Arc::new(ConstantTexture::new(0.2)),
             }),
             shape: Arc::new(Plane::new(
                 Point3::new(0.0, 0.0, 15.0),
@@ -65,11 +66,9 @@ pub fn main() -> std::result::Result<(), String> {
         }),
         Arc::new(GeometricPrimitive {
             emission: RGBSpectrum::from_rgb(0.0, 0.0, 0.0),
-            material: Arc::new(Matte {
-                kd: Arc::new(ConstantTexture::new(RGBSpectrum::from_rgb(
-                    255.0, 255.0, 255.0,
-                ))),
-            }),
+            material: Arc::new(Matte::lambertian(Arc::new(ConstantTexture::new(
+                RGBSpectrum::from_rgb(255.0, 255.0, 255.0),
+            )))),
             shape: Arc::new(Plane::new(
                 Point3::new(0.0, 20.0, 15.0),
                 Normal::new(0.0, -1.0, 0.0),
@@ -81,11 +80,9 @@ pub fn main() -> std::result::Result<(), String> {
         }),
         Arc::new(GeometricPrimitive {
             emission: RGBSpectrum::from_rgb(0.0, 0.0, 0.0),
-            material: Arc::new(Matte {
-                kd: Arc::new(ConstantTexture::new(RGBSpectrum::from_rgb(
-                    255.0, 10.0, 10.0,
-                ))),
-            }),
+            material: Arc::new(Matte::lambertian(Arc::new(ConstantTexture::new(
+                RGBSpectrum::from_rgb(255.0, 10.0, 10.0),
+            )))),
             shape: Arc::new(Plane::new(
                 Point3::new(-10.0, 0.0, 0.0),
                 Normal::new(1.0, 0.0, 0.0),
@@ -97,11 +94,9 @@ pub fn main() -> std::result::Result<(), String> {
         }),
         Arc::new(GeometricPrimitive {
             emission: RGBSpectrum::from_rgb(0.0, 0.0, 0.0),
-            material: Arc::new(Matte {
-                kd: Arc::new(ConstantTexture::new(RGBSpectrum::from_rgb(
-                    255.0, 255.0, 255.0,
-                ))),
-            }),
+            material: Arc::new(Matte::lambertian(Arc::new(ConstantTexture::new(
+                RGBSpectrum::from_rgb(255.0, 255.0, 255.0),
+            )))),
             shape: Arc::new(Plane::new(
                 Point3::new(0.0, 0.0, -50.0),
                 Normal::new(0.0, 0.0, 1.0),
@@ -113,11 +108,9 @@ pub fn main() -> std::result::Result<(), String> {
         }),
         Arc::new(GeometricPrimitive {
             emission: RGBSpectrum::from_rgb(0.0, 0.0, 0.0),
-            material: Arc::new(Matte {
-                kd: Arc::new(ConstantTexture::new(RGBSpectrum::from_rgb(
-                    255.0, 255.0, 255.0,
-                ))),
-            }),
+            material: Arc::new(Matte::lambertian(Arc::new(ConstantTexture::new(
+                RGBSpectrum::from_rgb(255.0, 255.0, 255.0),
+            )))),
             shape: Arc::new(Plane::new(
                 Point3::new(0.0, 0.0, 20.0),
                 Normal::new(0.0, 0.0, -1.0),
@@ -129,11 +122,9 @@ pub fn main() -> std::result::Result<(), String> {
         }),
         Arc::new(GeometricPrimitive {
             emission: RGBSpectrum::from_rgb(0.0, 0.0, 0.0),
-            material: Arc::new(Matte {
-                kd: Arc::new(ConstantTexture::new(RGBSpectrum::from_rgb(
-                    10.0, 255.0, 10.0,
-                ))),
-            }),
+            material: Arc::new(Matte::lambertian(Arc::new(ConstantTexture::new(
+                RGBSpectrum::from_rgb(10.0, 255.0, 10.0),
+            )))),
             shape: Arc::new(Plane::new(
                 Point3::new(10.0, 0.0, 0.0),
                 Normal::new(-1.0, 0.0, 0.0),
@@ -145,11 +136,9 @@ pub fn main() -> std::result::Result<(), String> {
         }),
         Arc::new(GeometricPrimitive {
             emission: RGBSpectrum::from_rgb(255.0, 255.0, 255.0) * 3.0,
-            material: Arc::new(Matte {
-                kd: Arc::new(ConstantTexture::new(RGBSpectrum::from_rgb(
-                    255.0, 255.0, 255.0,
-                ))),
-            }),
+            material: Arc::new(Matte::lambertian(Arc::new(ConstantTexture::new(
+                RGBSpectrum::from_rgb(255.0, 255.0, 255.0),
+            )))),
             shape: Arc::new(Sphere::new(Point3::new(0.0, 20.0, 15.0), 5.0)),
             medium_interface: MediumInterface {
                 inside: Box::new(ex_medium.clone()),
@@ -158,11 +147,9 @@ pub fn main() -> std::result::Result<(), String> {
         }),
         Arc::new(GeometricPrimitive {
             emission: RGBSpectrum::from_rgb(0.0, 0.0, 0.0) * 3.0,
-            material: Arc::new(Matte {
-                kd: Arc::new(ConstantTexture::new(RGBSpectrum::from_rgb(
-                    255.0, 255.0, 255.0,
-                ))),
-            }),
+            material: Arc::new(Matte::lambertian(Arc::new(ConstantTexture::new(
+                RGBSpectrum::from_rgb(255.0, 255.0, 255.0),
+            )))),
             shape: Arc::new(Sphere::new(Point3::new(0.0, 5.0, 15.0), 5.0)),
             medium_interface: MediumInterface {
                 inside: Box::new(ex_medium.clone()),
@@ -23,8 +23,8 @@ impl Default for Object {
 }
 
 // TODO: remove
-pub fn str_to_float(s: &str) -> Result<f64, ParseError> {
-    s.parse::<f64>().map_err(|_| ParseError::StrParseError)
+pub fn str_to_float(s: &str) -> Result<Float, ParseError> {
+    s.parse::<Float>().map_err(|_| ParseError::StrParseError)
 }
 
 /// Parses a .obj file into an `Object`.
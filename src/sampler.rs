@@ -11,7 +11,7 @@ pub trait Sampler {
 
     fn spp(&self) -> usize;
 
-    fn get_1d(&mut self) -> f64;
+    fn get_1d(&mut self) -> Float;
 
     fn get_2d(&mut self) -> Point2 {
         Point2::new(self.get_1d(), self.get_1d())
@@ -69,8 +69,8 @@ impl Sampler for RandomSampler {
         self.sample_count
     }
 
-    fn get_1d(&mut self) -> f64 {
-        self.rng.gen::<f64>()
+    fn get_1d(&mut self) -> Float {
+        self.rng.gen::<Float>()
     }
 
     fn start_next_sample(&mut self) -> bool {
@@ -78,3 +78,102 @@ impl Sampler for RandomSampler {
         self.sample_count < self.spp
     }
 }
+
+/// A piecewise constant distribution over [0, 1), for importance sampling tabulated functions
+#[derive(Debug, Clone)]
+pub struct Distribution1D {
+    pub func: Vec<Float>,
+    cdf: Vec<Float>,
+    pub integral: Float,
+}
+
+impl Distribution1D {
+    /// `func` has to be non-negative. When it's zero everywhere the distribution is uniform.
+    pub fn new(func: Vec<Float>) -> Self {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 1..=n {
+            cdf[i] = cdf[i - 1] + func[i - 1] / n as Float;
+        }
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral == 0.0 {
+                i as Float / n as Float
+            } else {
+                *c / integral
+            };
+        }
+        Self {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    /// Map `u` to a point in [0, 1), returning the point, the density there and the segment it
+    /// lies in
+    pub fn sample_continuous(&self, u: Float) -> (Float, Float, usize) {
+        // The last segment whose start is at most `u`
+        let offset = self
+            .cdf
+            .partition_point(|c| *c <= u)
+            .saturating_sub(1)
+            .min(self.count() - 1);
+        let mut du = u - self.cdf[offset];
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        if width > 0.0 {
+            du /= width;
+        }
+        let x = ((offset as Float + du) / self.count() as Float).min(1.0 - float::EPSILON);
+        (x, self.pdf(x), offset)
+    }
+
+    pub fn pdf(&self, x: Float) -> Float {
+        if self.integral == 0.0 {
+            return 1.0;
+        }
+        let i = ((x * self.count() as Float) as usize).min(self.count() - 1);
+        self.func[i] / self.integral
+    }
+}
+
+/// A piecewise constant distribution over [0, 1)^2, sampled by picking a row from the marginal
+/// distribution and then a column in that row
+#[derive(Debug, Clone)]
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// `func` holds `nv` rows of `nu` values
+    pub fn new(func: &[Float], nu: usize, nv: usize) -> Self {
+        let conditional: Vec<Distribution1D> = func
+            .chunks(nu)
+            .take(nv)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|d| d.integral).collect());
+        Self {
+            conditional,
+            marginal,
+        }
+    }
+
+    /// Map `u` to a point, returning it and the density there
+    pub fn sample_continuous(&self, u: &Point2) -> (Point2, Float) {
+        let (y, pdf_y, row) = self.marginal.sample_continuous(u.y);
+        let (x, pdf_x, _) = self.conditional[row].sample_continuous(u.x);
+        (Point2::new(x, y), pdf_x * pdf_y)
+    }
+
+    pub fn pdf(&self, p: &Point2) -> Float {
+        let nv = self.marginal.count();
+        let row = ((p.y * nv as Float) as usize).min(nv - 1);
+        self.conditional[row].pdf(p.x) * self.marginal.pdf(p.y)
+    }
+}